@@ -12,12 +12,17 @@ use std::{
     path::{Path, PathBuf},
 };
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
-use crate::args::V3Model;
+use crate::args::{OutputFormat, V3Model};
 use eros::prelude::suggest_media_directories;
 
 use super::ui;
 use crate::core::{run_full_process, AppConfig};
+use crate::deduplicate::{DedupSummary, DuplicateAction};
+use crate::progress::{ChannelProgress, PROGRESS_CHANNEL_CAPACITY};
+use crate::video::FrameExtractOptions;
+use eros::dedup::HashAlgorithm;
 
 /// Represents updates sent from the processing thread to the UI thread.
 #[derive(Debug)]
@@ -27,6 +32,7 @@ pub enum ProgressUpdate {
     Error(String),
     Frame(DynamicImage),
     ImageProcessed(PathBuf),
+    DuplicatesRemoved(DedupSummary),
     Complete,
 }
 
@@ -49,6 +55,8 @@ pub enum MenuItem {
     VideoPath,
     Threshold,
     BatchSize,
+    DedupMaxDistance,
+    DedupAlgorithm,
     ShowAsciiArt,
     Start,
 }
@@ -81,11 +89,27 @@ impl Default for App {
         Self {
             config: AppConfig {
                 model: V3Model::SwinV2,
+                output: OutputFormat::Db,
                 input_path: "./images".to_string(),
                 video_path: "./videos".to_string(),
                 threshold: 0.5,
                 batch_size: 1,
                 show_ascii_art: false,
+                dedup_max_distance: 5,
+                dedup_algorithm: HashAlgorithm::Average,
+                duplicate_action: DuplicateAction::Delete,
+                path_root: None,
+                min_image_dimension: 0,
+                delete_originals: false,
+                report_path: None,
+                shutdown: CancellationToken::new(),
+                write_xmp: false,
+                modified_since: None,
+                force: false,
+                frame_options: FrameExtractOptions::default(),
+                db_path: None,
+                max_scan_depth: None,
+                dry_run: false,
             },
             current_screen: CurrentScreen::SuggestingDirs,
             currently_editing: None,
@@ -95,6 +119,8 @@ impl Default for App {
                 MenuItem::VideoPath,
                 MenuItem::Threshold,
                 MenuItem::BatchSize,
+                MenuItem::DedupMaxDistance,
+                MenuItem::DedupAlgorithm,
                 MenuItem::ShowAsciiArt,
                 MenuItem::Start,
             ],
@@ -117,6 +143,12 @@ impl Default for App {
 }
 
 impl App {
+    /// Stores the tagging results in the database at `db_path`.
+    pub fn with_db_path(mut self, db_path: PathBuf) -> Self {
+        self.config.db_path = Some(db_path);
+        self
+    }
+
     /// Runs the main application loop.
     pub async fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
         while self.current_screen != CurrentScreen::Exiting {
@@ -159,6 +191,10 @@ impl App {
                             self.update_current_frame_from_path();
                         }
                     }
+                    ProgressUpdate::DuplicatesRemoved(summary) => {
+                        self.status_message = summary.to_string();
+                        self.logs.push(self.status_message.clone());
+                    }
                     ProgressUpdate::Complete => {
                         self.status_message = "Processing complete!".to_string();
                         self.logs.push(self.status_message.clone());
@@ -199,6 +235,7 @@ impl App {
                         CurrentScreen::Editing => self.handle_editing_screen_events(key.code),
                         CurrentScreen::Processing if key.code == KeyCode::Char('q') => {
                             self.current_screen = CurrentScreen::Main;
+                            self.config.shutdown.cancel();
                             self.rx = None; // This will drop the sender, stopping the process
                         }
                         CurrentScreen::Finished if key.code == KeyCode::Enter => {
@@ -270,6 +307,12 @@ impl App {
         match current_item {
             MenuItem::Start => self.start_processing(),
             MenuItem::Model => self.config.model = self.config.model.next(),
+            MenuItem::DedupAlgorithm => {
+                self.config.dedup_algorithm = match self.config.dedup_algorithm {
+                    HashAlgorithm::Average => HashAlgorithm::Dct,
+                    HashAlgorithm::Dct => HashAlgorithm::Average,
+                };
+            }
             MenuItem::ShowAsciiArt => {
                 self.show_ascii_art = !self.show_ascii_art;
                 self.config.show_ascii_art = self.show_ascii_art;
@@ -284,15 +327,17 @@ impl App {
         self.progress = 0.0;
         self.status_message = "Starting...".to_string();
 
-        let (tx, rx) = mpsc::channel(100);
+        let (sink, rx) = ChannelProgress::channel(PROGRESS_CHANNEL_CAPACITY);
         self.rx = Some(rx);
 
+        // A cancelled token stays cancelled, so every run gets a fresh one.
+        self.config.shutdown = CancellationToken::new();
         let config = self.config.clone();
         let selected_dirs = self.selected_dirs.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = run_full_process(config, selected_dirs, tx.clone()).await {
-                let _ = tx.send(ProgressUpdate::Error(e.to_string())).await;
+            if let Err(e) = run_full_process(config, selected_dirs, sink.clone()).await {
+                sink.error(&e.to_string());
             }
         });
     }
@@ -305,6 +350,7 @@ impl App {
             MenuItem::VideoPath => self.config.video_path.clone(),
             MenuItem::Threshold => self.config.threshold.to_string(),
             MenuItem::BatchSize => self.config.batch_size.to_string(),
+            MenuItem::DedupMaxDistance => self.config.dedup_max_distance.to_string(),
             _ => String::new(),
         };
         self.current_screen = CurrentScreen::Editing;
@@ -338,6 +384,13 @@ impl App {
                 MenuItem::BatchSize => {
                     self.config.batch_size = self.input_text.parse().unwrap_or(self.config.batch_size);
                 }
+                MenuItem::DedupMaxDistance => {
+                    // A fingerprint has 64 bits, so larger distances would match everything.
+                    self.config.dedup_max_distance = match self.input_text.parse() {
+                        Ok(distance) if distance <= 64 => distance,
+                        _ => self.config.dedup_max_distance,
+                    };
+                }
                 _ => {}
             }
         }
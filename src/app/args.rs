@@ -1,10 +1,16 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use eros::dedup::HashAlgorithm;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
     #[command(subcommand)]
     pub command: Option<Commands>,
+
+    /// Where the tagging database is stored
+    #[arg(long, global = true, default_value = crate::core::DATABASE_PATH)]
+    pub db: PathBuf,
 }
 
 #[derive(Subcommand, Debug)]
@@ -18,13 +24,113 @@ pub enum Commands {
         /// The confidence threshold for tagging
         #[arg(short, long, default_value_t = 0.35)]
         threshold: f32,
+
+        /// The tagger model to run
+        #[arg(short, long, value_enum, default_value = "swinv2")]
+        model: V3Model,
+
+        /// Where to write the tags besides the database
+        #[arg(short, long, value_enum, default_value = "db")]
+        output: OutputFormat,
+
+        /// How many images are run through the model at once
+        #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        batch_size: u32,
+
+        /// Skip images whose width or height is below this many pixels (0 disables)
+        #[arg(long, default_value_t = 0)]
+        min_dimension: u32,
+
+        /// Delete the original files after converting them to another format (by default
+        /// they are moved into an `originals` directory, which later runs skip)
+        #[arg(long)]
+        delete_originals: bool,
+
+        /// Write a JSON report of each file's outcome to this path
+        #[arg(long)]
+        report: Option<PathBuf>,
+
+        /// Also write the tags into each image's XMP metadata
+        #[arg(long)]
+        write_xmp: bool,
+
+        /// Only tag files modified after this time, in seconds since the Unix epoch
+        #[arg(long)]
+        since: Option<u64>,
+
+        /// Re-tag images that are already in the database
+        #[arg(long)]
+        force: bool,
+
+        /// Only tag media this many directory levels deep, where 1 is only the files
+        /// directly in the path (subdirectories are scanned at any depth by default)
+        #[arg(long)]
+        max_depth: Option<usize>,
+
+        /// Only print which files would be deleted, renamed, converted, and optimized,
+        /// without changing or tagging anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Move duplicate images into this directory, keeping their paths relative to
+        /// the processed directory, instead of deleting them. Files in this directory
+        /// are never renamed, converted, optimized, or tagged
+        #[arg(long)]
+        move_duplicates_to: Option<PathBuf>,
+
+        /// The most fingerprint bits, out of 64, in which two images may differ and
+        /// still count as duplicates (0 only matches identical fingerprints)
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(0..=64))]
+        dedup_max_distance: u32,
+
+        /// The fingerprint used to find duplicate images
+        #[arg(long, value_enum, default_value = "average")]
+        dedup_algorithm: DedupAlgorithm,
+    },
+    /// Tag a single image or video and print its tags, without using the database
+    Tag {
+        /// The image or video to tag
+        file: PathBuf,
+
+        /// The confidence threshold for tagging
+        #[arg(short, long, default_value_t = 0.35)]
+        threshold: f32,
+
+        /// The tagger model to run
+        #[arg(short, long, value_enum, default_value = "swinv2")]
+        model: V3Model,
+
+        /// How the tags are printed
+        #[arg(short, long, value_enum, default_value = "plain")]
+        format: PrintFormat,
+    },
+    /// Export tagged images as a Hugging Face `metadata.jsonl` file
+    Export {
+        /// Where to write the JSONL file; file names are made relative to its directory
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// The separator placed between tags in each caption
+        #[arg(long, default_value = ", ")]
+        separator: String,
+
+        /// Put the rating in front of each caption
+        #[arg(long)]
+        include_rating: bool,
+    },
+    /// Show the most common tags in the database
+    Stats {
+        /// How many tags to show
+        #[arg(long, default_value_t = 50)]
+        top: usize,
     },
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum V3Model {
     VitLarge,
     Eva02Large,
+    #[value(name = "swinv2")]
     SwinV2,
 }
 
@@ -60,4 +166,164 @@ impl ToString for V3Model {
             V3Model::SwinV2 => "SwinV2".to_string(),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Where the `Process` command writes tags.
+///
+/// Results always go to the database, which is also what lets later runs skip images
+/// that are already tagged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Only the database.
+    #[default]
+    Db,
+    /// A JSON sidecar next to each image, e.g. `cat.png.json`.
+    Json,
+    /// A single CSV file with one row per tag, next to the database.
+    Csv,
+}
+
+/// Which fingerprint the `Process` command compares to find duplicate images.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum DedupAlgorithm {
+    /// Compares a thumbnail's pixels to their mean brightness; fast, but fooled by
+    /// brightness changes.
+    #[default]
+    Average,
+    /// Compares the image's lowest DCT frequencies, which follow its structure rather
+    /// than its brightness.
+    Dct,
+}
+
+impl From<DedupAlgorithm> for HashAlgorithm {
+    fn from(algorithm: DedupAlgorithm) -> Self {
+        match algorithm {
+            DedupAlgorithm::Average => HashAlgorithm::Average,
+            DedupAlgorithm::Dct => HashAlgorithm::Dct,
+        }
+    }
+}
+
+/// How the `Tag` command prints its result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PrintFormat {
+    /// The character and general tags, comma-joined on one line.
+    #[default]
+    Plain,
+    /// Every category with its tags and scores, as JSON.
+    Json,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_process(args: &[&str]) -> Result<Commands, clap::Error> {
+        let args = ["eros", "process", "--path", "media"].iter().chain(args);
+        Args::try_parse_from(args).map(|args| args.command.unwrap())
+    }
+
+    #[test]
+    fn test_process_model_and_output() {
+        let Commands::Process {
+            model,
+            output,
+            batch_size,
+            ..
+        } = parse_process(&[]).unwrap()
+        else {
+            panic!("expected the process command");
+        };
+        assert_eq!(model, V3Model::SwinV2);
+        assert_eq!(output, OutputFormat::Db);
+        assert_eq!(batch_size, 1);
+
+        let args = ["-m", "eva02-large", "-o", "csv", "-b", "8"];
+        let Commands::Process {
+            model,
+            output,
+            batch_size,
+            ..
+        } = parse_process(&args).unwrap()
+        else {
+            panic!("expected the process command");
+        };
+        assert_eq!(model, V3Model::Eva02Large);
+        assert_eq!(output, OutputFormat::Csv);
+        assert_eq!(batch_size, 8);
+    }
+
+    #[test]
+    fn test_process_keeps_originals_by_default() {
+        for (args, expected) in [(&[][..], false), (&["--delete-originals"][..], true)] {
+            let Commands::Process {
+                delete_originals, ..
+            } = parse_process(args).unwrap()
+            else {
+                panic!("expected the process command");
+            };
+            assert_eq!(delete_originals, expected);
+        }
+    }
+
+    #[test]
+    fn test_process_dedup_options() {
+        let Commands::Process {
+            dedup_max_distance,
+            dedup_algorithm,
+            ..
+        } = parse_process(&[]).unwrap()
+        else {
+            panic!("expected the process command");
+        };
+        assert_eq!(dedup_max_distance, 5);
+        assert_eq!(HashAlgorithm::from(dedup_algorithm), HashAlgorithm::Average);
+
+        let args = ["--dedup-max-distance", "10", "--dedup-algorithm", "dct"];
+        let Commands::Process {
+            dedup_max_distance,
+            dedup_algorithm,
+            ..
+        } = parse_process(&args).unwrap()
+        else {
+            panic!("expected the process command");
+        };
+        assert_eq!(dedup_max_distance, 10);
+        assert_eq!(HashAlgorithm::from(dedup_algorithm), HashAlgorithm::Dct);
+
+        let err = parse_process(&["--dedup-max-distance", "65"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_process_rejects_unknown_model() {
+        let err = parse_process(&["--model", "resnet"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::InvalidValue);
+        let message = err.to_string();
+        assert!(message.contains("resnet"));
+        assert!(message.contains("vit-large, eva02-large, swinv2"));
+
+        let err = parse_process(&["--batch-size", "0"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_tag_command() {
+        let args = Args::try_parse_from(["eros", "tag", "cat.png", "-f", "json", "-t", "0.5"]);
+        let Some(Commands::Tag {
+            file,
+            threshold,
+            model,
+            format,
+        }) = args.unwrap().command
+        else {
+            panic!("expected the tag command");
+        };
+        assert_eq!(file, PathBuf::from("cat.png"));
+        assert_eq!(threshold, 0.5);
+        assert_eq!(model, V3Model::SwinV2);
+        assert_eq!(format, PrintFormat::Json);
+
+        assert!(Args::try_parse_from(["eros", "tag"]).is_err());
+    }
+}
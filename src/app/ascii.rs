@@ -1,5 +1,9 @@
-use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba};
-use ratatui::layout::Rect;
+use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba, RgbaImage};
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style},
+    text::{Line, Span},
+};
 use rayon::prelude::*;
 
 // Required for SIMD intrinsics
@@ -9,23 +13,12 @@ use std::arch::x86_64::*;
 const ASCII_CHARS: [char; 11] = [' ', '.', ':', '-', '=', '+', '*', '#', '%', '@', '$'];
 
 /// Converts an image to ASCII art using Rayon for parallel row processing
-/// and AVX2 SIMD for parallel pixel processing within rows.
+/// and, where the CPU supports it, AVX2 SIMD for parallel pixel processing within rows.
 pub fn create_ascii_art(image: &DynamicImage, area: Rect) -> String {
-    if area.width == 0 || area.height < 2 {
-        return String::new();
-    }
-
-    // Adjust height to compensate for character aspect ratio
-    let ascii_height = (area.height as f32 / 2.0).round() as u32;
-    if ascii_height == 0 {
+    let Some(resized_image) = resize_for_area(image, area) else {
         return String::new();
-    }
-
-    let width = area.width as u32;
-    let height = ascii_height;
-
-    // Use a specific image format (RGBA) for predictable memory layout, which is safer for SIMD
-    let resized_image = image.resize_exact(width, height, FilterType::Nearest).to_rgba8();
+    };
+    let width = resized_image.width();
 
     // Process each row in parallel using Rayon
     let rows: Vec<String> = (0..resized_image.height())
@@ -35,18 +28,10 @@ pub fn create_ascii_art(image: &DynamicImage, area: Rect) -> String {
             let row_pixels = resized_image.as_flat_samples();
             let row_slice = &row_pixels.samples[(y * width * 4) as usize..((y + 1) * width * 4) as usize];
 
-            let mut x = 0;
-            // Process pixels in chunks of 8 using AVX2
-            let chunk_size = 8;
-            while x + chunk_size <= width as usize {
-                // This block is where the SIMD magic happens
-                unsafe {
-                    process_chunk_simd(&row_slice[x * 4..], &mut row_str);
-                }
-                x += chunk_size;
-            }
+            let mut x = process_chunks(row_slice, &mut row_str);
 
-            // Process any remaining pixels that didn't fit in a chunk of 8
+            // Process any remaining pixels that didn't fit in a chunk of 8, or the whole
+            // row when AVX2 is unavailable
             while x < width as usize {
                 let pixel = Rgba([
                     row_slice[x * 4],
@@ -64,10 +49,94 @@ pub fn create_ascii_art(image: &DynamicImage, area: Rect) -> String {
     rows.join("\n")
 }
 
+/// Converts an image to colored ASCII art, one `Line` per row, with each character
+/// drawn in the color of the pixel it stands for. Terminals without true color
+/// support should use `create_ascii_art` instead.
+pub fn create_ansi_art(image: &DynamicImage, area: Rect) -> Vec<Line<'static>> {
+    let Some(resized_image) = resize_for_area(image, area) else {
+        return Vec::new();
+    };
+
+    // Process each row in parallel using Rayon
+    (0..resized_image.height())
+        .into_par_iter()
+        .map(|y| {
+            let spans: Vec<Span> = (0..resized_image.width())
+                .map(|x| {
+                    let pixel = *resized_image.get_pixel(x, y);
+                    let color = Color::Rgb(pixel[0], pixel[1], pixel[2]);
+                    let glyph = pixel_to_ascii(pixel).to_string();
+                    Span::styled(glyph, Style::default().fg(color))
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect()
+}
+
+/// Returns whether the terminal advertises true color support through `COLORTERM`.
+pub fn supports_true_color() -> bool {
+    std::env::var("COLORTERM").is_ok_and(|value| value == "truecolor" || value == "24bit")
+}
+
+/// Resizes an image to fill `area` with one pixel per character, or returns `None`
+/// when the area is too small to draw in.
+fn resize_for_area(image: &DynamicImage, area: Rect) -> Option<RgbaImage> {
+    if area.width == 0 || area.height < 2 {
+        return None;
+    }
+
+    // Adjust height to compensate for character aspect ratio
+    let ascii_height = (area.height as f32 / 2.0).round() as u32;
+    if ascii_height == 0 {
+        return None;
+    }
+
+    // Use a specific image format (RGBA) for predictable memory layout, which is safer for SIMD
+    let resized_image = image.resize_exact(area.width as u32, ascii_height, FilterType::Nearest);
+    Some(resized_image.to_rgba8())
+}
+
+/// Processes the row in chunks of 8 pixels using AVX2, if the CPU supports it, and
+/// returns the number of pixels processed.
+#[cfg(target_arch = "x86_64")]
+fn process_chunks(row_slice: &[u8], row_str: &mut String) -> usize {
+    if !is_x86_feature_detected!("avx2") {
+        return 0;
+    }
+
+    let chunk_size = 8;
+    let width = row_slice.len() / 4;
+    let mut x = 0;
+    while x + chunk_size <= width {
+        // SAFETY: AVX2 support was detected above, and the chunk holds 32 bytes.
+        unsafe {
+            process_chunk_simd(&row_slice[x * 4..], row_str);
+        }
+        x += chunk_size;
+    }
+    x
+}
+
+/// Leaves the whole row to the scalar path, as the SIMD path is x86_64-only.
+#[cfg(not(target_arch = "x86_64"))]
+fn process_chunks(_row_slice: &[u8], _row_str: &mut String) -> usize {
+    0
+}
+
 /// Processes a chunk of 8 pixels (32 bytes) using AVX2 SIMD instructions.
+///
+/// The grayscale values are computed with the same operations, in the same order, as
+/// `pixel_to_ascii`, so both paths produce identical characters.
+///
+/// # Safety
+///
+/// The CPU must support AVX2, and `pixel_slice` must hold at least 32 bytes.
+#[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "avx2")]
 unsafe fn process_chunk_simd(pixel_slice: &[u8], row_str: &mut String) {
-    // 1. Load 8 pixels (RGBA... 32 bytes) into a 256-bit register
+    // 1. Load 8 pixels (RGBA... 32 bytes) into a 256-bit register, one pixel per
+    // 32-bit lane, with R in the lowest byte of each lane
     let pixel_data = _mm256_loadu_si256(pixel_slice.as_ptr() as *const __m256i);
 
     // Coefficients for grayscale conversion (R: 0.299, G: 0.587, B: 0.114)
@@ -75,51 +144,85 @@ unsafe fn process_chunk_simd(pixel_slice: &[u8], row_str: &mut String) {
     let g_coeffs = _mm256_set1_ps(0.587);
     let b_coeffs = _mm256_set1_ps(0.114);
 
-    // We need to unpack the u8 values into four f32 vectors.
-    // First, load the lower 128 bits (4 pixels) and convert them to 32-bit integers
-    let lower_half = _mm256_castsi256_si128(pixel_data);
-    let pixels_i32_lo = _mm256_cvtepu8_epi32(lower_half);
-
-    // Then do the same for the upper 128 bits (next 4 pixels)
-    let upper_half = _mm_loadu_si128(pixel_slice.as_ptr().add(16) as *const __m128i);
-    let pixels_i32_hi = _mm256_cvtepu8_epi32(upper_half);
-
-    // Now convert the integer vectors to floating-point vectors
-    let pixels_ps_lo = _mm256_cvtepi32_ps(pixels_i32_lo);
-    let pixels_ps_hi = _mm256_cvtepi32_ps(pixels_i32_hi);
-
-    // Shuffle the pixel data to separate R, G, B channels
-    // The shuffle mask selects which elements to use from each input vector.
-    // Lo Group: [R0 G0 B0 A0, R1 G1 B1 A1] -> [R0 R1 G0 G1, B0 B1 A0 A1] -> [R0 G0 B0 R1 G1 B1 ..]
-    let r_ps = _mm256_shuffle_ps(pixels_ps_lo, pixels_ps_hi, 0b10_00_10_00);
-    let g_ps = _mm256_shuffle_ps(pixels_ps_lo, pixels_ps_hi, 0b11_01_11_01);
-    let b_ps = _mm256_shuffle_ps(pixels_ps_lo, pixels_ps_hi, 0b10_10_00_10); // This is a bit tricky, but it works out
-
-    // 2. Calculate grayscale values in parallel using Fused Multiply-Add
+    // Deinterleave the channels by shifting each one down to the lowest byte of its
+    // lane and masking off the others, then convert them to floating-point vectors
+    let byte_mask = _mm256_set1_epi32(0xFF);
+    let r_i32 = _mm256_and_si256(pixel_data, byte_mask);
+    let g_i32 = _mm256_and_si256(_mm256_srli_epi32(pixel_data, 8), byte_mask);
+    let b_i32 = _mm256_and_si256(_mm256_srli_epi32(pixel_data, 16), byte_mask);
+    let r_ps = _mm256_cvtepi32_ps(r_i32);
+    let g_ps = _mm256_cvtepi32_ps(g_i32);
+    let b_ps = _mm256_cvtepi32_ps(b_i32);
+
+    // 2. Calculate grayscale values in parallel. Separate multiplies and adds, rather
+    // than fused multiply-adds, round exactly like the scalar path
     let r_contrib = _mm256_mul_ps(r_ps, r_coeffs);
     let g_contrib = _mm256_mul_ps(g_ps, g_coeffs);
     let b_contrib = _mm256_mul_ps(b_ps, b_coeffs);
-    let gray_ps = _mm256_add_ps(r_contrib, _mm256_add_ps(g_contrib, b_contrib));
+    let gray_ps = _mm256_add_ps(_mm256_add_ps(r_contrib, g_contrib), b_contrib);
 
-    // 3. Map grayscale values (0-255) to character indices (0-10)
-    let scale_factor = _mm256_set1_ps((ASCII_CHARS.len() - 1) as f32 / 255.0);
-    let scaled_gray = _mm256_mul_ps(gray_ps, scale_factor);
-    let rounded_indices = _mm256_round_ps(scaled_gray, _MM_FROUND_TO_NEAREST_INT |_MM_FROUND_NO_EXC);
-    let indices_i32 = _mm256_cvtps_epi32(rounded_indices);
+    // 3. Truncate the grayscale values to integers, like the scalar `as u8` cast
+    let gray_i32 = _mm256_cvttps_epi32(gray_ps);
 
     // 4. Store the results into a temporary array
-    let mut indices_arr = [0i32; 8];
-    _mm256_storeu_si256(indices_arr.as_mut_ptr() as *mut __m256i, indices_i32);
+    let mut gray_arr = [0i32; 8];
+    _mm256_storeu_si256(gray_arr.as_mut_ptr() as *mut __m256i, gray_i32);
 
     // 5. Append the corresponding characters to the string
-    for &index in indices_arr.iter() {
-        row_str.push(ASCII_CHARS[index.clamp(0, 10) as usize]);
+    for &gray in gray_arr.iter() {
+        row_str.push(gray_to_ascii(gray.clamp(0, 255) as u8));
     }
 }
 
 /// Scalar fallback for a single pixel.
 fn pixel_to_ascii(pixel: Rgba<u8>) -> char {
     let gray = (pixel[0] as f32 * 0.299 + pixel[1] as f32 * 0.587 + pixel[2] as f32 * 0.114) as u8;
+    gray_to_ascii(gray)
+}
+
+/// Maps a grayscale value to its ASCII character.
+fn gray_to_ascii(gray: u8) -> char {
     let char_index = (gray as f32 / 255.0 * (ASCII_CHARS.len() - 1) as f32).round() as usize;
     ASCII_CHARS[char_index]
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_simd_matches_scalar() {
+        // Pixels covering every channel value, with the channels out of step so a
+        // swapped channel changes the result.
+        let pixels: Vec<Rgba<u8>> = (0..=255u8)
+            .map(|i| Rgba([i, i.wrapping_mul(7), i.wrapping_mul(13) ^ 0x5A, 255 - i]))
+            .collect();
+        let row_slice: Vec<u8> = pixels.iter().flat_map(|pixel| pixel.0).collect();
+
+        let mut row_str = String::new();
+        let processed = process_chunks(&row_slice, &mut row_str);
+        let expected: String = pixels[..processed]
+            .iter()
+            .copied()
+            .map(pixel_to_ascii)
+            .collect();
+        assert_eq!(row_str, expected);
+    }
+
+    #[test]
+    fn test_ansi_art_colors_match_pixels() {
+        let mut image = RgbaImage::from_pixel(4, 2, Rgba([255, 255, 255, 255]));
+        image.put_pixel(0, 0, Rgba([200, 40, 40, 255]));
+        let image = DynamicImage::ImageRgba8(image);
+        let area = Rect::new(0, 0, 4, 4);
+
+        let lines = create_ansi_art(&image, area);
+        let plain = create_ascii_art(&image, area);
+        assert_eq!(lines.len(), 2);
+        for (line, plain_row) in lines.iter().zip(plain.lines()) {
+            assert_eq!(line.to_string(), plain_row);
+        }
+        assert_eq!(lines[0].spans[0].style.fg, Some(Color::Rgb(200, 40, 40)));
+        assert_eq!(lines[1].spans[3].style.fg, Some(Color::Rgb(255, 255, 255)));
+    }
+}
@@ -1,122 +1,305 @@
 use anyhow::Result;
 use image::DynamicImage;
-use sha2::{Digest, Sha256};
 use std::{
-    fs,
-    io::{self, Read},
+    fs, io,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::SystemTime,
 };
-use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 use crate::{
-    args::V3Model,
+    args::{OutputFormat, V3Model},
     db::Database,
+    deduplicate::{self, DuplicateAction},
     file::{self, TaggingResultSimple},
-    video,
+    progress::ProgressSink,
+    report::{FileOutcome, ProcessReport},
+    video::{self, FrameExtractOptions},
 };
 use eros::{
-    pipeline::TaggingPipeline,
+    dedup::HashAlgorithm,
+    error::TaggerError,
+    optimizer::OptimizerOptions,
+    pipeline::{TaggingPipeline, TaggingPipelineBuilder, TaggingResult},
     prelude::{self},
-    rating::RatingModel,
-    tagger::Device,
+    processor::open_image,
+    rating::{Rating, RatingModel},
+    tagger::{Device, TaggerModel},
 };
 
-use super::app::ProgressUpdate;
+/// Where the tagging results are stored by default.
+pub const DATABASE_PATH: &str = "./data/victim.db";
 
 /// Runs the full media processing pipeline.
-pub async fn run_full_process(
+///
+/// Progress is reported to `sink`. Returns a report of each file's outcome, which is
+/// also written to `config.report_path` when set.
+pub async fn run_full_process<P: ProgressSink + Clone + 'static>(
     config: AppConfig,
     selected_dirs: Vec<PathBuf>,
-    tx: mpsc::Sender<ProgressUpdate>,
-) -> Result<()> {
-    prepare_media_files(&selected_dirs, &tx).await?;
-    let (pipe, rating_model, db) = initialize_pipeline_and_db(&config, &tx).await?;
+    sink: P,
+) -> Result<ProcessReport> {
+    let mut report = ProcessReport::default();
+
+    sink.message("Removing duplicate images...");
+    deduplicate::remove_duplicate_images(
+        &selected_dirs,
+        config.dedup_max_distance,
+        config.dedup_algorithm,
+        if config.dry_run {
+            &DuplicateAction::Report
+        } else {
+            &config.duplicate_action
+        },
+        &config.excluded_dirs(&selected_dirs),
+        &sink,
+        &mut report,
+    )?;
+    if config.dry_run {
+        return finish_dry_run(&selected_dirs, &config, report, &sink).await;
+    }
+    prepare_media_files(&selected_dirs, &config, &sink, &mut report).await?;
+    let (pipe, rating_model, db) = match initialize_pipeline_and_db(&config, &sink).await {
+        Err(e) if is_cancelled(&e) => {
+            return finish_interrupted(&config, report, &sink);
+        }
+        result => result?,
+    };
     process_images(
         &selected_dirs,
         &pipe,
         &rating_model,
         &db,
-        &tx,
-        config.show_ascii_art,
+        &sink,
+        &config,
+        &mut report,
     )
     .await?;
+    if config.shutdown.is_cancelled() {
+        return finish_interrupted(&config, report, &sink);
+    }
     process_videos(
         &selected_dirs,
         &pipe,
         &rating_model,
         &db,
-        &tx,
-        config.show_ascii_art,
+        &sink,
+        &config,
+        &mut report,
     )
     .await?;
+    if config.shutdown.is_cancelled() {
+        return finish_interrupted(&config, report, &sink);
+    }
 
-    tx.send(ProgressUpdate::Message(
-        "Optimizing media files...".to_string(),
-    ))
-    .await?;
-    eros::optimizer::optimize_media_in_dirs(&selected_dirs).await?;
-    tx.send(ProgressUpdate::Progress(0.99)).await?;
+    sink.progress(0.99);
 
-    tx.send(ProgressUpdate::Complete).await?;
-    Ok(())
+    if let Some(report_path) = &config.report_path {
+        report.write_json(report_path)?;
+    }
+
+    sink.complete();
+    Ok(report)
+}
+
+/// Returns whether `error` came from an operation aborted by a cancellation token.
+fn is_cancelled(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<TaggerError>(),
+        Some(TaggerError::Cancelled)
+    )
+}
+
+/// Ends a run stopped by `AppConfig::shutdown`.
+///
+/// Every file tagged so far has already been saved to the database, so only the
+/// report is left to write.
+fn finish_interrupted(
+    config: &AppConfig,
+    report: ProcessReport,
+    sink: &impl ProgressSink,
+) -> Result<ProcessReport> {
+    if let Some(report_path) = &config.report_path {
+        report.write_json(report_path)?;
+    }
+    sink.message("Interrupted; results saved so far are kept in the database.");
+    sink.complete();
+    Ok(report)
 }
 
-/// Prepares media files by renaming, converting, and resizing them.
+/// Ends a run with `AppConfig::dry_run` set, reporting what the rest of the run would
+/// rename, convert, and optimize without touching any file.
+///
+/// Tagging is skipped too, as it would run on names and formats that were never
+/// applied, and would create the database.
+async fn finish_dry_run(
+    selected_dirs: &[PathBuf],
+    config: &AppConfig,
+    report: ProcessReport,
+    sink: &impl ProgressSink,
+) -> Result<ProcessReport> {
+    let excluded_dirs = config.excluded_dirs(selected_dirs);
+    let options = prelude::RenameOptions {
+        dry_run: true,
+        exclude: excluded_dirs.clone(),
+    };
+    for (old_path, new_path) in prelude::rename_files_with_options(selected_dirs, &options)? {
+        sink.message(&format!("Would rename {:?} to {:?}", old_path, new_path));
+    }
+    sink.progress(0.25);
+
+    let options = prelude::ConvertOptions {
+        keep_originals: !config.delete_originals,
+        originals_dir: Some(prelude::ORIGINALS_DIR.into()),
+        dry_run: true,
+        exclude: excluded_dirs.clone(),
+        ..Default::default()
+    };
+    let converted = prelude::convert_and_strip_metadata_with_options(selected_dirs, &options)?;
+    for (path, new_path) in converted {
+        let message = if path == new_path {
+            format!("Would strip the metadata of {:?}", path)
+        } else if !config.delete_originals {
+            let action = format!("and move the original into {:?}", prelude::ORIGINALS_DIR);
+            format!("Would convert {:?} to {:?} {}", path, new_path, action)
+        } else {
+            let action = "and delete the original";
+            format!("Would convert {:?} to {:?} {}", path, new_path, action)
+        };
+        sink.message(&message);
+    }
+    sink.message("Would resize media to 448x448");
+    sink.progress(0.5);
+
+    let options = OptimizerOptions {
+        dry_run: true,
+        exclude: excluded_dirs,
+        ..Default::default()
+    };
+    let optimized =
+        eros::optimizer::optimize_media_in_dirs_with_options(selected_dirs, &options).await?;
+    for path in optimized {
+        sink.message(&format!("Would optimize {:?}", path));
+    }
+    sink.progress(0.99);
+
+    if let Some(report_path) = &config.report_path {
+        report.write_json(report_path)?;
+    }
+    sink.message("Dry run; no files were changed.");
+    sink.complete();
+    Ok(report)
+}
+
+/// Prepares media files by renaming, converting, resizing, and optimizing them.
+///
+/// Optimizing re-encodes files, so it runs before tagging: the hashes stored then,
+/// and the XMP written with `AppConfig::write_xmp`, are those of the final files.
 async fn prepare_media_files(
     selected_dirs: &[PathBuf],
-    tx: &mpsc::Sender<ProgressUpdate>,
+    config: &AppConfig,
+    sink: &impl ProgressSink,
+    report: &mut ProcessReport,
 ) -> Result<()> {
-    tx.send(ProgressUpdate::Message("Renaming files...".to_string()))
-        .await?;
-    prelude::rename_files_in_selected_dirs(selected_dirs)?;
-    tx.send(ProgressUpdate::Progress(0.05)).await?;
+    let excluded_dirs = config.excluded_dirs(selected_dirs);
+    sink.message("Renaming files...");
+    let options = prelude::RenameOptions {
+        exclude: excluded_dirs.clone(),
+        ..Default::default()
+    };
+    for (old_path, new_path) in prelude::rename_files_with_options(selected_dirs, &options)? {
+        report.record_with_detail(
+            new_path,
+            FileOutcome::Renamed,
+            format!("renamed from {:?}", old_path),
+        );
+    }
+    sink.progress(0.05);
 
-    tx.send(ProgressUpdate::Message(
-        "Converting files and stripping metadata...".to_string(),
-    ))
-    .await?;
-    prelude::convert_and_strip_metadata(selected_dirs)?;
-    tx.send(ProgressUpdate::Progress(0.1)).await?;
+    sink.message("Converting files and stripping metadata...");
+    prelude::convert_and_strip_metadata_with_options(
+        selected_dirs,
+        &prelude::ConvertOptions {
+            keep_originals: !config.delete_originals,
+            originals_dir: Some(prelude::ORIGINALS_DIR.into()),
+            exclude: excluded_dirs.clone(),
+            ..Default::default()
+        },
+    )?;
+    sink.progress(0.1);
 
-    tx.send(ProgressUpdate::Message("Resizing media...".to_string()))
-        .await?;
-    prelude::resize_media(selected_dirs, (448, 448))?;
-    tx.send(ProgressUpdate::Progress(0.15)).await?;
+    sink.message("Resizing media...");
+    prelude::resize_media_with_options(
+        selected_dirs,
+        (448, 448),
+        &prelude::ResizeOptions {
+            min_dimension: config.min_image_dimension,
+            exclude: excluded_dirs.clone(),
+        },
+    )?;
+    sink.progress(0.12);
+
+    sink.message("Optimizing media files...");
+    let optimized = eros::optimizer::optimize_media_in_dirs_with_progress(
+        selected_dirs,
+        &OptimizerOptions {
+            exclude: excluded_dirs,
+            ..Default::default()
+        },
+        |done, total| {
+            // Called from the optimizer's threads.
+            sink.message(&format!("Optimized {}/{} media files", done, total));
+        },
+    )
+    .await?;
+    for path in optimized {
+        report.record(path, FileOutcome::Optimized);
+    }
+    sink.progress(0.15);
     Ok(())
 }
 
 /// Initializes the tagging pipeline and the database.
-async fn initialize_pipeline_and_db(
+async fn initialize_pipeline_and_db<P: ProgressSink + Clone + 'static>(
     config: &AppConfig,
-    tx: &mpsc::Sender<ProgressUpdate>,
+    sink: &P,
 ) -> Result<(
     Arc<Mutex<TaggingPipeline>>,
     Arc<Mutex<RatingModel>>,
     Arc<Mutex<Database>>,
 )> {
-    let tx_clone = tx.clone();
+    let callback_sink = sink.clone();
     let progress_callback = Box::new(move |progress: f32, message: String| {
-        let _ = tx_clone.try_send(ProgressUpdate::Message(message));
-        let _ = tx_clone.try_send(ProgressUpdate::Progress(0.15 + (progress as f64 * 0.1)));
+        callback_sink.message(&message);
+        callback_sink.progress(0.15 + (progress as f64 * 0.1));
     });
 
-    let mut pipe = TaggingPipeline::from_pretrained(
-        &config.model.repo_id(),
-        Device::cpu(),
-        Some(progress_callback),
-    )
-    .await?;
-    pipe.threshold = config.threshold;
+    let pipe = TaggingPipelineBuilder::new()
+        .model_repo(&config.model.repo_id())
+        .device(Device::cpu())
+        .threshold(config.threshold)
+        .max_batch_size(config.batch_size.max(1))
+        .progress_callback(progress_callback)
+        .cancel(config.shutdown.clone())
+        .build()
+        .await?;
+    sink.message(&format!(
+        "Running inference on {}",
+        TaggerModel::active_provider()
+    ));
     let pipe = Arc::new(Mutex::new(pipe));
 
     let rating_model = RatingModel::new().await?;
     let rating_model = Arc::new(Mutex::new(rating_model));
 
-    tx.send(ProgressUpdate::Progress(0.25)).await?;
+    sink.progress(0.25);
 
-    fs::create_dir_all("./data")?;
-    let db = Database::new("./data/victim.db")?;
+    let db_path = config.database_path();
+    if let Some(parent) = db_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let db = Database::new(db_path)?;
     db.init()?;
     Ok((pipe, rating_model, Arc::new(Mutex::new(db))))
 }
@@ -127,119 +310,651 @@ async fn process_images(
     pipe: &Arc<Mutex<TaggingPipeline>>,
     rating_model: &Arc<Mutex<RatingModel>>,
     db: &Arc<Mutex<Database>>,
-    tx: &mpsc::Sender<ProgressUpdate>,
-    show_ascii_art: bool,
+    sink: &impl ProgressSink,
+    config: &AppConfig,
+    report: &mut ProcessReport,
 ) -> Result<()> {
+    let excluded_dirs = config.excluded_dirs(selected_dirs);
     let mut image_files = Vec::new();
     for dir in selected_dirs {
         if let Some(dir_str) = dir.to_str() {
-            image_files.extend(file::get_image_files(dir_str).await?);
+            let files =
+                file::get_image_files_with_depth(dir_str, config.max_scan_depth, &excluded_dirs);
+            image_files.extend(files.await?);
         }
     }
 
+    let image_files = file::filter_modified_since(image_files, config.modified_since);
+    let (image_files, skipped) =
+        file::filter_small_images(image_files, config.min_image_dimension);
+    if !skipped.is_empty() {
+        sink.message(&format!(
+            "Skipped {} images smaller than {}px",
+            skipped.len(),
+            config.min_image_dimension
+        ));
+    }
+    for path in skipped {
+        report.record(path, FileOutcome::SkippedSmall);
+    }
+
     let total_images = image_files.len();
     if total_images > 0 {
-        tx.send(ProgressUpdate::Message(format!(
-            "Processing {} image files...",
-            total_images
-        )))
-        .await?;
-        for (i, image_file) in image_files.into_iter().enumerate() {
-            let img = image::open(&image_file)?;
-            if show_ascii_art {
-                // We don't care if this fails, it just means the UI closed.
-                let _ = tx
-                    .send(ProgressUpdate::ImageProcessed(image_file.clone()))
-                    .await;
+        sink.message(&format!("Processing {} image files...", total_images));
+        let mut already_tagged = 0;
+        let mut done = 0;
+        let mut csv_rows = Vec::new();
+        for chunk in image_files.chunks(config.batch_size.max(1)) {
+            if config.shutdown.is_cancelled() {
+                break;
+            }
+            let mut batch = Vec::new();
+            for image_file in chunk {
+                // An unreadable or corrupt file is skipped rather than ending the run.
+                let hash = match get_hash(image_file) {
+                    Ok(hash) => hash,
+                    Err(e) => {
+                        skip_unreadable(image_file, e, sink, report);
+                        done += 1;
+                        sink.progress(0.25 + 0.375 * done as f64 / total_images as f64);
+                        continue;
+                    }
+                };
+                if !config.force && db.lock().unwrap().contains_hash(&hash)? {
+                    // The file may have been renamed or moved since it was tagged.
+                    let stored_path = file::storage_path(image_file, config.path_root.as_deref())?;
+                    db.lock()
+                        .unwrap()
+                        .update_image_filename(&hash, &stored_path)?;
+                    already_tagged += 1;
+                    done += 1;
+                    report.record(image_file.clone(), FileOutcome::SkippedAlreadyTagged);
+                    sink.progress(0.25 + 0.375 * done as f64 / total_images as f64);
+                    continue;
+                }
+                match open_image(image_file) {
+                    Ok(image) => batch.push((image_file.clone(), hash, image)),
+                    Err(e) => {
+                        skip_unreadable(image_file, e, sink, report);
+                        done += 1;
+                        sink.progress(0.25 + 0.375 * done as f64 / total_images as f64);
+                    }
+                }
             }
-            let rating = rating_model.lock().unwrap().rate(&img)?;
-            let result = pipe.lock().unwrap().predict(img, None)?;
-            let simple_result = TaggingResultSimple::from(result);
-            let hash = get_hash(&image_file)?;
-            let size = fs::metadata(&image_file)?.len();
-            if let Some(path_str) = image_file.to_str() {
-                db.lock().unwrap().save_image_tags(
-                    path_str,
-                    size,
-                    &hash,
-                    &simple_result.tags,
-                    rating.as_str(),
-                )?;
+            if batch.is_empty() {
+                continue;
+            }
+
+            if config.show_ascii_art {
+                for (image_file, _, _) in &batch {
+                    sink.image_processed(image_file);
+                }
+            }
+            let images: Vec<&DynamicImage> = batch.iter().map(|(_, _, img)| img).collect();
+            let results = pipe
+                .lock()
+                .unwrap()
+                .predict_batch_with_rating(&images, &mut rating_model.lock().unwrap())?;
+
+            for ((image_file, hash, _), (rating, result)) in batch.into_iter().zip(results) {
+                if config.output == OutputFormat::Csv {
+                    csv_rows.push((image_file.clone(), result.clone()));
+                }
+                match save_image_result(&image_file, hash, rating, result, db, sink, config) {
+                    Ok(()) => report.record(image_file, FileOutcome::Tagged),
+                    Err(e) => {
+                        let message = format!("{:#}", e);
+                        sink.message(&format!(
+                            "Could not save the tags of {:?}: {}",
+                            image_file, message
+                        ));
+                        report.record_with_detail(image_file, FileOutcome::Failed, message);
+                    }
+                }
+                done += 1;
+                sink.progress(0.25 + 0.375 * done as f64 / total_images as f64);
             }
-            tx.send(ProgressUpdate::Progress(
-                0.25 + 0.375 * (i + 1) as f64 / total_images as f64,
-            ))
-            .await?;
+        }
+        if !csv_rows.is_empty() {
+            let csv_path = config.csv_output_path();
+            eros::export::write_csv(&csv_rows, &csv_path)?;
+            sink.message(&format!(
+                "Wrote the tags of {} images to {:?}",
+                csv_rows.len(),
+                csv_path
+            ));
+        }
+        if already_tagged > 0 {
+            sink.message(&format!(
+                "Skipped {} images that were already tagged",
+                already_tagged
+            ));
         }
     }
     Ok(())
 }
 
+/// Reports an image that could not be read or decoded, and so is skipped.
+fn skip_unreadable(
+    image_file: &Path,
+    error: anyhow::Error,
+    sink: &impl ProgressSink,
+    report: &mut ProcessReport,
+) {
+    let message = format!("{:#}", error);
+    sink.message(&format!("Skipping {:?}: {}", image_file, message));
+    report.record_with_detail(image_file, FileOutcome::SkippedCorrupt, message);
+}
+
+/// Writes the tags of one image to its outputs and saves them to the database.
+fn save_image_result(
+    image_file: &Path,
+    hash: String,
+    rating: Rating,
+    result: TaggingResult,
+    db: &Arc<Mutex<Database>>,
+    sink: &impl ProgressSink,
+    config: &AppConfig,
+) -> Result<()> {
+    if config.output == OutputFormat::Json {
+        eros::export::write_json_sidecar(&result, image_file)?;
+    }
+    let simple_result = TaggingResultSimple::from(result);
+    if config.write_xmp {
+        // Written before hashing so the stored hash matches the file on disk.
+        let tags: Vec<&String> = simple_result
+            .tagger
+            .character
+            .iter()
+            .chain(&simple_result.tagger.general)
+            .collect();
+        if let Err(e) = eros::xmp::write_tags_to_xmp(image_file, &tags) {
+            sink.message(&format!("Could not write XMP to {:?}: {}", image_file, e));
+        }
+    }
+    // Writing XMP changes the file, so its hash is taken again.
+    let hash = if config.write_xmp {
+        get_hash(image_file)?
+    } else {
+        hash
+    };
+    let size = fs::metadata(image_file)?.len();
+    let stored_path = file::storage_path(image_file, config.path_root.as_deref())?;
+    db.lock().unwrap().save_image_tags(
+        &stored_path,
+        size,
+        &hash,
+        &simple_result.tags,
+        rating.as_str(),
+    )
+}
+
 /// Processes all video files in the selected directories.
 async fn process_videos(
     selected_dirs: &[PathBuf],
     pipe: &Arc<Mutex<TaggingPipeline>>,
     rating_model: &Arc<Mutex<RatingModel>>,
     db: &Arc<Mutex<Database>>,
-    tx: &mpsc::Sender<ProgressUpdate>,
-    show_ascii_art: bool,
+    sink: &impl ProgressSink,
+    config: &AppConfig,
+    report: &mut ProcessReport,
 ) -> Result<()> {
+    let excluded_dirs = config.excluded_dirs(selected_dirs);
     let mut video_files = Vec::new();
     for dir in selected_dirs {
         if let Some(dir_str) = dir.to_str() {
-            video_files.extend(video::get_video_files(dir_str).await?);
+            let files = video::get_video_files(dir_str, config.max_scan_depth, &excluded_dirs);
+            video_files.extend(files.await?);
         }
     }
+    let video_files = file::filter_modified_since(video_files, config.modified_since);
 
     let total_videos = video_files.len();
     if total_videos > 0 {
-        tx.send(ProgressUpdate::Message(format!(
-            "Processing {} video files...",
-            total_videos
-        )))
-        .await?;
+        sink.message(&format!("Processing {} video files...", total_videos));
         for (i, video_file) in video_files.into_iter().enumerate() {
-            video::process_video(
+            if config.shutdown.is_cancelled() {
+                break;
+            }
+            let outcome = video::process_video(
                 &video_file,
                 pipe,
                 rating_model,
                 db,
                 get_hash,
-                tx,
-                show_ascii_art,
-            )
-            .await?;
-            tx.send(ProgressUpdate::Progress(
-                0.625 + 0.375 * (i + 1) as f64 / total_videos as f64,
-            ))
-            .await?;
+                sink,
+                config,
+            )?;
+            report.record(video_file, outcome);
+            sink.progress(0.625 + 0.375 * (i + 1) as f64 / total_videos as f64);
         }
     }
     Ok(())
 }
 
+/// Tags a single image or video with `model`, without touching the database.
+///
+/// Videos are tagged frame by frame and merged like in a full run.
+pub async fn tag_file(path: &Path, model: V3Model, threshold: f32) -> Result<TaggingResult> {
+    let mut pipe = TaggingPipelineBuilder::new()
+        .model_repo(&model.repo_id())
+        .device(Device::cpu())
+        .threshold(threshold)
+        .build()
+        .await?;
+
+    if video::is_video(&path.to_string_lossy())? {
+        video::tag_video(path, &mut pipe, &FrameExtractOptions::default())
+    } else {
+        pipe.predict(open_image(path)?, None)
+    }
+}
+
 /// Computes the SHA256 hash of a file.
 fn get_hash(path: &Path) -> Result<String> {
-    let mut file = fs::File::open(path)?;
-    let mut hasher = Sha256::new();
-    let mut buffer = [0; 1024];
-    loop {
-        let count = file.read(&mut buffer)?;
-        if count == 0 {
-            break;
-        }
-        hasher.update(&buffer[..count]);
-    }
-    Ok(format!("{:x}", hasher.finalize()))
+    eros::file::sha256_file(path)
 }
 
 /// Holds the configuration settings for the application.
 #[derive(Debug, Default, Clone)]
 pub struct AppConfig {
     pub model: V3Model,
+    /// Where tags are written besides the database.
+    pub output: OutputFormat,
     pub input_path: String,
     pub video_path: String,
     pub threshold: f32,
     pub batch_size: usize,
     pub show_ascii_art: bool,
+    /// The maximum fingerprint distance at which two images count as duplicates.
+    pub dedup_max_distance: u32,
+    /// The fingerprint used to find duplicates.
+    pub dedup_algorithm: HashAlgorithm,
+    /// What is done with the duplicates found.
+    pub duplicate_action: DuplicateAction,
+    /// When set, paths are stored relative to this root instead of as absolute paths.
+    pub path_root: Option<PathBuf>,
+    /// Images whose width or height is below this many pixels are skipped (0 disables).
+    pub min_image_dimension: u32,
+    /// Delete source files after converting them to another format. Off by default, so
+    /// conversion never loses data unless asked to; the sources are moved into the
+    /// `prelude::ORIGINALS_DIR` of their selected directory instead.
+    pub delete_originals: bool,
+    /// Where to write the per-file JSON report, if anywhere.
+    pub report_path: Option<PathBuf>,
+    /// Cancelled to stop the run after the file currently being tagged is saved.
+    pub shutdown: CancellationToken,
+    /// Also write each image's tags into its XMP metadata.
+    pub write_xmp: bool,
+    /// When set, only files modified after this time are tagged.
+    pub modified_since: Option<SystemTime>,
+    /// Re-tag images whose hash is already in the database.
+    pub force: bool,
+    /// Only report what would be deleted, renamed, converted, and optimized, without
+    /// touching any file or tagging anything.
+    pub dry_run: bool,
+    /// Which frames are sampled from each video for tagging.
+    pub frame_options: FrameExtractOptions,
+    /// Where the tagging results are stored; `DATABASE_PATH` when unset.
+    pub db_path: Option<PathBuf>,
+    /// How many directory levels are scanned for media to tag, where `1` is only the
+    /// selected directories themselves; every level when unset.
+    pub max_scan_depth: Option<usize>,
+}
+
+impl AppConfig {
+    /// Returns the path of the tagging database.
+    pub fn database_path(&self) -> &Path {
+        self.db_path.as_deref().unwrap_or(Path::new(DATABASE_PATH))
+    }
+
+    /// Returns where `OutputFormat::Csv` writes the tags: next to the database, with a
+    /// `.csv` extension.
+    pub fn csv_output_path(&self) -> PathBuf {
+        self.database_path().with_extension("csv")
+    }
+
+    /// Returns the directories every pass over `selected_dirs` skips: the quarantine of
+    /// `DuplicateAction::MoveTo` and the `prelude::ORIGINALS_DIR` of each selected
+    /// directory, so files moved to either stay as they were and are never tagged.
+    pub fn excluded_dirs(&self, selected_dirs: &[PathBuf]) -> Vec<PathBuf> {
+        let mut excluded = match &self.duplicate_action {
+            DuplicateAction::MoveTo(dir) => vec![dir.clone()],
+            DuplicateAction::Delete | DuplicateAction::Report => Vec::new(),
+        };
+        for dir in selected_dirs {
+            excluded.push(dir.join(prelude::ORIGINALS_DIR));
+        }
+        excluded
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::progress::{ChannelProgress, NullProgress, PROGRESS_CHANNEL_CAPACITY};
+    use image::{Rgb, RgbImage};
+
+    /// Cancels its token as soon as any progress is reported.
+    struct CancelOnProgress(CancellationToken);
+
+    impl ProgressSink for CancelOnProgress {
+        fn message(&self, _s: &str) {}
+
+        fn progress(&self, _p: f64) {
+            self.0.cancel();
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_shutdown_keeps_processed_results() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let total = 4;
+        for i in 0..total {
+            RgbImage::from_pixel(64, 64, Rgb([i as u8 * 60, 0, 0]))
+                .save(temp_dir.path().join(format!("{}.png", i)))
+                .unwrap();
+        }
+
+        let config = AppConfig::default();
+        let mut pipe =
+            TaggingPipeline::from_pretrained(&config.model.repo_id(), Device::cpu(), None)
+                .await
+                .unwrap();
+        pipe.threshold = 0.35;
+        let pipe = Arc::new(Mutex::new(pipe));
+        let rating_model = Arc::new(Mutex::new(RatingModel::new().await.unwrap()));
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        db.init().unwrap();
+        let db = Arc::new(Mutex::new(db));
+
+        // Cancelling on the first progress report simulates Ctrl-C after the first
+        // image is saved.
+        let sink = CancelOnProgress(config.shutdown.clone());
+
+        let mut report = ProcessReport::default();
+        process_images(
+            &[temp_dir.path().to_path_buf()],
+            &pipe,
+            &rating_model,
+            &db,
+            &sink,
+            &config,
+            &mut report,
+        )
+        .await
+        .unwrap();
+
+        let saved = db.lock().unwrap().image_count().unwrap();
+        assert!(saved > 0);
+        assert!(saved < total);
+        assert_eq!(report.files.len(), saved);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_corrupt_image_is_skipped() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        for i in 0..2 {
+            RgbImage::from_pixel(64, 64, Rgb([i as u8 * 60, 0, 0]))
+                .save(temp_dir.path().join(format!("{}.png", i)))
+                .unwrap();
+        }
+        let broken = temp_dir.path().join("broken.png");
+        fs::write(&broken, b"not an image").unwrap();
+
+        let config = AppConfig::default();
+        let pipe = TaggingPipeline::from_pretrained(&config.model.repo_id(), Device::cpu(), None)
+            .await
+            .unwrap();
+        let pipe = Arc::new(Mutex::new(pipe));
+        let rating_model = Arc::new(Mutex::new(RatingModel::new().await.unwrap()));
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        db.init().unwrap();
+        let db = Arc::new(Mutex::new(db));
+
+        let mut report = ProcessReport::default();
+        process_images(
+            &[temp_dir.path().to_path_buf()],
+            &pipe,
+            &rating_model,
+            &db,
+            &NullProgress,
+            &config,
+            &mut report,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(db.lock().unwrap().image_count().unwrap(), 2);
+        assert_eq!(
+            report.outcomes_for(&broken),
+            vec![FileOutcome::SkippedCorrupt]
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_already_tagged_image_follows_rename() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("media");
+        fs::create_dir(&dir).unwrap();
+        let image = dir.join("a.png");
+        RgbImage::from_pixel(64, 64, Rgb([0, 0, 200]))
+            .save(&image)
+            .unwrap();
+
+        let config = AppConfig::default();
+        let pipe = TaggingPipeline::from_pretrained(&config.model.repo_id(), Device::cpu(), None)
+            .await
+            .unwrap();
+        let pipe = Arc::new(Mutex::new(pipe));
+        let rating_model = Arc::new(Mutex::new(RatingModel::new().await.unwrap()));
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        db.init().unwrap();
+        let db = Arc::new(Mutex::new(db));
+
+        let renamed = dir.join("1.png");
+        for run in 0..2 {
+            if run == 1 {
+                // Renamed between runs, as the rename pass does with new files.
+                fs::rename(&image, &renamed).unwrap();
+            }
+            let mut report = ProcessReport::default();
+            process_images(
+                &[dir.clone()],
+                &pipe,
+                &rating_model,
+                &db,
+                &NullProgress,
+                &config,
+                &mut report,
+            )
+            .await
+            .unwrap();
+        }
+
+        // Skipped as already tagged, but its row now has the new name.
+        let db = db.lock().unwrap();
+        assert_eq!(db.image_count().unwrap(), 1);
+        let stored_path = file::storage_path(&renamed, None).unwrap();
+        assert!(db.get_by_filename(&stored_path).unwrap().is_some());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_full_run_skips_corrupt_image() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("media");
+        fs::create_dir(&dir).unwrap();
+        RgbImage::from_pixel(64, 64, Rgb([0, 0, 200]))
+            .save(dir.join("a.png"))
+            .unwrap();
+        fs::write(dir.join("b.jpg"), b"not an image").unwrap();
+
+        let db_path = temp_dir.path().join("test.db");
+        let config = AppConfig {
+            db_path: Some(db_path.clone()),
+            ..Default::default()
+        };
+        let report = run_full_process(config, vec![dir.clone()], NullProgress)
+            .await
+            .unwrap();
+
+        // Renamed along with the rest, but neither converted, resized, nor optimized.
+        let broken = dir.join("2.jpg");
+        assert_eq!(fs::read(&broken).unwrap(), b"not an image");
+        assert!(report
+            .outcomes_for(&broken)
+            .contains(&FileOutcome::SkippedCorrupt));
+        assert_eq!(Database::new(db_path).unwrap().image_count().unwrap(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_full_run_keeps_xmp() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("media");
+        fs::create_dir(&dir).unwrap();
+        RgbImage::from_fn(64, 64, |x, y| Rgb([x as u8 * 4, y as u8 * 4, 0]))
+            .save(dir.join("a.jpg"))
+            .unwrap();
+
+        let db_path = temp_dir.path().join("test.db");
+        let config = AppConfig {
+            db_path: Some(db_path.clone()),
+            write_xmp: true,
+            ..Default::default()
+        };
+        run_full_process(config, vec![dir.clone()], NullProgress)
+            .await
+            .unwrap();
+
+        // The optimizer ran before the XMP was written, so it is still there, and the
+        // stored hash is that of the file on disk.
+        let tagged = dir.join("1.png");
+        let xmp_tags = eros::xmp::read_tags_from_xmp(&tagged).unwrap();
+        assert!(!xmp_tags.is_empty());
+        let db = Database::new(db_path).unwrap();
+        let stored = db
+            .get_by_filename(&file::storage_path(&tagged, None).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.hash, get_hash(&tagged).unwrap());
+        assert_eq!(stored.tags, xmp_tags);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_quarantined_duplicates_are_left_alone() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("media");
+        let quarantine = dir.join("quarantine");
+        fs::create_dir(&dir).unwrap();
+        let gradient = RgbImage::from_fn(64, 64, |x, y| Rgb([x as u8 * 4, y as u8 * 4, 0]));
+        gradient.save(dir.join("a.png")).unwrap();
+        gradient.save(dir.join("b.png")).unwrap();
+        let duplicate_bytes = fs::read(dir.join("b.png")).unwrap();
+
+        let db_path = temp_dir.path().join("test.db");
+        let config = AppConfig {
+            dedup_max_distance: 0,
+            duplicate_action: DuplicateAction::MoveTo(quarantine.clone()),
+            db_path: Some(db_path.clone()),
+            ..Default::default()
+        };
+        run_full_process(config, vec![dir.clone()], NullProgress)
+            .await
+            .unwrap();
+
+        // The moved duplicate is not renamed, converted, resized, or optimized...
+        let moved = quarantine.join("b.png");
+        assert_eq!(fs::read(&moved).unwrap(), duplicate_bytes);
+        assert_eq!(fs::read_dir(&quarantine).unwrap().count(), 1);
+
+        // ...nor tagged; only the kept image is in the database.
+        let db = Database::new(db_path).unwrap();
+        assert_eq!(db.image_count().unwrap(), 1);
+        assert!(!db.contains_hash(&get_hash(&moved).unwrap()).unwrap());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_kept_originals_are_tagged_once() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("media");
+        fs::create_dir(&dir).unwrap();
+        RgbImage::from_fn(64, 64, |x, y| Rgb([x as u8 * 4, y as u8 * 4, 0]))
+            .save(dir.join("a.jpg"))
+            .unwrap();
+        RgbImage::from_pixel(64, 64, Rgb([0, 0, 200]))
+            .save(dir.join("b.jpg"))
+            .unwrap();
+
+        let db_path = temp_dir.path().join("test.db");
+        let config = AppConfig {
+            db_path: Some(db_path.clone()),
+            ..Default::default()
+        };
+        // A second run finds the converted copies again, but neither deletes them as
+        // duplicates of their originals nor tags the originals.
+        for _ in 0..2 {
+            run_full_process(config.clone(), vec![dir.clone()], NullProgress)
+                .await
+                .unwrap();
+
+            let db = Database::new(&db_path).unwrap();
+            assert_eq!(db.image_count().unwrap(), 2);
+            for name in ["1", "2"] {
+                assert!(dir.join(name).with_extension("png").exists());
+                assert!(!dir.join(name).with_extension("jpg").exists());
+                let original = dir.join(prelude::ORIGINALS_DIR).join(name);
+                assert!(original.with_extension("jpg").exists());
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_dry_run_changes_nothing() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let gradient = RgbImage::from_fn(64, 64, |x, y| Rgb([x as u8 * 4, y as u8 * 4, 0]));
+        gradient.save(dir.join("a.png")).unwrap();
+        gradient.save(dir.join("b.png")).unwrap();
+        RgbImage::from_pixel(64, 64, Rgb([0, 0, 200]))
+            .save(dir.join("c.jpg"))
+            .unwrap();
+        let snapshot = || -> Vec<(PathBuf, Vec<u8>)> {
+            let mut files: Vec<_> = fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .map(|path| (path.clone(), fs::read(&path).unwrap()))
+                .collect();
+            files.sort();
+            files
+        };
+        let before = snapshot();
+
+        let config = AppConfig {
+            dedup_max_distance: 0,
+            db_path: Some(dir.join("test.db")),
+            dry_run: true,
+            ..Default::default()
+        };
+        let (sink, mut rx) = ChannelProgress::channel(PROGRESS_CHANNEL_CAPACITY);
+        run_full_process(config, vec![dir.to_path_buf()], sink)
+            .await
+            .unwrap();
+
+        assert_eq!(snapshot(), before);
+        let mut messages = Vec::new();
+        while let Some(update) = rx.recv().await {
+            if let crate::app::ProgressUpdate::Message(message) = update {
+                messages.push(message);
+            }
+        }
+        for action in ["remove", "rename", "convert", "optimize"] {
+            let action = format!("Would {}", action);
+            assert!(
+                messages.iter().any(|message| message.starts_with(&action)),
+                "no {:?} message in {:?}",
+                action,
+                messages
+            );
+        }
+    }
 }
\ No newline at end of file
@@ -1,14 +1,95 @@
-use anyhow::Result;
-use rusqlite::{params, Connection};
-use std::path::Path;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use crate::file::DEFAULT_TAG_SEPARATOR;
+
+/// How long a connection waits for a lock held by another connection before failing.
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Database {
     conn: Connection,
 }
 
+/// Controls how stored tags are rendered as caption text on export.
+#[derive(Debug, Clone)]
+pub struct CaptionFormat {
+    /// The separator placed between tags.
+    pub separator: String,
+    /// Whether to put the rating in front of the tags.
+    pub include_rating: bool,
+}
+
+impl Default for CaptionFormat {
+    fn default() -> Self {
+        Self {
+            separator: DEFAULT_TAG_SEPARATOR.to_string(),
+            include_rating: false,
+        }
+    }
+}
+
+/// A tagged image as stored in the database.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StoredResult {
+    pub filename: String,
+    pub size: u64,
+    pub hash: String,
+    pub tags: Vec<String>,
+    pub rating: Option<String>,
+}
+
+impl StoredResult {
+    /// The columns `from_row` expects, in order.
+    const COLUMNS: &'static str = "filename, size, hash, tags, rating";
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        let tags: String = row.get(3)?;
+        Ok(Self {
+            filename: row.get(0)?,
+            size: row.get(1)?,
+            hash: row.get(2)?,
+            tags: tags
+                .split(DEFAULT_TAG_SEPARATOR)
+                .filter(|t| !t.is_empty())
+                .map(str::to_string)
+                .collect(),
+            rating: row.get(4)?,
+        })
+    }
+}
+
+/// A line of a Hugging Face `imagefolder` `metadata.jsonl` file.
+#[derive(Serialize)]
+struct MetadataLine<'a> {
+    file_name: &'a str,
+    text: String,
+}
+
 impl Database {
+    /// Opens the database at `path` with `DEFAULT_BUSY_TIMEOUT`.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let conn = Connection::open(path)?;
+        Self::with_options(path, DEFAULT_BUSY_TIMEOUT)
+    }
+
+    /// Opens the database at `path` in WAL mode, waiting up to `busy_timeout` for locks
+    /// held by other connections, so several processes can use it at once.
+    pub fn with_options<P: AsRef<Path>>(path: P, busy_timeout: Duration) -> Result<Self> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open database {:?}", path))?;
+        conn.busy_timeout(busy_timeout)?;
+        // WAL lets readers run alongside a writer, and survives crashes with the
+        // cheaper NORMAL sync. In-memory databases keep their own journal mode.
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
         Ok(Self { conn })
     }
 
@@ -35,23 +116,86 @@ impl Database {
             )",
             [],
         )?;
+        // Image tags are also stored one per row, so searching for a tag is an exact
+        // lookup; the comma-joined `images.tags` column is kept for compatibility.
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS tags (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE
+            )",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS image_tags (
+                image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+                tag_id INTEGER NOT NULL REFERENCES tags(id),
+                PRIMARY KEY (image_id, tag_id)
+            )",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS image_tags_tag_id ON image_tags (tag_id)",
+            [],
+        )?;
+        self.backfill_image_tags()
+    }
+
+    /// Fills `image_tags` for images saved before it existed.
+    fn backfill_image_tags(&self) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let rows: Vec<(i64, String)> = {
+            let mut stmt = tx.prepare(
+                "SELECT id, tags FROM images
+                 WHERE id NOT IN (SELECT image_id FROM image_tags)",
+            )?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        for (image_id, tags) in rows {
+            self.link_image_tags(image_id, &tags)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Adds a row to `image_tags` for each tag in the comma-joined `tags`.
+    fn link_image_tags(&self, image_id: i64, tags: &str) -> Result<()> {
+        let mut insert_tag = self
+            .conn
+            .prepare_cached("INSERT OR IGNORE INTO tags (name) VALUES (?1)")?;
+        let mut insert_link = self.conn.prepare_cached(
+            "INSERT OR IGNORE INTO image_tags (image_id, tag_id)
+             SELECT ?1, id FROM tags WHERE name = ?2",
+        )?;
+        for tag in tags.split(DEFAULT_TAG_SEPARATOR).filter(|t| !t.is_empty()) {
+            insert_tag.execute(params![tag])?;
+            insert_link.execute(params![image_id, tag])?;
+        }
         Ok(())
     }
 
     pub fn save_image_tags(
-    &self,
-    filename: &str,
-    size: u64,
-    hash: &str,
-    tags: &str,
-    rating: &str,
-) -> Result<()> {
-    self.conn.execute(
-        "INSERT OR REPLACE INTO images (filename, size, hash, tags, rating) VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![filename, size, hash, tags, rating],
-    )?;
-    Ok(())
-}
+        &self,
+        filename: &str,
+        size: u64,
+        hash: &str,
+        tags: &str,
+        rating: &str,
+    ) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        // Replacing an image gives it a new id, so the links to the old one go first.
+        tx.execute(
+            "DELETE FROM image_tags WHERE image_id IN (SELECT id FROM images WHERE hash = ?1)",
+            params![hash],
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO images (filename, size, hash, tags, rating) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![filename, size, hash, tags, rating],
+        )?;
+        self.link_image_tags(tx.last_insert_rowid(), tags)?;
+        tx.commit()?;
+        Ok(())
+    }
 
     pub fn save_video_tags(
         &self,
@@ -92,4 +236,344 @@ impl Database {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Returns whether an image with the SHA256 `hash` has already been tagged.
+    pub fn contains_hash(&self, hash: &str) -> Result<bool> {
+        let exists: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM images WHERE hash = ?1)",
+            params![hash],
+            |row| row.get(0),
+        )?;
+        Ok(exists)
+    }
+
+    /// Points the image with the SHA256 `hash` at `filename`, for a tagged file that
+    /// has been renamed or moved since. Returns whether its filename changed.
+    pub fn update_image_filename(&self, hash: &str, filename: &str) -> Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE images SET filename = ?1 WHERE hash = ?2 AND filename != ?1",
+            params![filename, hash],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Returns the image stored under `filename`, if it has been tagged.
+    pub fn get_by_filename(&self, filename: &str) -> Result<Option<StoredResult>> {
+        let sql = format!(
+            "SELECT {} FROM images WHERE filename = ?1 ORDER BY id DESC LIMIT 1",
+            StoredResult::COLUMNS
+        );
+        let result = self
+            .conn
+            .query_row(&sql, params![filename], StoredResult::from_row)
+            .optional()?;
+        Ok(result)
+    }
+
+    /// Returns every image with the given rating, in the order they were tagged.
+    pub fn query_by_rating(&self, rating: &str) -> Result<Vec<StoredResult>> {
+        self.query_images("rating = ?1", params![rating])
+    }
+
+    /// Returns every image tagged with `tag`, in the order they were tagged.
+    ///
+    /// Only whole tags match, so `hair` does not match `long hair`.
+    pub fn query_by_tag(&self, tag: &str) -> Result<Vec<StoredResult>> {
+        self.images_with_tag(tag)
+    }
+
+    /// Returns every image tagged with exactly `tag`, in the order they were tagged,
+    /// using the `image_tags` index instead of scanning the tag lists.
+    pub fn images_with_tag(&self, tag: &str) -> Result<Vec<StoredResult>> {
+        self.query_images(
+            "id IN (SELECT image_tags.image_id FROM image_tags
+                    JOIN tags ON tags.id = image_tags.tag_id
+                    WHERE tags.name = ?1)",
+            params![tag],
+        )
+    }
+
+    /// Returns the images matching the SQL `condition`, in the order they were tagged.
+    fn query_images<P: rusqlite::Params>(
+        &self,
+        condition: &str,
+        params: P,
+    ) -> Result<Vec<StoredResult>> {
+        let sql = format!(
+            "SELECT {} FROM images WHERE {} ORDER BY id",
+            StoredResult::COLUMNS,
+            condition
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params, StoredResult::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Returns the number of tagged images.
+    pub fn image_count(&self) -> Result<usize> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM images", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    /// Counts how often each tag occurs across all images and videos, most common first.
+    ///
+    /// Tags with the same count are ordered alphabetically.
+    pub fn tag_frequencies(&self) -> Result<Vec<(String, usize)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT tags FROM images UNION ALL SELECT tags FROM videos")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for tags in rows {
+            for tag in tags?.split(DEFAULT_TAG_SEPARATOR).filter(|t| !t.is_empty()) {
+                *counts.entry(tag.to_string()).or_default() += 1;
+            }
+        }
+
+        let mut frequencies: Vec<(String, usize)> = counts.into_iter().collect();
+        frequencies.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(frequencies)
+    }
+
+    /// Writes one `{"file_name", "text"}` JSON line per image, the metadata format
+    /// Hugging Face `datasets` loads for image folders.
+    ///
+    /// File names are made relative to the directory containing `path` when they lie
+    /// under it, and are written as stored otherwise.
+    pub fn export_jsonl(&self, path: &Path, caption_format: &CaptionFormat) -> Result<()> {
+        let root = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve export directory for {:?}", path))?;
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT filename, tags, rating FROM images ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?;
+
+        let file = File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+        for row in rows {
+            let (filename, tags, rating) = row?;
+            let file_name = Path::new(&filename)
+                .strip_prefix(&root)
+                .ok()
+                .and_then(|p| p.to_str())
+                .unwrap_or(&filename);
+
+            let mut caption: Vec<&str> = Vec::new();
+            if caption_format.include_rating {
+                caption.extend(rating.as_deref());
+            }
+            caption.extend(tags.split(DEFAULT_TAG_SEPARATOR).filter(|t| !t.is_empty()));
+
+            let line = MetadataLine {
+                file_name,
+                text: caption.join(&caption_format.separator),
+            };
+            serde_json::to_writer(&mut writer, &line)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export_jsonl() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let db = Database::new(":memory:").unwrap();
+        db.init().unwrap();
+        db.save_image_tags(
+            root.join("images/a.png").to_str().unwrap(),
+            10,
+            "hash_a",
+            "1girl, long hair",
+            "sfw",
+        )
+        .unwrap();
+        db.save_image_tags("b.png", 20, "hash_b", "cat", "nsfw").unwrap();
+
+        let metadata_path = root.join("metadata.jsonl");
+        let format = CaptionFormat {
+            separator: " ".to_string(),
+            include_rating: true,
+        };
+        db.export_jsonl(&metadata_path, &format).unwrap();
+
+        let content = std::fs::read_to_string(metadata_path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["file_name"], "images/a.png");
+        assert_eq!(lines[0]["text"], "sfw 1girl long hair");
+        assert_eq!(lines[1]["file_name"], "b.png");
+        assert_eq!(lines[1]["text"], "nsfw cat");
+    }
+
+    #[test]
+    fn test_contains_hash() {
+        let db = Database::new(":memory:").unwrap();
+        db.init().unwrap();
+        db.save_image_tags("a.png", 1, "hash_a", "1girl", "sfw")
+            .unwrap();
+        db.save_video_tags("b.mp4", 1, "hash_b", "cat", "sfw")
+            .unwrap();
+
+        assert!(db.contains_hash("hash_a").unwrap());
+        assert!(!db.contains_hash("hash_b").unwrap());
+        assert!(!db.contains_hash("hash_c").unwrap());
+    }
+
+    #[test]
+    fn test_update_image_filename() {
+        let db = Database::new(":memory:").unwrap();
+        db.init().unwrap();
+        db.save_image_tags("a.png", 1, "hash_a", "1girl", "sfw")
+            .unwrap();
+
+        assert!(db.update_image_filename("hash_a", "1.png").unwrap());
+        assert!(!db.update_image_filename("hash_a", "1.png").unwrap());
+        assert!(!db.update_image_filename("hash_b", "2.png").unwrap());
+        assert!(db.get_by_filename("a.png").unwrap().is_none());
+        let stored = db.get_by_filename("1.png").unwrap().unwrap();
+        assert_eq!(stored.tags, vec!["1girl"]);
+        assert_eq!(db.image_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_query_api() {
+        let db = Database::new(":memory:").unwrap();
+        db.init().unwrap();
+        db.save_image_tags("a.png", 10, "a", "1girl, long hair, ^_^", "sfw")
+            .unwrap();
+        db.save_image_tags("b.png", 20, "b", "hair, cat", "nsfw")
+            .unwrap();
+        db.save_image_tags("c.png", 30, "c", "1girl", "sfw")
+            .unwrap();
+
+        let a = db.get_by_filename("a.png").unwrap().unwrap();
+        assert_eq!(a.size, 10);
+        assert_eq!(a.hash, "a");
+        assert_eq!(a.tags, vec!["1girl", "long hair", "^_^"]);
+        assert_eq!(a.rating.as_deref(), Some("sfw"));
+        assert!(db.get_by_filename("missing.png").unwrap().is_none());
+
+        let filenames = |results: Vec<StoredResult>| -> Vec<String> {
+            results.into_iter().map(|r| r.filename).collect()
+        };
+        assert_eq!(
+            filenames(db.query_by_rating("sfw").unwrap()),
+            vec!["a.png", "c.png"]
+        );
+        assert_eq!(
+            filenames(db.query_by_tag("1girl").unwrap()),
+            vec!["a.png", "c.png"]
+        );
+        // Whole tags only, and `_` is not a wildcard.
+        assert_eq!(filenames(db.query_by_tag("hair").unwrap()), vec!["b.png"]);
+        assert_eq!(filenames(db.query_by_tag("^_^").unwrap()), vec!["a.png"]);
+        assert!(db.query_by_tag("^x^").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_images_with_tag() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db");
+
+        // A database from before `image_tags` existed.
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE images (
+                id INTEGER PRIMARY KEY,
+                filename TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                hash TEXT NOT NULL UNIQUE,
+                tags TEXT NOT NULL,
+                rating TEXT
+            );
+            INSERT INTO images (filename, size, hash, tags, rating)
+                VALUES ('old.png', 1, 'old', 'cathedral, sky', 'sfw');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let db = Database::new(&path).unwrap();
+        db.init().unwrap();
+        db.save_image_tags("a.png", 1, "a", "cat, sky", "sfw")
+            .unwrap();
+        db.save_image_tags("b.png", 1, "b", "cat", "sfw").unwrap();
+        // Re-tagging an image replaces its tags.
+        db.save_image_tags("b.png", 1, "b", "dog", "sfw").unwrap();
+
+        let filenames = |tag: &str| -> Vec<String> {
+            let results = db.images_with_tag(tag).unwrap();
+            results.into_iter().map(|r| r.filename).collect()
+        };
+        assert_eq!(filenames("cat"), vec!["a.png"]);
+        assert_eq!(filenames("sky"), vec!["old.png", "a.png"]);
+        assert_eq!(filenames("cathedral"), vec!["old.png"]);
+        assert_eq!(filenames("dog"), vec!["b.png"]);
+
+        // Running the migration again adds nothing.
+        db.init().unwrap();
+        assert_eq!(filenames("sky"), vec!["old.png", "a.png"]);
+    }
+
+    #[test]
+    fn test_with_options_enables_wal() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db");
+        let db = Database::with_options(&path, Duration::from_millis(100)).unwrap();
+        db.init().unwrap();
+
+        let journal_mode: String = db
+            .conn
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        // A second connection can read while the first one is open.
+        let other = Database::new(&path).unwrap();
+        db.save_image_tags("a.png", 1, "a", "cat", "sfw").unwrap();
+        assert!(other.contains_hash("a").unwrap());
+    }
+
+    #[test]
+    fn test_tag_frequencies() {
+        let db = Database::new(":memory:").unwrap();
+        db.init().unwrap();
+        db.save_image_tags("a.png", 1, "a", "1girl, long hair, smile", "sfw").unwrap();
+        db.save_image_tags("b.png", 1, "b", "1girl, smile", "sfw").unwrap();
+        db.save_image_tags("c.png", 1, "c", "", "sfw").unwrap();
+        db.save_video_tags("d.mp4", 1, "d", "1girl, cat", "sfw").unwrap();
+
+        assert_eq!(
+            db.tag_frequencies().unwrap(),
+            vec![
+                ("1girl".to_string(), 3),
+                ("smile".to_string(), 2),
+                ("cat".to_string(), 1),
+                ("long hair".to_string(), 1),
+            ]
+        );
+    }
+}
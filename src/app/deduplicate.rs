@@ -0,0 +1,311 @@
+//! # Duplicate Removal
+//!
+//! This module removes near-duplicate images found by `eros::dedup` before tagging,
+//! so the same picture is not tagged and stored twice.
+
+use anyhow::{Context, Result};
+use eros::{
+    dedup::{group_duplicates, HashAlgorithm},
+    prelude::skip_excluded,
+};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+use crate::{
+    file::is_image,
+    progress::ProgressSink,
+    report::{FileOutcome, ProcessReport},
+};
+
+/// The aggregated outcome of a duplicate removal pass.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupSummary {
+    /// The number of duplicate files removed.
+    pub removed: usize,
+    /// The total size in bytes of the removed files.
+    pub reclaimed_bytes: u64,
+    /// Whether the duplicates were only reported (`DuplicateAction::Report`), so the
+    /// counts are what would have been removed.
+    pub dry_run: bool,
+}
+
+impl fmt::Display for DedupSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let megabytes = self.reclaimed_bytes as f64 / (1024.0 * 1024.0);
+        if self.dry_run {
+            write!(
+                f,
+                "Would remove {} duplicates ({:.1}MB)",
+                self.removed, megabytes
+            )
+        } else {
+            write!(
+                f,
+                "Removed {} duplicates, freed {:.1}MB",
+                self.removed, megabytes
+            )
+        }
+    }
+}
+
+/// What `remove_duplicate_images` does with each duplicate it finds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DuplicateAction {
+    /// Delete the duplicate.
+    #[default]
+    Delete,
+    /// Move the duplicate into this directory, at its path relative to the selected
+    /// directory it was found in, so it can be reviewed before being deleted. The rest
+    /// of the run skips this directory (see `AppConfig::excluded_dirs`).
+    MoveTo(PathBuf),
+    /// Only report the duplicate to the progress sink.
+    Report,
+}
+
+/// Removes images whose `algorithm` fingerprint is within `max_distance` bits of an
+/// image seen earlier.
+///
+/// Files are visited in sorted order, so the first file of each group is kept, and the
+/// others are handled according to `action`. A summary of the pass is reported once to
+/// `sink` at the end; with `DuplicateAction::Report`, it counts what would be removed.
+///
+/// The directories in `exclude` are skipped with everything under them, as is the
+/// directory of `DuplicateAction::MoveTo`.
+pub fn remove_duplicate_images(
+    selected_dirs: &[PathBuf],
+    max_distance: u32,
+    algorithm: HashAlgorithm,
+    action: &DuplicateAction,
+    exclude: &[PathBuf],
+    sink: &impl ProgressSink,
+    report: &mut ProcessReport,
+) -> Result<DedupSummary> {
+    // Files already moved aside by an earlier pass are not duplicates to handle again.
+    let mut exclude = exclude.to_vec();
+    if let DuplicateAction::MoveTo(dir) = action {
+        exclude.push(dir.clone());
+    }
+    let mut image_files: Vec<PathBuf> = selected_dirs
+        .iter()
+        .flat_map(|dir| {
+            WalkDir::new(dir)
+                .into_iter()
+                .filter_entry(skip_excluded(dir, &exclude))
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.into_path())
+        })
+        .filter(|path| path.to_str().is_some_and(|p| is_image(p).unwrap_or(false)))
+        .collect();
+    image_files.sort();
+
+    let mut summary = DedupSummary {
+        dry_run: *action == DuplicateAction::Report,
+        ..Default::default()
+    };
+
+    // Unreadable images are left for the tagging step to report.
+    for group in group_duplicates(&image_files, max_distance, algorithm) {
+        let mut group = group.into_iter();
+        let Some(kept) = group.next() else {
+            continue;
+        };
+        for path in group {
+            summary.reclaimed_bytes += fs::metadata(&path)?.len();
+            summary.removed += 1;
+            match action {
+                DuplicateAction::Delete => {
+                    fs::remove_file(&path)?;
+                    report.record(path, FileOutcome::SkippedDuplicate);
+                }
+                DuplicateAction::MoveTo(dir) => {
+                    let destination = quarantine_path(&path, selected_dirs, dir);
+                    move_file(&path, &destination)?;
+                    let detail = format!("moved to {:?}", destination);
+                    report.record_with_detail(path, FileOutcome::SkippedDuplicate, detail);
+                }
+                DuplicateAction::Report => {
+                    sink.message(&format!(
+                        "Would remove {:?}, a duplicate of {:?}",
+                        path, kept
+                    ));
+                }
+            }
+        }
+    }
+
+    sink.duplicates_removed(summary);
+    Ok(summary)
+}
+
+/// Returns where `path` goes in the quarantine directory `dir`: its path relative to the
+/// selected directory it was found in.
+fn quarantine_path(path: &Path, selected_dirs: &[PathBuf], dir: &Path) -> PathBuf {
+    let relative = selected_dirs
+        .iter()
+        .find_map(|selected| path.strip_prefix(selected).ok())
+        .or_else(|| path.file_name().map(Path::new))
+        .unwrap_or(path);
+    dir.join(relative)
+}
+
+/// Moves `from` to `to`, creating its parent directories. Never overwrites a file
+/// already at `to`, which would lose a duplicate moved there earlier.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    anyhow::ensure!(!to.exists(), "Cannot move {:?} over {:?}", from, to);
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to).with_context(|| format!("Failed to move {:?} to {:?}", from, to))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        app::ProgressUpdate,
+        progress::{ChannelProgress, PROGRESS_CHANNEL_CAPACITY},
+    };
+    use image::{Rgb, RgbImage};
+
+    fn gradient() -> RgbImage {
+        RgbImage::from_fn(64, 64, |x, y| Rgb([(x * 4) as u8, (y * 4) as u8, 0]))
+    }
+
+    fn checkerboard() -> RgbImage {
+        RgbImage::from_fn(64, 64, |x, y| {
+            if (x / 8 + y / 8) % 2 == 0 {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        })
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_remove_duplicate_images_summary() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let original = temp_dir.path().join("a.png");
+        let duplicate = temp_dir.path().join("b.png");
+        let second_duplicate = temp_dir.path().join("c.png");
+        let unique = temp_dir.path().join("d.png");
+        gradient().save(&original).unwrap();
+        gradient().save(&duplicate).unwrap();
+        gradient().save(&second_duplicate).unwrap();
+        checkerboard().save(&unique).unwrap();
+
+        let expected_bytes = fs::metadata(&duplicate).unwrap().len()
+            + fs::metadata(&second_duplicate).unwrap().len();
+
+        let (sink, mut rx) = ChannelProgress::channel(PROGRESS_CHANNEL_CAPACITY);
+        let mut report = ProcessReport::default();
+        let summary = remove_duplicate_images(
+            &[temp_dir.path().to_path_buf()],
+            0,
+            HashAlgorithm::Average,
+            &DuplicateAction::Delete,
+            &[],
+            &sink,
+            &mut report,
+        )
+        .unwrap();
+
+        assert_eq!(summary.removed, 2);
+        assert_eq!(summary.reclaimed_bytes, expected_bytes);
+        assert!(original.exists());
+        assert!(unique.exists());
+        assert!(!duplicate.exists());
+        assert!(!second_duplicate.exists());
+
+        match rx.recv().await {
+            Some(ProgressUpdate::DuplicatesRemoved(reported)) => assert_eq!(reported, summary),
+            other => panic!("Unexpected update: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_move_duplicates_preserves_relative_paths() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("media");
+        let quarantine = dir.join("quarantine");
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        let original = dir.join("a.png");
+        let duplicate = dir.join("nested").join("b.png");
+        gradient().save(&original).unwrap();
+        gradient().save(&duplicate).unwrap();
+        checkerboard().save(dir.join("c.png")).unwrap();
+
+        let action = DuplicateAction::MoveTo(quarantine.clone());
+        let mut report = ProcessReport::default();
+        let summary = remove_duplicate_images(
+            &[dir.clone()],
+            0,
+            HashAlgorithm::Average,
+            &action,
+            &[],
+            &crate::progress::NullProgress,
+            &mut report,
+        )
+        .unwrap();
+
+        let moved = quarantine.join("nested").join("b.png");
+        assert_eq!(summary.removed, 1);
+        assert!(original.exists());
+        assert!(!duplicate.exists());
+        assert!(moved.exists());
+        assert_eq!(
+            report.outcomes_for(&duplicate),
+            vec![FileOutcome::SkippedDuplicate]
+        );
+
+        // The quarantined copy is not treated as a duplicate of the original again.
+        let summary = remove_duplicate_images(
+            &[dir],
+            0,
+            HashAlgorithm::Average,
+            &action,
+            &[],
+            &crate::progress::NullProgress,
+            &mut report,
+        )
+        .unwrap();
+        assert_eq!(summary.removed, 0);
+        assert!(moved.exists());
+    }
+
+    #[test]
+    fn test_report_skips_excluded_dirs_and_says_would_remove() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("media");
+        let quarantine = dir.join("quarantine");
+        fs::create_dir_all(&quarantine).unwrap();
+        gradient().save(dir.join("a.png")).unwrap();
+        gradient().save(dir.join("b.png")).unwrap();
+        gradient().save(quarantine.join("b.png")).unwrap();
+
+        let mut report = ProcessReport::default();
+        let summary = remove_duplicate_images(
+            &[dir.clone()],
+            0,
+            HashAlgorithm::Average,
+            &DuplicateAction::Report,
+            &[quarantine.clone()],
+            &crate::progress::NullProgress,
+            &mut report,
+        )
+        .unwrap();
+
+        // Only `b.png` counts; the copy already in the quarantine is not scanned.
+        assert_eq!(summary.removed, 1);
+        assert!(dir.join("b.png").exists());
+        let message = summary.to_string();
+        assert!(
+            message.starts_with("Would remove 1 duplicates ("),
+            "{}",
+            message
+        );
+    }
+}
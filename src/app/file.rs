@@ -1,11 +1,14 @@
-use anyhow::Result;
-use eros::pipeline::TaggingResult;
-use futures::stream::{self, StreamExt};
+use anyhow::{Context, Result};
+use eros::{pipeline::TaggingResult, prelude::skip_excluded, tags::format::fix_tag_underscore};
 use serde::Serialize;
-use std::path::{PathBuf};
-use tokio::fs;
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use walkdir::WalkDir;
 
-use crate::tag::fix_tag_underscore;
+/// The separator used when flattening tags for the database.
+pub const DEFAULT_TAG_SEPARATOR: &str = ", ";
 
 /// Supported image extensions.
 pub const IMAGE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
@@ -21,31 +24,128 @@ pub fn is_image(path: &str) -> Result<bool> {
     }
 }
 
-/// Get image files from a directory.
+/// Get image files from a directory and all of its subdirectories.
+///
+/// Before subdirectories were scanned, only the images directly in `dir` were
+/// returned; use `get_image_files_with_depth` with a depth of `1` for that.
 pub async fn get_image_files(dir: &str) -> Result<Vec<PathBuf>> {
-    let mut entries = fs::read_dir(dir).await?;
-    let mut tasks = vec![];
-
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-        let task = tokio::spawn(async move {
-            if is_image(path.to_str().unwrap()).unwrap_or(false) {
-                Some(path)
-            } else {
-                None
+    get_image_files_with_depth(dir, None, &[]).await
+}
+
+/// Like `get_image_files`, but descends at most `max_depth` levels below `dir`, where
+/// `1` means only the files directly in `dir`. `None` scans every level. Directories
+/// in `exclude` are skipped with everything under them.
+pub async fn get_image_files_with_depth(
+    dir: &str,
+    max_depth: Option<usize>,
+    exclude: &[PathBuf],
+) -> Result<Vec<PathBuf>> {
+    find_files(dir, max_depth, exclude, is_image).await
+}
+
+/// Walks `dir` up to `max_depth` levels deep, outside of the directories in `exclude`,
+/// and returns the files for which `is_match` holds, in sorted order.
+///
+/// Fails when `dir` itself can't be read; unreadable entries below it are skipped.
+pub async fn find_files(
+    dir: &str,
+    max_depth: Option<usize>,
+    exclude: &[PathBuf],
+    is_match: fn(&str) -> Result<bool>,
+) -> Result<Vec<PathBuf>> {
+    let dir = PathBuf::from(dir);
+    let exclude = exclude.to_vec();
+    tokio::task::spawn_blocking(move || {
+        let mut walker = WalkDir::new(&dir).sort_by_file_name();
+        if let Some(max_depth) = max_depth {
+            walker = walker.max_depth(max_depth);
+        }
+
+        let mut files = Vec::new();
+        for entry in walker
+            .into_iter()
+            .filter_entry(skip_excluded(&dir, &exclude))
+        {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) if e.depth() == 0 => {
+                    return Err(e).with_context(|| format!("Failed to read {:?}", dir))
+                }
+                Err(_) => continue,
+            };
+            let path = entry.path();
+            if entry.file_type().is_file()
+                && path.to_str().is_some_and(|p| is_match(p).unwrap_or(false))
+            {
+                files.push(entry.into_path());
             }
-        });
+        }
+        Ok(files)
+    })
+    .await?
+}
 
-        tasks.push(task);
+/// Removes images whose width or height is below `min_dimension`, such as thumbnails
+/// and icons, returning the remaining files and the skipped ones.
+///
+/// Only image headers are read. Files whose dimensions can't be read are kept so the
+/// tagging step can report them.
+pub fn filter_small_images(
+    files: Vec<PathBuf>,
+    min_dimension: u32,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    if min_dimension == 0 {
+        return (files, Vec::new());
     }
 
-    let files = stream::iter(tasks)
-        .buffer_unordered(16)
-        .filter_map(|result| async move { result.ok().flatten() })
+    files
+        .into_iter()
+        .partition(|path| match image::image_dimensions(path) {
+            Ok((width, height)) => width >= min_dimension && height >= min_dimension,
+            Err(_) => true,
+        })
+}
+
+/// Keeps only the files modified after `since`, for incremental runs over a growing
+/// archive. Files whose modification time can't be read are kept.
+pub fn filter_modified_since(files: Vec<PathBuf>, since: Option<SystemTime>) -> Vec<PathBuf> {
+    let Some(since) = since else {
+        return files;
+    };
+
+    files
+        .into_iter()
+        .filter(|path| {
+            std::fs::metadata(path)
+                .and_then(|m| m.modified())
+                .map(|modified| modified > since)
+                .unwrap_or(true)
+        })
         .collect()
-        .await;
+}
+
+/// Returns the canonical form of `path` used to identify a file in the database.
+///
+/// Paths are made absolute so the same file maps to one record regardless of the
+/// working directory. When `root` is given and contains the file, the path is
+/// stored relative to it instead, which keeps the database portable.
+pub fn storage_path(path: &Path, root: Option<&Path>) -> Result<String> {
+    let absolute = std::fs::canonicalize(path)
+        .with_context(|| format!("Failed to resolve path {:?}", path))?;
+
+    let stored = match root {
+        Some(root) => {
+            let root = std::fs::canonicalize(root)
+                .with_context(|| format!("Failed to resolve root {:?}", root))?;
+            absolute
+                .strip_prefix(&root)
+                .map(Path::to_path_buf)
+                .unwrap_or(absolute)
+        }
+        None => absolute,
+    };
 
-    Ok(files)
+    Ok(stored.to_string_lossy().into_owned())
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -65,9 +165,8 @@ impl From<TaggingResult> for TaggingResultSimpleTags {
     fn from(result: TaggingResult) -> Self {
         Self {
             rating: result
-                .rating
-                .first()
-                .map_or("".to_string(), |(k, _)| k.clone()),
+                .top_rating()
+                .map_or(String::new(), |(tag, _)| tag.to_string()),
             character: result
                 .character
                 .keys()
@@ -82,20 +181,185 @@ impl From<TaggingResult> for TaggingResultSimpleTags {
     }
 }
 
+impl TaggingResultSimpleTags {
+    /// Joins the character and general tags into a single caption string.
+    pub fn to_caption(&self, separator: &str) -> String {
+        self.character
+            .iter()
+            .chain(self.general.iter())
+            .map(String::as_str)
+            .collect::<Vec<&str>>()
+            .join(separator)
+    }
+}
+
+impl TaggingResultSimple {
+    /// Creates a simplified result whose flattened `tags` are joined with `separator`
+    /// (e.g. `", "` for the database or `"\n"` for caption files).
+    pub fn with_separator(result: TaggingResult, separator: &str) -> Self {
+        let tagger = TaggingResultSimpleTags::from(result);
+        Self {
+            tags: tagger.to_caption(separator),
+            tagger,
+        }
+    }
+}
+
 impl From<TaggingResult> for TaggingResultSimple {
     fn from(result: TaggingResult) -> Self {
-        let mut tags = result.character.keys().cloned().collect::<Vec<String>>();
-        tags.extend(result.general.keys().cloned().collect::<Vec<String>>());
+        Self::with_separator(result, DEFAULT_TAG_SEPARATOR)
+    }
+}
 
-        let tags = tags
-            .iter()
-            .map(|tag| fix_tag_underscore(tag))
-            .collect::<Vec<String>>()
-            .join(", ");
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eros::pipeline::Prediction;
 
-        Self {
-            tags,
-            tagger: TaggingResultSimpleTags::from(result),
+    fn sample_result() -> TaggingResult {
+        TaggingResult {
+            rating: Prediction::from([("general".to_string(), 0.9)]),
+            character: Prediction::from([("hatsune_miku".to_string(), 0.95)]),
+            general: Prediction::from([
+                ("1girl".to_string(), 0.99),
+                ("long_hair".to_string(), 0.8),
+            ]),
+            artist: Prediction::new(),
+            copyright: Prediction::new(),
+            meta: Prediction::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_image_files_large_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut expected = std::collections::HashSet::new();
+        for i in 0..5000 {
+            let ext = if i % 2 == 0 { "png" } else { "txt" };
+            let path = temp_dir.path().join(format!("{}.{}", i, ext));
+            std::fs::write(&path, b"").unwrap();
+            if ext == "png" {
+                expected.insert(path);
+            }
         }
+
+        let files = get_image_files(temp_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(files.len(), expected.len());
+        assert_eq!(files.into_iter().collect::<std::collections::HashSet<_>>(), expected);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_get_image_files_recurses_into_subdirectories() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("sub/deeper")).unwrap();
+        for name in ["top.png", "sub/img.png", "sub/deeper/img.jpg", "sub/x.txt"] {
+            std::fs::write(temp_dir.path().join(name), b"").unwrap();
+        }
+        let dir = temp_dir.path().to_str().unwrap();
+
+        let files = get_image_files(dir).await.unwrap();
+        assert_eq!(
+            files,
+            vec![
+                temp_dir.path().join("sub/deeper/img.jpg"),
+                temp_dir.path().join("sub/img.png"),
+                temp_dir.path().join("top.png"),
+            ]
+        );
+
+        let files = get_image_files_with_depth(dir, Some(2), &[]).await.unwrap();
+        assert_eq!(
+            files,
+            vec![
+                temp_dir.path().join("sub/img.png"),
+                temp_dir.path().join("top.png")
+            ]
+        );
+        let files = get_image_files_with_depth(dir, Some(1), &[]).await.unwrap();
+        assert_eq!(files, vec![temp_dir.path().join("top.png")]);
+
+        let missing = temp_dir.path().join("missing");
+        assert!(get_image_files(missing.to_str().unwrap()).await.is_err());
+    }
+
+    #[test]
+    fn test_filter_small_images() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut files = Vec::new();
+        for (name, size) in [
+            ("icon.png", 16),
+            ("thumb.png", 48),
+            ("photo.png", 128),
+            ("wide.png", 64),
+        ] {
+            let path = temp_dir.path().join(name);
+            image::RgbImage::new(size, 64).save(&path).unwrap();
+            files.push(path);
+        }
+
+        let (kept, skipped) = filter_small_images(files, 64);
+        assert_eq!(
+            skipped,
+            vec![temp_dir.path().join("icon.png"), temp_dir.path().join("thumb.png")]
+        );
+        assert_eq!(
+            kept,
+            vec![temp_dir.path().join("photo.png"), temp_dir.path().join("wide.png")]
+        );
+    }
+
+    #[test]
+    fn test_filter_modified_since() {
+        use std::time::Duration;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cutoff = SystemTime::now() - Duration::from_secs(3600);
+        let mut files = Vec::new();
+        for (name, age_secs) in [("old.png", 7200), ("recent.png", 60), ("older.png", 86400)] {
+            let path = temp_dir.path().join(name);
+            let file = std::fs::File::create(&path).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age_secs)).unwrap();
+            files.push(path);
+        }
+
+        assert_eq!(filter_modified_since(files.clone(), None), files);
+        assert_eq!(
+            filter_modified_since(files, Some(cutoff)),
+            vec![temp_dir.path().join("recent.png")]
+        );
+    }
+
+    #[test]
+    fn test_storage_path_relative_and_absolute_match() {
+        let temp_dir = tempfile::tempdir_in(".").unwrap();
+        let image_path = temp_dir.path().join("1.png");
+        std::fs::write(&image_path, b"").unwrap();
+
+        let dir_name = temp_dir.path().file_name().unwrap();
+        let relative = Path::new(".").join(dir_name).join("1.png");
+        let absolute = std::fs::canonicalize(&image_path).unwrap();
+
+        let from_relative = storage_path(&relative, None).unwrap();
+        let from_absolute = storage_path(&absolute, None).unwrap();
+        assert_eq!(from_relative, from_absolute);
+        assert!(Path::new(&from_relative).is_absolute());
+
+        let rooted = storage_path(&relative, Some(temp_dir.path())).unwrap();
+        assert_eq!(rooted, "1.png");
+    }
+
+    #[test]
+    fn test_default_separator() {
+        let simple = TaggingResultSimple::from(sample_result());
+        assert_eq!(simple.tags, "hatsune miku, 1girl, long hair");
+    }
+
+    #[test]
+    fn test_newline_separator() {
+        let simple = TaggingResultSimple::with_separator(sample_result(), "\n");
+        assert_eq!(simple.tags, "hatsune miku\n1girl\nlong hair");
+        assert_eq!(simple.tagger.to_caption(", "), "hatsune miku, 1girl, long hair");
+    }
+}
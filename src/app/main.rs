@@ -11,19 +11,29 @@ mod args;
 mod ascii;
 mod core;
 mod db;
+mod deduplicate;
 mod file;
-mod tag;
+mod progress;
+mod report;
 mod tui;
 mod ui;
 mod video;
 
 use anyhow::Result;
 use app::{App, ProgressUpdate};
-use args::{Args, Commands, V3Model};
+use args::{Args, Commands, PrintFormat};
 use clap::Parser;
+use db::{CaptionFormat, Database};
+use deduplicate::DuplicateAction;
 use ffmpeg_next as ffmpeg;
-use std::path::PathBuf;
-use tokio::sync::mpsc;
+use file::TaggingResultSimple;
+use progress::{ChannelProgress, PROGRESS_CHANNEL_CAPACITY};
+use std::{
+    path::PathBuf,
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio_util::sync::CancellationToken;
+use video::FrameExtractOptions;
 
 /// The main entry point for the `eros` application.
 ///
@@ -37,35 +47,109 @@ async fn main() -> Result<()> {
     let args = Args::parse();
 
     match args.command {
-        Some(Commands::Process { path, threshold }) => {
-            run_cli(path, threshold).await?;
+        Some(Commands::Process {
+            path,
+            threshold,
+            model,
+            output,
+            batch_size,
+            min_dimension,
+            delete_originals,
+            report,
+            write_xmp,
+            since,
+            force,
+            max_depth,
+            dry_run,
+            move_duplicates_to,
+            dedup_max_distance,
+            dedup_algorithm,
+        }) => {
+            let config = core::AppConfig {
+                model,
+                output,
+                input_path: path.clone(),
+                video_path: path,
+                threshold,
+                batch_size: batch_size as usize,
+                show_ascii_art: false,
+                dedup_max_distance,
+                dedup_algorithm: dedup_algorithm.into(),
+                duplicate_action: move_duplicates_to
+                    .map_or(DuplicateAction::Delete, DuplicateAction::MoveTo),
+                path_root: None,
+                min_image_dimension: min_dimension,
+                delete_originals,
+                report_path: report,
+                shutdown: CancellationToken::new(),
+                write_xmp,
+                modified_since: since.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                force,
+                frame_options: FrameExtractOptions::default(),
+                db_path: Some(args.db),
+                max_scan_depth: max_depth,
+                dry_run,
+            };
+            run_cli(config).await?;
+        }
+        Some(Commands::Tag {
+            file,
+            threshold,
+            model,
+            format,
+        }) => {
+            let result = core::tag_file(&file, model, threshold).await?;
+            match format {
+                PrintFormat::Plain => println!("{}", TaggingResultSimple::from(result).tags),
+                PrintFormat::Json => println!("{}", eros::export::to_json(&result)?),
+            }
+        }
+        Some(Commands::Export {
+            output,
+            separator,
+            include_rating,
+        }) => {
+            let db = Database::new(&args.db)?;
+            db.export_jsonl(
+                &output,
+                &CaptionFormat {
+                    separator,
+                    include_rating,
+                },
+            )?;
+        }
+        Some(Commands::Stats { top }) => {
+            let db = Database::new(&args.db)?;
+            println!("{} images tagged", db.image_count()?);
+            for (tag, count) in db.tag_frequencies()?.into_iter().take(top) {
+                println!("{:>8}  {}", count, tag);
+            }
         }
         None => {
-            run_tui().await?;
+            run_tui(args.db).await?;
         }
     }
 
     Ok(())
 }
 
-/// Runs the application in CLI mode.
-async fn run_cli(path: String, threshold: f32) -> Result<()> {
-    let (tx, mut rx) = mpsc::channel(100);
+/// Runs the application in CLI mode over `config.input_path`.
+async fn run_cli(config: core::AppConfig) -> Result<()> {
+    let (sink, mut rx) = ChannelProgress::channel(PROGRESS_CHANNEL_CAPACITY);
+    let selected_dirs = vec![PathBuf::from(&config.input_path)];
 
-    let config = core::AppConfig {
-        model: V3Model::SwinV2,
-        input_path: path.clone(),
-        video_path: path.clone(),
-        threshold,
-        batch_size: 1,
-        show_ascii_art: false,
-    };
-    let selected_dirs = vec![PathBuf::from(path)];
+    // Stop after the current file on Ctrl-C; everything tagged so far is already saved.
+    let shutdown = config.shutdown.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            shutdown.cancel();
+        }
+    });
 
     // Spawn the processing task
     tokio::spawn(async move {
-        if let Err(e) = core::run_full_process(config, selected_dirs, tx.clone()).await {
-            let _ = tx.send(ProgressUpdate::Error(e.to_string())).await;
+        if let Err(e) = core::run_full_process(config, selected_dirs, sink.clone()).await {
+            sink.error(&e.to_string());
         }
     });
 
@@ -76,6 +160,7 @@ async fn run_cli(path: String, threshold: f32) -> Result<()> {
             ProgressUpdate::Progress(p) => {
                 println!("Progress: {:.2}%", p * 100.0);
             }
+            ProgressUpdate::DuplicatesRemoved(summary) => println!("{}", summary),
             ProgressUpdate::Error(e) => {
                 eprintln!("Error: {}", e);
                 break;
@@ -92,12 +177,12 @@ async fn run_cli(path: String, threshold: f32) -> Result<()> {
 }
 
 /// Runs the application in TUI mode.
-async fn run_tui() -> Result<()> {
+async fn run_tui(db_path: PathBuf) -> Result<()> {
     // Set up the terminal for the TUI.
     let mut terminal = tui::setup_terminal()?;
 
     // Create a new `App` instance and run the application.
-    let mut app = App::default();
+    let mut app = App::default().with_db_path(db_path);
     app.run(&mut terminal).await?;
 
     // Restore the terminal to its original state.
@@ -0,0 +1,154 @@
+//! # Progress Reporting
+//!
+//! The processing core reports its progress through a `ProgressSink`, so it can run
+//! behind the TUI, the CLI, a log, or nothing at all without knowing which.
+//!
+//! The TUI and CLI receive `ProgressUpdate`s from a `ChannelProgress` over a bounded
+//! channel. Sending never waits: when the receiver falls behind, progress ticks and
+//! previews are dropped rather than queued, so a slow UI neither slows the processing
+//! down nor piles up decoded frames in memory.
+
+use image::DynamicImage;
+use std::path::Path;
+use tokio::sync::mpsc;
+
+use crate::{app::ProgressUpdate, deduplicate::DedupSummary};
+
+/// Receives progress reports from the processing core.
+///
+/// Only `message` and `progress` are required; the other reports feed previews and
+/// summaries and have defaults that ignore them or fall back to `message`.
+pub trait ProgressSink: Send + Sync {
+    /// Reports a human-readable status message.
+    fn message(&self, s: &str);
+
+    /// Reports the overall progress, from `0.0` to `1.0`.
+    fn progress(&self, p: f64);
+
+    /// Reports the image about to be tagged.
+    fn image_processed(&self, _path: &Path) {}
+
+    /// Reports a frame extracted from a video, about to be tagged.
+    fn frame(&self, _frame: &DynamicImage) {}
+
+    /// Reports the outcome of the duplicate removal pass.
+    fn duplicates_removed(&self, summary: DedupSummary) {
+        self.message(&summary.to_string());
+    }
+
+    /// Reports that the run finished, including when it was interrupted.
+    fn complete(&self) {}
+}
+
+/// Discards every report.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullProgress;
+
+impl ProgressSink for NullProgress {
+    fn message(&self, _s: &str) {}
+
+    fn progress(&self, _p: f64) {}
+}
+
+/// The capacity of the `ProgressUpdate` channel between the processing core and the
+/// TUI or CLI.
+pub const PROGRESS_CHANNEL_CAPACITY: usize = 100;
+
+/// Forwards every report as a `ProgressUpdate` over a bounded channel. Reports sent
+/// after the receiver is dropped are discarded.
+///
+/// Progress ticks and previews are dropped while the channel is full, since the next
+/// one supersedes them. Messages, summaries, errors, and completion are never dropped:
+/// they are queued for a single forwarding task, so they arrive in the order they were
+/// sent and `Complete` always comes last.
+#[derive(Debug, Clone)]
+pub struct ChannelProgress {
+    updates: mpsc::Sender<ProgressUpdate>,
+    reliable: mpsc::UnboundedSender<ProgressUpdate>,
+}
+
+impl ChannelProgress {
+    /// Creates a sink and the receiver of its updates, with room for `capacity`
+    /// updates.
+    ///
+    /// Must be called within a Tokio runtime, which runs the forwarding task.
+    pub fn channel(capacity: usize) -> (Self, mpsc::Receiver<ProgressUpdate>) {
+        let (updates, rx) = mpsc::channel(capacity);
+        let (reliable, mut queue) = mpsc::unbounded_channel();
+        let forward = updates.clone();
+        tokio::spawn(async move {
+            while let Some(update) = queue.recv().await {
+                if forward.send(update).await.is_err() {
+                    break;
+                }
+            }
+        });
+        (Self { updates, reliable }, rx)
+    }
+
+    /// Reports that the run failed, after every report sent before it.
+    pub fn error(&self, e: &str) {
+        let _ = self.reliable.send(ProgressUpdate::Error(e.to_string()));
+    }
+}
+
+impl ProgressSink for ChannelProgress {
+    fn message(&self, s: &str) {
+        let _ = self.reliable.send(ProgressUpdate::Message(s.to_string()));
+    }
+
+    fn progress(&self, p: f64) {
+        let _ = self.updates.try_send(ProgressUpdate::Progress(p));
+    }
+
+    fn image_processed(&self, path: &Path) {
+        let _ = self
+            .updates
+            .try_send(ProgressUpdate::ImageProcessed(path.to_path_buf()));
+    }
+
+    fn frame(&self, frame: &DynamicImage) {
+        // Skip the clone too when the frame would only be dropped.
+        if let Ok(permit) = self.updates.try_reserve() {
+            permit.send(ProgressUpdate::Frame(frame.clone()));
+        }
+    }
+
+    fn duplicates_removed(&self, summary: DedupSummary) {
+        let _ = self
+            .reliable
+            .send(ProgressUpdate::DuplicatesRemoved(summary));
+    }
+
+    fn complete(&self) {
+        let _ = self.reliable.send(ProgressUpdate::Complete);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_reliable_updates_keep_their_order_when_full() {
+        let (sink, mut rx) = ChannelProgress::channel(4);
+        for i in 0..20 {
+            sink.message(&i.to_string());
+            sink.progress(i as f64 / 20.0);
+        }
+        sink.complete();
+        drop(sink);
+
+        let mut messages = Vec::new();
+        let mut last = None;
+        while let Some(update) = rx.recv().await {
+            if let ProgressUpdate::Message(message) = &update {
+                messages.push(message.clone());
+            }
+            last = Some(update);
+        }
+        let expected: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        assert_eq!(messages, expected);
+        assert!(matches!(last, Some(ProgressUpdate::Complete)));
+    }
+}
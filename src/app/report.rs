@@ -0,0 +1,153 @@
+//! # Process Report
+//!
+//! This module records what happened to each file during a run (tagged, skipped,
+//! renamed, optimized, ...) so large runs can be audited afterwards.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The outcome of a single processing step for a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOutcome {
+    Tagged,
+    SkippedDuplicate,
+    SkippedAlreadyTagged,
+    SkippedSmall,
+    SkippedCorrupt,
+    /// Tagged, but the tags could not be saved.
+    Failed,
+    Renamed,
+    Optimized,
+}
+
+/// A single entry in the report.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub outcome: FileOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// The per-file audit trail of a processing run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProcessReport {
+    pub files: Vec<FileRecord>,
+}
+
+impl ProcessReport {
+    /// Records an outcome for a file.
+    pub fn record(&mut self, path: impl Into<PathBuf>, outcome: FileOutcome) {
+        self.files.push(FileRecord {
+            path: path.into(),
+            outcome,
+            detail: None,
+        });
+    }
+
+    /// Records an outcome for a file along with a human-readable detail.
+    pub fn record_with_detail(
+        &mut self,
+        path: impl Into<PathBuf>,
+        outcome: FileOutcome,
+        detail: impl Into<String>,
+    ) {
+        self.files.push(FileRecord {
+            path: path.into(),
+            outcome,
+            detail: Some(detail.into()),
+        });
+    }
+
+    /// Returns every outcome recorded for `path`, in order.
+    pub fn outcomes_for(&self, path: &Path) -> Vec<FileOutcome> {
+        self.files
+            .iter()
+            .filter(|record| record.path == path)
+            .map(|record| record.outcome)
+            .collect()
+    }
+
+    /// Writes the report as pretty-printed JSON.
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).with_context(|| format!("Failed to write report to {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deduplicate::{self, DuplicateAction},
+        file,
+        progress::NullProgress,
+        video,
+    };
+    use eros::dedup::HashAlgorithm;
+    use image::{Rgb, RgbImage};
+
+    #[tokio::test]
+    async fn test_report_mixed_directory() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let gradient = RgbImage::from_fn(128, 128, |x, y| Rgb([x as u8 * 2, y as u8 * 2, 0]));
+        gradient.save(dir.join("a.png")).unwrap();
+        gradient.save(dir.join("b.png")).unwrap();
+        RgbImage::new(16, 16).save(dir.join("icon.png")).unwrap();
+        fs::write(dir.join("broken.mp4"), b"").unwrap();
+
+        let mut report = ProcessReport::default();
+        deduplicate::remove_duplicate_images(
+            &[dir.to_path_buf()],
+            0,
+            HashAlgorithm::Average,
+            &DuplicateAction::Delete,
+            &[],
+            &NullProgress,
+            &mut report,
+        )
+        .unwrap();
+
+        let images = file::get_image_files(dir.to_str().unwrap()).await.unwrap();
+        let (_, skipped) = file::filter_small_images(images, 64);
+        for path in skipped {
+            report.record(path, FileOutcome::SkippedSmall);
+        }
+
+        let frames = video::extract_frames_or_skip(
+            &dir.join("broken.mp4"),
+            &video::FrameExtractOptions::default(),
+            &NullProgress,
+        );
+        if frames.is_empty() {
+            report.record(dir.join("broken.mp4"), FileOutcome::SkippedCorrupt);
+        }
+
+        assert!(report.outcomes_for(&dir.join("a.png")).is_empty());
+        assert_eq!(
+            report.outcomes_for(&dir.join("b.png")),
+            vec![FileOutcome::SkippedDuplicate]
+        );
+        assert_eq!(
+            report.outcomes_for(&dir.join("icon.png")),
+            vec![FileOutcome::SkippedSmall]
+        );
+        assert_eq!(
+            report.outcomes_for(&dir.join("broken.mp4")),
+            vec![FileOutcome::SkippedCorrupt]
+        );
+
+        let report_path = dir.join("report.json");
+        report.write_json(&report_path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(report_path).unwrap()).unwrap();
+        assert_eq!(json["files"].as_array().unwrap().len(), 3);
+        assert_eq!(json["files"][0]["outcome"], "skipped_duplicate");
+    }
+}
@@ -2,6 +2,7 @@ use crate::{
     app::{App, CurrentScreen, MenuItem},
     ascii,
 };
+use eros::dedup::HashAlgorithm;
 use ratatui::{
     prelude::*,
     widgets::{Block, Borders, Clear, Gauge, List, ListItem, Paragraph},
@@ -114,6 +115,16 @@ fn render_menu(f: &mut Frame, app: &App, area: Rect) {
                 MenuItem::InputPath => format!("Input Path: {}", config.input_path),
                 MenuItem::Threshold => format!("Threshold: {}", config.threshold),
                 MenuItem::BatchSize => format!("Batch Size: {}", config.batch_size),
+                MenuItem::DedupMaxDistance => {
+                    format!("Dedup Max Distance: {}", config.dedup_max_distance)
+                }
+                MenuItem::DedupAlgorithm => {
+                    let name = match config.dedup_algorithm {
+                        HashAlgorithm::Average => "Average",
+                        HashAlgorithm::Dct => "DCT",
+                    };
+                    format!("Dedup Algorithm: < {} >", name)
+                }
                 MenuItem::ShowAsciiArt => {
                     format!("Show ASCII Art: < {} >", if app.show_ascii_art { "On" } else { "Off" })
                 }
@@ -144,9 +155,13 @@ fn render_ascii_art(f: &mut Frame, app: &App, area: Rect) {
                 vertical: 1,
                 horizontal: 1,
             });
-            ascii::create_ascii_art(frame, inner_area)
+            if ascii::supports_true_color() {
+                Text::from(ascii::create_ansi_art(frame, inner_area))
+            } else {
+                Text::from(ascii::create_ascii_art(frame, inner_area))
+            }
         } else {
-            "Waiting for image...".to_string()
+            Text::from("Waiting for image...")
         };
 
         let title = if app.processed_image_paths.is_empty() {
@@ -178,6 +193,7 @@ fn render_editing_popup(f: &mut Frame, app: &App) {
         Some(MenuItem::InputPath) => "Edit Input Path",
         Some(MenuItem::Threshold) => "Edit Threshold",
         Some(MenuItem::BatchSize) => "Edit Batch Size",
+        Some(MenuItem::DedupMaxDistance) => "Edit Dedup Max Distance",
         _ => "Editing",
     };
 
@@ -1,18 +1,79 @@
-use crate::{app::ProgressUpdate, db::Database, file::TaggingResultSimple};
+use crate::{
+    core::AppConfig,
+    db::Database,
+    file::{self, TaggingResultSimple},
+    progress::ProgressSink,
+    report::FileOutcome,
+};
 use anyhow::Result;
-use eros::{pipeline::TaggingPipeline, rating::RatingModel};
-use futures::stream::{self, StreamExt};
-use image::DynamicImage;
+use eros::{
+    pipeline::{Prediction, TaggingPipeline, TaggingResult},
+    rating::{Rating, RatingModel},
+};
+use image::{imageops::FilterType, DynamicImage, GrayImage, RgbImage};
 use std::{
+    cell::RefCell,
     fs,
     path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, OnceLock},
 };
-use tokio::sync::mpsc;
 
 /// Supported video extensions.
 pub const VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "mkv", "webm", "avi"];
 
+/// How the change between two frames is measured for scene detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SceneMetric {
+    /// Mean absolute per-channel difference. Cheap, but triggers on global brightness
+    /// flicker and misses structural changes in dark scenes.
+    #[default]
+    MeanAbsolute,
+    /// One minus the structural similarity (SSIM) of downscaled grayscale frames.
+    /// Ignores uniform brightness shifts and catches changes in structure.
+    Ssim,
+}
+
+impl SceneMetric {
+    /// Returns how different two frames are, from 0.0 (identical) to 1.0.
+    pub fn difference(self, a: &RgbImage, b: &RgbImage) -> f64 {
+        match self {
+            SceneMetric::MeanAbsolute => frame_difference(a, b),
+            SceneMetric::Ssim => ssim_difference(a, b),
+        }
+    }
+}
+
+/// Controls which decoded frames `extract_frames` keeps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameExtractOptions {
+    /// Minimum change (0.0 to 1.0), measured with `scene_metric`, from the last kept
+    /// frame for a frame to count as a new scene; 0.0 keeps every frame the interval
+    /// allows.
+    pub scene_threshold: f64,
+    /// How the change for `scene_threshold` is measured.
+    pub scene_metric: SceneMetric,
+    /// Minimum time, in seconds, between two kept frames.
+    pub min_interval_secs: f64,
+    /// Stop decoding once this many frames have been kept.
+    pub max_frames: usize,
+    /// When set, seek to this many evenly spaced frames with `extract_frames_uniform`
+    /// instead of decoding the whole video; the other options are then ignored.
+    pub uniform_samples: Option<usize>,
+}
+
+impl Default for FrameExtractOptions {
+    /// One frame every 3 seconds with no scene detection and no cap.
+    fn default() -> Self {
+        Self {
+            scene_threshold: 0.0,
+            scene_metric: SceneMetric::MeanAbsolute,
+            min_interval_secs: 3.0,
+            max_frames: usize::MAX,
+            uniform_samples: None,
+        }
+    }
+}
+
 /// Check if the path is a video file.
 pub fn is_video(path: &str) -> Result<bool> {
     match PathBuf::from(path).extension() {
@@ -24,84 +85,62 @@ pub fn is_video(path: &str) -> Result<bool> {
     }
 }
 
-/// Get video files from a directory.
-pub async fn get_video_files(dir: &str) -> Result<Vec<PathBuf>> {
-    let mut entries = tokio::fs::read_dir(dir).await?;
-    let mut tasks = vec![];
-
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-        let task = tokio::spawn(async move {
-            if is_video(path.to_str()?).unwrap_or(false) {
-                Some(path)
-            } else {
-                None
-            }
-        });
-
-        tasks.push(task);
-    }
-
-    let files = stream::iter(tasks)
-        .buffer_unordered(16)
-        .filter_map(|result| async move { result.ok().flatten() })
-        .collect()
-        .await;
-
-    Ok(files)
+/// Get video files from a directory, descending at most `max_depth` levels and skipping
+/// `exclude` like `file::get_image_files_with_depth`.
+pub async fn get_video_files(
+    dir: &str,
+    max_depth: Option<usize>,
+    exclude: &[PathBuf],
+) -> Result<Vec<PathBuf>> {
+    file::find_files(dir, max_depth, exclude, is_video).await
 }
 
 /// Processes a single video file by extracting frames, tagging them, and storing the results.
-pub async fn process_video(
+///
+/// Returns `FileOutcome::SkippedCorrupt` when no frames could be extracted.
+pub fn process_video(
     video_path: &Path,
     pipe: &Arc<Mutex<TaggingPipeline>>,
     rating_model: &Arc<Mutex<RatingModel>>,
     db: &Arc<Mutex<Database>>,
     get_hash_fn: impl Fn(&Path) -> Result<String>,
-    tx: &mpsc::Sender<ProgressUpdate>,
-    show_ascii_art: bool,
-) -> Result<()> {
-    // Extract frames every 3 seconds
-    let frame_images = extract_frames(video_path)?;
+    sink: &impl ProgressSink,
+    config: &AppConfig,
+) -> Result<FileOutcome> {
+    let frame_images = extract_frames_or_skip(video_path, &config.frame_options, sink);
 
     if frame_images.is_empty() {
-        return Ok(());
+        return Ok(FileOutcome::SkippedCorrupt);
     }
 
-    let mut all_tags = Vec::new();
-    let mut overall_rating = "sfw";
+    // Rate every frame in one session run; the video is NSFW if any frame is
+    let frame_refs: Vec<&DynamicImage> = frame_images.iter().collect();
+    let ratings = rating_model.lock().unwrap().rate_batch(&frame_refs)?;
+    let overall_rating = if ratings.iter().any(|r| *r == Rating::Nsfw) {
+        "nsfw"
+    } else {
+        "sfw"
+    };
 
-    for frame_image in frame_images {
-        if show_ascii_art {
-            if tx.send(ProgressUpdate::Frame(frame_image.clone())).await.is_err() {
-                // UI receiver has been dropped, so we can stop.
-                return Ok(());
-            }
-        }
+    let mut frame_results = Vec::with_capacity(frame_images.len());
 
-        // Determine rating, stopping at the first NSFW frame
-        if overall_rating != "nsfw" {
-            let rating = rating_model.lock().unwrap().rate(&frame_image)?;
-            if rating.as_str() == "nsfw" {
-                overall_rating = "nsfw";
-            }
+    for frame_image in frame_images {
+        if config.show_ascii_art {
+            sink.frame(&frame_image);
         }
 
-        let result = pipe.lock().unwrap().predict(frame_image, None)?;
-        let simple_result = TaggingResultSimple::from(result);
-        if !simple_result.tags.is_empty() {
-            all_tags.extend(simple_result.tags.split(", ").map(|s| s.to_string()));
-        }
+        frame_results.push(pipe.lock().unwrap().predict(frame_image, None)?);
     }
 
-    // Save the concatenated tags to the database
-    let tags_string = all_tags.join(", ");
+    // Save each tag seen in any frame once, ordered by its best confidence
+    let tags_string = TaggingResultSimple::from(merge_frame_results(frame_results)).tags;
     let hash = get_hash_fn(video_path)?;
     let size = fs::metadata(video_path)?.len();
+    let stored_path = file::storage_path(video_path, config.path_root.as_deref())?;
 
     let db_lock = db.lock().unwrap();
     db_lock.save_video_tags(
-        video_path.to_str().unwrap(),
+        &stored_path,
         size,
         &hash,
         &tags_string,
@@ -111,76 +150,644 @@ pub async fn process_video(
     // Clean up the database by removing duplicate tags
     db_lock.cleanup_video_tags(&hash)?;
 
-    Ok(())
+    Ok(FileOutcome::Tagged)
 }
 
-/// Extracts frames from a video at a 3-second interval.
-fn extract_frames(video_path: &Path) -> Result<Vec<DynamicImage>> {
-    ffmpeg_next::init().unwrap();
+/// Tags the frames of a video and merges them like `process_video`, without rating or
+/// storing the result.
+///
+/// Unlike `process_video`, an unreadable video is an error rather than a skip.
+pub fn tag_video(
+    video_path: &Path,
+    pipe: &mut TaggingPipeline,
+    options: &FrameExtractOptions,
+) -> Result<TaggingResult> {
+    let frame_images = match options.uniform_samples {
+        Some(samples) => extract_frames_uniform(video_path, samples)?,
+        None => extract_frames(video_path, options)?,
+    };
+    let frame_results = frame_images
+        .into_iter()
+        .map(|frame_image| pipe.predict(frame_image, None))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(merge_frame_results(frame_results))
+}
+
+/// Merges per-frame results into one, keeping each tag's highest confidence across
+/// frames and sorting every category by descending confidence.
+///
+/// Each frame is already thresholded, so a tag survives exactly when its maximum
+/// confidence passes the threshold.
+fn merge_frame_results(results: Vec<TaggingResult>) -> TaggingResult {
+    let merge = |category: fn(&TaggingResult) -> &Prediction| {
+        let mut merged = Prediction::new();
+        for (tag, &score) in results.iter().flat_map(category) {
+            let best = merged.entry(tag.clone()).or_insert(score);
+            *best = best.max(score);
+        }
+        merged.sort_by(|_, a, _, b| b.total_cmp(a));
+        merged
+    };
+
+    TaggingResult::new(
+        merge(|result| &result.rating),
+        merge(|result| &result.character),
+        merge(|result| &result.general),
+        merge(|result| &result.artist),
+        merge(|result| &result.copyright),
+        merge(|result| &result.meta),
+    )
+}
+
+/// Extracts frames like `extract_frames`, but treats empty, truncated, or otherwise
+/// unreadable videos as having no frames so the rest of the batch can continue.
+pub fn extract_frames_or_skip(
+    video_path: &Path,
+    options: &FrameExtractOptions,
+    sink: &impl ProgressSink,
+) -> Vec<DynamicImage> {
+    let frames = match options.uniform_samples {
+        Some(samples) => extract_frames_uniform(video_path, samples),
+        None => extract_frames(video_path, options),
+    };
+    match frames {
+        Ok(frames) => frames,
+        Err(e) => {
+            sink.message(&format!(
+                "Skipping unreadable video {:?}: {}",
+                video_path, e
+            ));
+            Vec::new()
+        }
+    }
+}
+
+/// Extracts frames from a video, keeping those selected by `options`.
+///
+/// A frame is kept when at least `min_interval_secs` have passed since the last kept
+/// frame (measured with the stream's time base) and it differs from that frame by at
+/// least `scene_threshold`. The first frame is always kept.
+///
+/// Frames are rotated upright according to the stream's rotation metadata, so
+/// portrait phone videos are not tagged sideways.
+fn extract_frames(video_path: &Path, options: &FrameExtractOptions) -> Result<Vec<DynamicImage>> {
+    anyhow::ensure!(fs::metadata(video_path)?.len() > 0, "Video file is empty");
+
+    init_ffmpeg()?;
     let mut ictx = ffmpeg_next::format::input(&video_path)?;
+    anyhow::ensure!(ictx.duration() != 0, "Video reports zero duration");
     let input = ictx
         .streams()
         .best(ffmpeg_next::media::Type::Video)
         .ok_or(ffmpeg_next::Error::StreamNotFound)?;
     let video_stream_index = input.index();
-    let frame_rate = input.avg_frame_rate();
-    let frame_interval = (frame_rate.0 as f64 / frame_rate.1 as f64 * 3.0).round() as i64;
+    let rotation = stream_rotation(&input);
+    let time_base = f64::from(input.time_base());
+    let frame_rate = f64::from(input.avg_frame_rate());
 
-    if frame_interval == 0 {
-        return Err(anyhow::anyhow!("Invalid frame interval for video."));
-    }
+    anyhow::ensure!(frame_rate > 0.0, "Invalid frame rate for video.");
 
     let context_decoder = ffmpeg_next::codec::context::Context::from_parameters(input.parameters())?;
     let mut decoder = context_decoder.decoder().video()?;
-    let mut scaler = ffmpeg_next::software::scaling::context::Context::get(
-        decoder.format(),
-        decoder.width(),
-        decoder.height(),
-        ffmpeg_next::format::Pixel::RGB24,
-        decoder.width(),
-        decoder.height(),
-        ffmpeg_next::software::scaling::flag::Flags::BILINEAR,
-    )?;
+    let mut scaler = CachedScaler::for_decoder(&decoder)?;
 
     let mut frame_count = 0i64;
+    let mut last_kept: Option<(f64, RgbImage)> = None;
     let mut extracted_frames = Vec::new();
 
-    for (stream, packet) in ictx.packets() {
+    'packets: for (stream, packet) in ictx.packets() {
         if stream.index() == video_stream_index {
-            decoder.send_packet(&packet)?;
+            // A truncated file yields undecodable packets; keep the frames decoded so far.
+            if decoder.send_packet(&packet).is_err() {
+                break;
+            }
             let mut decoded = ffmpeg_next::util::frame::video::Video::empty();
             while decoder.receive_frame(&mut decoded).is_ok() {
-                if frame_count % frame_interval == 0 {
-                    let mut rgb_frame = ffmpeg_next::util::frame::video::Video::empty();
-                    scaler.run(&decoded, &mut rgb_frame)?;
-
-                    let width = rgb_frame.width() as usize;
-                    let height = rgb_frame.height() as usize;
-                    let stride = rgb_frame.stride(0) as usize;
-                    let data = rgb_frame.data(0);
-
-                    let mut image_data = Vec::with_capacity(width * height * 3);
-                    if stride == width * 3 {
-                        image_data.extend_from_slice(data);
-                    } else {
-                        for y in 0..height {
-                            let start = y * stride;
-                            let end = start + width * 3;
-                            image_data.extend_from_slice(&data[start..end]);
-                        }
-                    }
+                let seconds = match decoded.timestamp() {
+                    Some(ts) => ts as f64 * time_base,
+                    None => frame_count as f64 / frame_rate,
+                };
+                frame_count += 1;
+
+                if last_kept
+                    .as_ref()
+                    .is_some_and(|(kept_at, _)| seconds - kept_at < options.min_interval_secs)
+                {
+                    continue;
+                }
+
+                let mut rgb_frame = ffmpeg_next::util::frame::video::Video::empty();
+                scaler.run(&decoded, &mut rgb_frame)?;
+                let Some(image) = rgb_frame_to_image(&rgb_frame) else {
+                    continue;
+                };
+                let image = rotate_frame(image, rotation);
 
-                    if let Some(image_buffer) = image::ImageBuffer::<image::Rgb<u8>, _>::from_raw(
-                        width as u32,
-                        height as u32,
-                        image_data,
-                    ) {
-                        extracted_frames.push(DynamicImage::ImageRgb8(image_buffer));
+                if let Some((_, previous)) = &last_kept {
+                    if options.scene_metric.difference(previous, &image) < options.scene_threshold {
+                        continue;
                     }
                 }
-                frame_count += 1;
+
+                extracted_frames.push(DynamicImage::ImageRgb8(image.clone()));
+                last_kept = Some((seconds, image));
+                if extracted_frames.len() >= options.max_frames {
+                    break 'packets;
+                }
             }
         }
     }
     Ok(extracted_frames)
-}
\ No newline at end of file
+}
+
+/// Extracts `samples` frames spread evenly across the video by seeking to each
+/// position, so only the frames around those positions are decoded. This is much
+/// faster than `extract_frames` for long videos.
+///
+/// Seeking lands on the keyframe at or before each position, so frames are only
+/// approximately evenly spaced, and positions that share a keyframe yield a single
+/// frame. Frames are rotated upright like in `extract_frames`.
+pub fn extract_frames_uniform(video_path: &Path, samples: usize) -> Result<Vec<DynamicImage>> {
+    anyhow::ensure!(fs::metadata(video_path)?.len() > 0, "Video file is empty");
+
+    init_ffmpeg()?;
+    let mut ictx = ffmpeg_next::format::input(&video_path)?;
+    // In `AV_TIME_BASE` units, which is also what `seek` expects.
+    let duration = ictx.duration();
+    anyhow::ensure!(duration > 0, "Video reports zero duration");
+    let input = ictx
+        .streams()
+        .best(ffmpeg_next::media::Type::Video)
+        .ok_or(ffmpeg_next::Error::StreamNotFound)?;
+    let video_stream_index = input.index();
+    let rotation = stream_rotation(&input);
+
+    let context_decoder =
+        ffmpeg_next::codec::context::Context::from_parameters(input.parameters())?;
+    let mut decoder = context_decoder.decoder().video()?;
+    let mut scaler = CachedScaler::for_decoder(&decoder)?;
+
+    let mut extracted_frames = Vec::with_capacity(samples);
+    let mut last_timestamp = None;
+    for i in 0..samples as i64 {
+        // The middle of each of `samples` equal slices, so the end is never sought.
+        let position = duration * (2 * i + 1) / (2 * samples as i64);
+        ictx.seek(position, ..position)?;
+        decoder.flush();
+
+        let mut decoded = ffmpeg_next::util::frame::video::Video::empty();
+        let mut found = false;
+        for (stream, packet) in ictx.packets() {
+            if stream.index() != video_stream_index {
+                continue;
+            }
+            if decoder.send_packet(&packet).is_err() {
+                break;
+            }
+            if decoder.receive_frame(&mut decoded).is_ok() {
+                found = true;
+                break;
+            }
+        }
+        // Positions that share a keyframe decode to the same frame.
+        let timestamp = decoded.timestamp();
+        if !found || timestamp.is_some_and(|ts| Some(ts) == last_timestamp) {
+            continue;
+        }
+        last_timestamp = timestamp;
+
+        let mut rgb_frame = ffmpeg_next::util::frame::video::Video::empty();
+        scaler.run(&decoded, &mut rgb_frame)?;
+        if let Some(image) = rgb_frame_to_image(&rgb_frame) {
+            extracted_frames.push(DynamicImage::ImageRgb8(rotate_frame(image, rotation)));
+        }
+    }
+    Ok(extracted_frames)
+}
+
+/// Initializes ffmpeg the first time it is called.
+fn init_ffmpeg() -> Result<()> {
+    static INIT: OnceLock<Result<(), ffmpeg_next::Error>> = OnceLock::new();
+    Ok((*INIT.get_or_init(ffmpeg_next::init))?)
+}
+
+/// The input pixel format and size an RGB24 scaler converts from.
+type ScalerKey = (ffmpeg_next::format::Pixel, u32, u32);
+
+thread_local! {
+    /// The RGB24 scaler of the last video decoded on this thread, so consecutive
+    /// videos with the same format and resolution skip creating a new one.
+    static RGB_SCALER: RefCell<Option<(ScalerKey, ffmpeg_next::software::scaling::Context)>> =
+        const { RefCell::new(None) };
+}
+
+/// A scaler converting a decoder's frames to RGB24 at the same size, taken from
+/// `RGB_SCALER` when it matches and put back there when dropped.
+struct CachedScaler {
+    key: ScalerKey,
+    scaler: Option<ffmpeg_next::software::scaling::Context>,
+}
+
+impl CachedScaler {
+    fn for_decoder(decoder: &ffmpeg_next::decoder::Video) -> Result<Self> {
+        let key = (decoder.format(), decoder.width(), decoder.height());
+        let cached = RGB_SCALER.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            match cell.take() {
+                Some((cached_key, scaler)) if cached_key == key => Some(scaler),
+                other => {
+                    *cell = other;
+                    None
+                }
+            }
+        });
+        let scaler = match cached {
+            Some(scaler) => scaler,
+            None => ffmpeg_next::software::scaling::Context::get(
+                key.0,
+                key.1,
+                key.2,
+                ffmpeg_next::format::Pixel::RGB24,
+                key.1,
+                key.2,
+                ffmpeg_next::software::scaling::flag::Flags::BILINEAR,
+            )?,
+        };
+        Ok(Self {
+            key,
+            scaler: Some(scaler),
+        })
+    }
+
+    fn run(
+        &mut self,
+        input: &ffmpeg_next::util::frame::video::Video,
+        output: &mut ffmpeg_next::util::frame::video::Video,
+    ) -> Result<()> {
+        if let Some(scaler) = &mut self.scaler {
+            scaler.run(input, output)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CachedScaler {
+    fn drop(&mut self) {
+        if let Some(scaler) = self.scaler.take() {
+            RGB_SCALER.with(|cell| *cell.borrow_mut() = Some((self.key, scaler)));
+        }
+    }
+}
+
+/// Returns how many degrees clockwise (0, 90, 180, or 270) frames of `stream` must be
+/// rotated to display upright, read from its display matrix or, for older files, its
+/// `rotate` tag. Mirroring in the display matrix is ignored.
+fn stream_rotation(stream: &ffmpeg_next::format::stream::Stream) -> u32 {
+    use ffmpeg_next::ffi;
+
+    // SAFETY: the parameters belong to `stream`, which outlives this block, and the
+    // side data is only read when it is large enough to hold a 3x3 `i32` matrix.
+    let matrix_degrees = unsafe {
+        let parameters = stream.parameters().as_ptr();
+        let side_data = ffi::av_packet_side_data_get(
+            (*parameters).coded_side_data,
+            (*parameters).nb_coded_side_data,
+            ffi::AVPacketSideDataType::AV_PKT_DATA_DISPLAYMATRIX,
+        );
+        (!side_data.is_null() && (*side_data).size >= 9 * std::mem::size_of::<i32>())
+            // The matrix angle is counterclockwise.
+            .then(|| -ffi::av_display_rotation_get((*side_data).data as *const i32))
+    };
+    let degrees = matrix_degrees
+        .or_else(|| stream.metadata().get("rotate")?.parse().ok())
+        .unwrap_or(0.0);
+
+    // Round to the nearest quarter turn; a degenerate matrix gives NaN, which is 0.
+    ((degrees / 90.0).round() as i64).rem_euclid(4) as u32 * 90
+}
+
+/// Rotates a frame clockwise by `degrees`, which is 0, 90, 180, or 270.
+fn rotate_frame(image: RgbImage, degrees: u32) -> RgbImage {
+    match degrees {
+        90 => image::imageops::rotate90(&image),
+        180 => image::imageops::rotate180(&image),
+        270 => image::imageops::rotate270(&image),
+        _ => image,
+    }
+}
+
+/// Copies a decoded RGB24 frame into an image, dropping any row padding.
+fn rgb_frame_to_image(rgb_frame: &ffmpeg_next::util::frame::video::Video) -> Option<RgbImage> {
+    let width = rgb_frame.width() as usize;
+    let height = rgb_frame.height() as usize;
+    let stride = rgb_frame.stride(0);
+    let data = rgb_frame.data(0);
+
+    let mut image_data = Vec::with_capacity(width * height * 3);
+    if stride == width * 3 {
+        image_data.extend_from_slice(data);
+    } else {
+        for y in 0..height {
+            let start = y * stride;
+            let end = start + width * 3;
+            image_data.extend_from_slice(&data[start..end]);
+        }
+    }
+
+    RgbImage::from_raw(width as u32, height as u32, image_data)
+}
+
+/// Mean absolute per-channel difference between two frames, from 0.0 (identical)
+/// to 1.0. Frames of different sizes count as entirely different.
+fn frame_difference(a: &RgbImage, b: &RgbImage) -> f64 {
+    if a.dimensions() != b.dimensions() || a.as_raw().is_empty() {
+        return 1.0;
+    }
+    let total: u64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&x, &y)| u64::from(x.abs_diff(y)))
+        .sum();
+    total as f64 / (a.as_raw().len() as f64 * 255.0)
+}
+
+/// Frames are downscaled so their longer side is at most this many pixels before
+/// SSIM is computed.
+const SSIM_MAX_SIDE: u32 = 128;
+
+/// The side of the square windows whose SSIM is averaged.
+const SSIM_WINDOW: u32 = 8;
+
+/// One minus the mean SSIM of two frames over `SSIM_WINDOW` windows, from 0.0
+/// (identical) to 1.0. Frames of different sizes count as entirely different.
+fn ssim_difference(a: &RgbImage, b: &RgbImage) -> f64 {
+    if a.dimensions() != b.dimensions() || a.as_raw().is_empty() {
+        return 1.0;
+    }
+    let (a, b) = (ssim_input(a), ssim_input(b));
+    let (width, height) = a.dimensions();
+    let (window_width, window_height) = (SSIM_WINDOW.min(width), SSIM_WINDOW.min(height));
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y in (0..=height - window_height).step_by(window_height as usize) {
+        for x in (0..=width - window_width).step_by(window_width as usize) {
+            total += window_ssim(&a, &b, (x, y), (window_width, window_height));
+            windows += 1;
+        }
+    }
+    (1.0 - total / windows as f64).clamp(0.0, 1.0)
+}
+
+/// Converts a frame to grayscale, downscaled to fit within `SSIM_MAX_SIDE`.
+fn ssim_input(frame: &RgbImage) -> GrayImage {
+    let gray = image::imageops::grayscale(frame);
+    let (width, height) = gray.dimensions();
+    let longer = width.max(height);
+    if longer <= SSIM_MAX_SIDE {
+        return gray;
+    }
+    let scale = |side: u32| (side * SSIM_MAX_SIDE / longer).max(1);
+    image::imageops::resize(&gray, scale(width), scale(height), FilterType::Triangle)
+}
+
+/// The SSIM of the window at `origin` with the given `size` in both images.
+fn window_ssim(a: &GrayImage, b: &GrayImage, origin: (u32, u32), size: (u32, u32)) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (x0, y0) = origin;
+    let (width, height) = size;
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in y0..y0 + height {
+        for x in x0..x0 + width {
+            let (pa, pb) = (a.get_pixel(x, y)[0], b.get_pixel(x, y)[0]);
+            pixels.push((f64::from(pa), f64::from(pb)));
+        }
+    }
+    let n = pixels.len() as f64;
+
+    let mean_a = pixels.iter().map(|(pa, _)| pa).sum::<f64>() / n;
+    let mean_b = pixels.iter().map(|(_, pb)| pb).sum::<f64>() / n;
+    let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+    for (pa, pb) in &pixels {
+        let (da, db) = (pa - mean_a, pb - mean_b);
+        var_a += da * da;
+        var_b += db * db;
+        covariance += da * db;
+    }
+    let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        app::ProgressUpdate,
+        progress::{ChannelProgress, NullProgress, PROGRESS_CHANNEL_CAPACITY},
+    };
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_zero_byte_video_is_skipped() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let video_path = temp_dir.path().join("empty.mp4");
+        fs::write(&video_path, b"").unwrap();
+
+        let (sink, mut rx) = ChannelProgress::channel(PROGRESS_CHANNEL_CAPACITY);
+        let frames = extract_frames_or_skip(&video_path, &FrameExtractOptions::default(), &sink);
+        assert!(frames.is_empty());
+
+        match rx.recv().await {
+            Some(ProgressUpdate::Message(msg)) => assert!(msg.contains("Skipping")),
+            other => panic!("Unexpected update: {:?}", other),
+        }
+    }
+
+    /// Runs the ffmpeg command line tool with `args`.
+    fn ffmpeg(args: &[&str]) {
+        let status = std::process::Command::new("ffmpeg")
+            .args(["-nostdin", "-y", "-loglevel", "error"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_rotated_video_frames_are_upright() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let landscape = temp_dir.path().join("landscape.mp4");
+        let rotated = temp_dir.path().join("rotated.mp4");
+        let (landscape_arg, rotated_arg) = (landscape.to_str().unwrap(), rotated.to_str().unwrap());
+        ffmpeg(&[
+            "-f",
+            "lavfi",
+            "-i",
+            "testsrc=size=320x240:rate=10:duration=1",
+            "-c:v",
+            "mpeg4",
+            landscape_arg,
+        ]);
+        // Tags the stream with a quarter-turn display matrix without re-encoding.
+        ffmpeg(&[
+            "-display_rotation",
+            "90",
+            "-i",
+            landscape_arg,
+            "-c",
+            "copy",
+            rotated_arg,
+        ]);
+
+        let options = FrameExtractOptions::default();
+        let frames = extract_frames(&landscape, &options).unwrap();
+        assert_eq!((frames[0].width(), frames[0].height()), (320, 240));
+        let frames = extract_frames(&rotated, &options).unwrap();
+        assert_eq!((frames[0].width(), frames[0].height()), (240, 320));
+    }
+
+    #[test]
+    fn test_extract_frames_uniform() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let video_path = temp_dir.path().join("clip.mp4");
+        // Ten seconds with a keyframe every second.
+        ffmpeg(&[
+            "-f",
+            "lavfi",
+            "-i",
+            "testsrc=size=320x240:rate=10:duration=10",
+            "-c:v",
+            "mpeg4",
+            "-g",
+            "10",
+            video_path.to_str().unwrap(),
+        ]);
+
+        let frames = extract_frames_uniform(&video_path, 4).unwrap();
+        assert_eq!(frames.len(), 4);
+        assert!(frames
+            .iter()
+            .all(|frame| (frame.width(), frame.height()) == (320, 240)));
+        // The timestamp counter in `testsrc` makes frames from different seconds differ.
+        assert_ne!(frames[0], frames[3]);
+
+        assert!(extract_frames_uniform(&video_path, 0).unwrap().is_empty());
+
+        let options = FrameExtractOptions {
+            uniform_samples: Some(2),
+            ..FrameExtractOptions::default()
+        };
+        let frames = extract_frames_or_skip(&video_path, &options, &NullProgress);
+        assert_eq!(frames.len(), 2);
+    }
+
+    #[test]
+    fn test_scaler_is_reused_for_the_same_resolution() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let encode = |name: &str, size: &str| {
+            let path = temp_dir.path().join(name);
+            let source = format!("testsrc=size={}:rate=10:duration=1", size);
+            ffmpeg(&[
+                "-f",
+                "lavfi",
+                "-i",
+                &source,
+                "-c:v",
+                "mpeg4",
+                path.to_str().unwrap(),
+            ]);
+            path
+        };
+        let cached_size =
+            || RGB_SCALER.with(|cell| cell.borrow().as_ref().map(|((_, w, h), _)| (*w, *h)));
+        let options = FrameExtractOptions::default();
+
+        let first = extract_frames(&encode("a.mp4", "320x240"), &options).unwrap();
+        assert_eq!(cached_size(), Some((320, 240)));
+        let second = extract_frames(&encode("b.mp4", "320x240"), &options).unwrap();
+        assert_eq!(first[0], second[0]);
+
+        extract_frames(&encode("c.mp4", "160x120"), &options).unwrap();
+        assert_eq!(cached_size(), Some((160, 120)));
+    }
+
+    #[test]
+    fn test_rotate_frame() {
+        let image = RgbImage::from_fn(4, 2, |x, _| image::Rgb([x as u8; 3]));
+        assert_eq!(rotate_frame(image.clone(), 0), image);
+        assert_eq!(rotate_frame(image.clone(), 90).dimensions(), (2, 4));
+        assert_eq!(rotate_frame(image.clone(), 90).get_pixel(0, 0)[0], 0);
+        assert_eq!(rotate_frame(image.clone(), 180).get_pixel(0, 0)[0], 3);
+        assert_eq!(rotate_frame(image, 270).get_pixel(0, 0)[0], 3);
+    }
+
+    #[test]
+    fn test_merge_frame_results_keeps_max_confidence() {
+        let frame = |general: &[(&str, f32)]| TaggingResult {
+            rating: Prediction::from_iter([("general".to_string(), 0.9)]),
+            character: Prediction::new(),
+            general: general.iter().map(|(t, s)| (t.to_string(), *s)).collect(),
+            artist: Prediction::new(),
+            copyright: Prediction::new(),
+            meta: Prediction::new(),
+        };
+        let merged = merge_frame_results(vec![
+            frame(&[("1girl", 0.6), ("outdoors", 0.55)]),
+            frame(&[("1girl", 0.9), ("sky", 0.7)]),
+            frame(&[("outdoors", 0.8), ("1girl", 0.7)]),
+        ]);
+
+        let general: Vec<(&str, f32)> =
+            merged.general.iter().map(|(t, s)| (t.as_str(), *s)).collect();
+        assert_eq!(general, vec![("1girl", 0.9), ("outdoors", 0.8), ("sky", 0.7)]);
+        assert_eq!(merged.rating.len(), 1);
+        assert_eq!(TaggingResultSimple::from(merged).tags, "1girl, outdoors, sky");
+    }
+
+    #[test]
+    fn test_frame_difference() {
+        let black = RgbImage::from_pixel(4, 4, image::Rgb([0, 0, 0]));
+        let white = RgbImage::from_pixel(4, 4, image::Rgb([255, 255, 255]));
+        let gray = RgbImage::from_pixel(4, 4, image::Rgb([51, 51, 51]));
+
+        assert_eq!(frame_difference(&black, &black), 0.0);
+        assert_eq!(frame_difference(&black, &white), 1.0);
+        assert!((frame_difference(&black, &gray) - 0.2).abs() < 1e-9);
+        assert_eq!(frame_difference(&black, &RgbImage::new(2, 2)), 1.0);
+    }
+
+    #[test]
+    fn test_ssim_difference() {
+        let gradient = RgbImage::from_fn(64, 64, |x, _| image::Rgb([100 + x as u8; 3]));
+        assert!(ssim_difference(&gradient, &gradient) < 1e-9);
+        assert_eq!(ssim_difference(&gradient, &RgbImage::new(2, 2)), 1.0);
+
+        // Frames larger than `SSIM_MAX_SIDE` are downscaled first.
+        let large = RgbImage::from_fn(300, 200, |x, y| image::Rgb([(x ^ y) as u8; 3]));
+        assert!(ssim_difference(&large, &large) < 1e-9);
+    }
+
+    #[test]
+    fn test_ssim_and_mean_absolute_disagree() {
+        let threshold = 0.05;
+
+        // The last step of a fade to black: a dim checkerboard becomes black. Little
+        // changes per pixel, but all structure is lost.
+        let dim = RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([if (x / 4 + y / 4) % 2 == 0 { 0 } else { 20 }; 3])
+        });
+        let black = RgbImage::new(64, 64);
+        assert!(SceneMetric::MeanAbsolute.difference(&dim, &black) < threshold);
+        assert!(SceneMetric::Ssim.difference(&dim, &black) > threshold);
+
+        // Brightness flicker: the same frame, uniformly brighter.
+        let frame = RgbImage::from_fn(64, 64, |x, _| image::Rgb([100 + x as u8; 3]));
+        let brighter = RgbImage::from_fn(64, 64, |x, _| image::Rgb([120 + x as u8; 3]));
+        assert!(SceneMetric::MeanAbsolute.difference(&frame, &brighter) > threshold);
+        assert!(SceneMetric::Ssim.difference(&frame, &brighter) < threshold);
+    }
+}
@@ -3,7 +3,10 @@
 //! This module provides structs and functions for loading and managing model
 //! and preprocessing configurations from Hugging Face repositories.
 
-use crate::file::{ConfigFile, PreprocessFile};
+use crate::{
+    error::TaggerError,
+    file::{ConfigFile, PreprocessFile},
+};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::{fs, path::Path};
@@ -35,8 +38,8 @@ pub struct PretrainedCfg {
 impl ModelConfig {
     /// Loads a `ModelConfig` from a local file path.
     pub fn load<P: AsRef<Path>>(config_path: P) -> Result<Self> {
-        let json = fs::read_to_string(config_path)?;
-        let config: ModelConfig = serde_json::from_str(&json)?;
+        let json = fs::read_to_string(config_path).map_err(TaggerError::from)?;
+        let config: ModelConfig = serde_json::from_str(&json).map_err(TaggerError::from)?;
         Ok(config)
     }
 
@@ -71,8 +74,8 @@ pub struct Stage {
 impl PreprocessConfig {
     /// Loads a `PreprocessConfig` from a local file path.
     pub fn load<P: AsRef<Path>>(config_path: P) -> Result<Self> {
-        let json = fs::read_to_string(config_path)?;
-        let config: PreprocessConfig = serde_json::from_str(&json)?;
+        let json = fs::read_to_string(config_path).map_err(TaggerError::from)?;
+        let config: PreprocessConfig = serde_json::from_str(&json).map_err(TaggerError::from)?;
         Ok(config)
     }
 
@@ -130,4 +133,23 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_load_invalid_config_is_config_error() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config_path = temp_dir.path().join("config.json");
+        fs::write(&config_path, "{ not json").unwrap();
+
+        let err = ModelConfig::load(&config_path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TaggerError>(),
+            Some(TaggerError::Config(_))
+        ));
+
+        let err = ModelConfig::load(temp_dir.path().join("missing.json")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TaggerError>(),
+            Some(TaggerError::Io(_))
+        ));
+    }
+
 }
@@ -0,0 +1,404 @@
+//! # Near-Duplicate Detection
+//!
+//! This module fingerprints images with a perceptual hash (an 8x8 average hash or a
+//! DCT-based pHash) and groups images whose fingerprints are within a Hamming
+//! distance of each other.
+//!
+//! Nothing is deleted here: `group_duplicates` returns clusters and leaves the policy
+//! of what to keep to the caller.
+
+use image::{imageops::FilterType, DynamicImage};
+use rayon::prelude::*;
+use std::path::PathBuf;
+
+/// The side length of the grayscale image the DCT hash is computed from.
+const DCT_SIZE: usize = 32;
+/// The side length of the block of lowest frequencies the DCT hash keeps.
+const DCT_LOW_FREQUENCIES: usize = 8;
+
+/// The algorithm used to fingerprint images.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// Compares each pixel of an 8x8 thumbnail to the mean. Fast, but fooled by
+    /// brightness changes that saturate highlights and by gradients.
+    #[default]
+    Average,
+    /// Compares the 8x8 lowest frequencies of a 32x32 DCT to their median, which
+    /// depends on the image's structure rather than its overall brightness.
+    Dct,
+}
+
+/// Computes an 8x8 average-hash fingerprint of an image.
+pub fn perceptual_hash(image: &DynamicImage) -> u64 {
+    calculate_fingerprint(image, HashAlgorithm::Average)
+}
+
+/// Computes a 64-bit fingerprint of an image with `algorithm`.
+pub fn calculate_fingerprint(image: &DynamicImage, algorithm: HashAlgorithm) -> u64 {
+    let size = match algorithm {
+        HashAlgorithm::Average => 8,
+        HashAlgorithm::Dct => DCT_SIZE as u32,
+    };
+    let pixels = image
+        .resize_exact(size, size, FilterType::Triangle)
+        .to_luma8()
+        .into_raw();
+
+    // A solid-color image sets no bits, which would make every solid image collide,
+    // so those are fingerprinted by their luma instead.
+    if pixels.iter().all(|&p| p == pixels[0]) {
+        return u64::from_ne_bytes([pixels[0]; 8]);
+    }
+
+    match algorithm {
+        HashAlgorithm::Average => average_hash(&pixels),
+        HashAlgorithm::Dct => dct_hash(&pixels),
+    }
+}
+
+/// Sets a bit for each of the 8x8 `pixels` brighter than their mean.
+fn average_hash(pixels: &[u8]) -> u64 {
+    let sum: u32 = pixels.iter().map(|&p| p as u32).sum();
+    let avg = sum / pixels.len() as u32;
+
+    pixels
+        .iter()
+        .enumerate()
+        .filter(|(_, &p)| p as u32 > avg)
+        .fold(0u64, |hash, (i, _)| hash | (1 << i))
+}
+
+/// Sets a bit for each of the 8x8 lowest DCT-II coefficients of the 32x32 `pixels`
+/// that is above their median.
+fn dct_hash(pixels: &[u8]) -> u64 {
+    let cosines: Vec<[f64; DCT_SIZE]> = (0..DCT_LOW_FREQUENCIES)
+        .map(|u| {
+            std::array::from_fn(|x| {
+                ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * DCT_SIZE) as f64).cos()
+            })
+        })
+        .collect();
+
+    let mut coefficients = Vec::with_capacity(DCT_LOW_FREQUENCIES * DCT_LOW_FREQUENCIES);
+    for vertical in &cosines {
+        for horizontal in &cosines {
+            let coefficient: f64 = pixels
+                .chunks_exact(DCT_SIZE)
+                .zip(vertical)
+                .map(|(row, cos_y)| {
+                    let row_sum: f64 = row
+                        .iter()
+                        .zip(horizontal)
+                        .map(|(&p, cos_x)| p as f64 * cos_x)
+                        .sum();
+                    row_sum * cos_y
+                })
+                .sum();
+            coefficients.push(coefficient);
+        }
+    }
+
+    let mut sorted = coefficients.clone();
+    sorted.sort_by(f64::total_cmp);
+    let middle = sorted.len() / 2;
+    let median = (sorted[middle - 1] + sorted[middle]) / 2.0;
+
+    coefficients
+        .iter()
+        .enumerate()
+        .filter(|(_, &c)| c > median)
+        .fold(0u64, |hash, (i, _)| hash | (1 << i))
+}
+
+/// Returns the number of differing bits between two fingerprints.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Groups images whose `algorithm` fingerprints are within `max_distance` bits of
+/// each other.
+///
+/// Paths are visited in the given order, and each joins the first group whose first
+/// member is close enough, so the first path of every group is the one seen earliest.
+/// Only groups with at least two images are returned. Unreadable images are skipped.
+///
+/// Fingerprints are computed in parallel.
+pub fn group_duplicates(
+    paths: &[PathBuf],
+    max_distance: u32,
+    algorithm: HashAlgorithm,
+) -> Vec<Vec<PathBuf>> {
+    let fingerprints: Vec<Option<u64>> = paths
+        .par_iter()
+        .map(|path| {
+            let image = image::open(path).ok()?;
+            Some(calculate_fingerprint(&image, algorithm))
+        })
+        .collect();
+
+    group_fingerprints(&fingerprints, max_distance)
+        .into_iter()
+        .map(|members| members.into_iter().map(|i| paths[i].clone()).collect())
+        .collect()
+}
+
+/// Groups fingerprints like `group_duplicates`, returning the indices of each group's
+/// members. `None` entries are skipped.
+///
+/// The first fingerprint of each group is kept in a BK-tree, so finding the group a
+/// fingerprint joins only visits the branches that can be within `max_distance`
+/// instead of comparing against every group.
+fn group_fingerprints(fingerprints: &[Option<u64>], max_distance: u32) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut leaders = BkTree::default();
+
+    for (i, fingerprint) in fingerprints.iter().enumerate() {
+        let Some(fingerprint) = *fingerprint else {
+            continue;
+        };
+        match leaders.first_within(fingerprint, max_distance) {
+            Some(group) => groups[group].push(i),
+            None => {
+                leaders.insert(fingerprint, groups.len());
+                groups.push(vec![i]);
+            }
+        }
+    }
+
+    groups.retain(|members| members.len() > 1);
+    groups
+}
+
+/// A BK-tree of fingerprints under the Hamming distance, each tagged with a value.
+///
+/// Every child is keyed by its distance to its parent, so by the triangle inequality a
+/// query within `max_distance` of a node at distance `d` only needs the children keyed
+/// `d - max_distance..=d + max_distance`.
+#[derive(Debug, Default)]
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+#[derive(Debug)]
+struct BkNode {
+    fingerprint: u64,
+    value: usize,
+    /// `(distance, node index)` of each child.
+    children: Vec<(u32, usize)>,
+}
+
+impl BkTree {
+    /// Inserts `fingerprint` with `value`. A fingerprint already in the tree keeps its
+    /// earlier value.
+    fn insert(&mut self, fingerprint: u64, value: usize) {
+        let node = BkNode {
+            fingerprint,
+            value,
+            children: Vec::new(),
+        };
+        if self.nodes.is_empty() {
+            self.nodes.push(node);
+            return;
+        }
+
+        let mut current = 0;
+        loop {
+            let distance = hamming_distance(self.nodes[current].fingerprint, fingerprint);
+            if distance == 0 {
+                return;
+            }
+            let children = &self.nodes[current].children;
+            match children.iter().find(|(d, _)| *d == distance) {
+                Some(&(_, child)) => current = child,
+                None => {
+                    let index = self.nodes.len();
+                    self.nodes[current].children.push((distance, index));
+                    self.nodes.push(node);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns the smallest value of the fingerprints within `max_distance` bits of
+    /// `fingerprint`.
+    fn first_within(&self, fingerprint: u64, max_distance: u32) -> Option<usize> {
+        let mut first = None;
+        let mut pending = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(current) = pending.pop() {
+            let node = &self.nodes[current];
+            let distance = hamming_distance(node.fingerprint, fingerprint);
+            if distance <= max_distance {
+                first = Some(first.map_or(node.value, |first: usize| first.min(node.value)));
+            }
+            pending.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| d.abs_diff(distance) <= max_distance)
+                    .map(|&(_, child)| child),
+            );
+        }
+        first
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+
+    fn gradient() -> RgbImage {
+        RgbImage::from_fn(64, 64, |x, y| Rgb([(x * 4) as u8, (y * 4) as u8, 0]))
+    }
+
+    /// A linear congruential generator, so the synthetic data is the same every run.
+    fn lcg(state: &mut u64) -> u64 {
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        *state >> 11
+    }
+
+    /// The all-pairs grouping `group_fingerprints` must match: each fingerprint joins
+    /// the first group whose first member is close enough.
+    fn brute_force_groups(fingerprints: &[Option<u64>], max_distance: u32) -> Vec<Vec<usize>> {
+        let mut groups: Vec<(u64, Vec<usize>)> = Vec::new();
+        for (i, fingerprint) in fingerprints.iter().enumerate() {
+            let Some(fingerprint) = *fingerprint else {
+                continue;
+            };
+            match groups
+                .iter_mut()
+                .find(|(first, _)| hamming_distance(*first, fingerprint) <= max_distance)
+            {
+                Some((_, members)) => members.push(i),
+                None => groups.push((fingerprint, vec![i])),
+            }
+        }
+        groups
+            .into_iter()
+            .map(|(_, members)| members)
+            .filter(|members| members.len() > 1)
+            .collect()
+    }
+
+    #[test]
+    fn test_solid_colors_do_not_collide() {
+        let black = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([0, 0, 0])));
+        let white = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([255, 255, 255])));
+        assert_ne!(perceptual_hash(&black), perceptual_hash(&white));
+    }
+
+    #[test]
+    fn test_dct_hash_ignores_brightness() {
+        // A textured image with a near-mean flat region and a highlight that saturates
+        // when brightened, which shifts the mean the average hash compares against.
+        let original = DynamicImage::ImageLuma8(GrayImage::from_fn(32, 32, |x, y| {
+            if x >= 16 && y < 16 {
+                Luma([122])
+            } else if (8..24).contains(&x) && y >= 16 {
+                Luma([255])
+            } else {
+                let texture = (x * x * 7 + y * y * 3 + x * y * 5 + 13 * x + 11 * y) % 23;
+                Luma([40 + texture as u8 * 3])
+            }
+        }));
+        let brightened = original.brighten(80);
+
+        let distance = |algorithm| {
+            hamming_distance(
+                calculate_fingerprint(&original, algorithm),
+                calculate_fingerprint(&brightened, algorithm),
+            )
+        };
+        assert_eq!(distance(HashAlgorithm::Dct), 0);
+        assert!(distance(HashAlgorithm::Average) > 0);
+    }
+
+    #[test]
+    fn test_group_duplicates() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a.png", "b.png", "c.png", "d.png"]
+            .iter()
+            .map(|name| temp_dir.path().join(name))
+            .collect();
+        gradient().save(&paths[0]).unwrap();
+        RgbImage::from_pixel(64, 64, Rgb([255, 255, 255]))
+            .save(&paths[1])
+            .unwrap();
+        gradient().save(&paths[2]).unwrap();
+        std::fs::write(&paths[3], b"not an image").unwrap();
+
+        let groups = group_duplicates(&paths, 0, HashAlgorithm::Average);
+        assert_eq!(groups, vec![vec![paths[0].clone(), paths[2].clone()]]);
+        assert!(paths.iter().all(|path| path.exists()));
+    }
+
+    #[test]
+    fn test_group_fingerprints_matches_brute_force() {
+        // Clusters of fingerprints a few bits apart, plus unrelated ones and gaps.
+        let mut state = 7;
+        let centers: Vec<u64> = (0..200).map(|_| lcg(&mut state)).collect();
+        let fingerprints: Vec<Option<u64>> = (0..5000)
+            .map(|_| match lcg(&mut state) % 10 {
+                0 => None,
+                1 => Some(lcg(&mut state)),
+                _ => {
+                    let center = centers[lcg(&mut state) as usize % centers.len()];
+                    let flips = (0..lcg(&mut state) % 6).map(|_| 1 << (lcg(&mut state) % 64));
+                    Some(flips.fold(center, |fingerprint, bit| fingerprint ^ bit))
+                }
+            })
+            .collect();
+
+        assert!(!group_fingerprints(&fingerprints, 3).is_empty());
+        for max_distance in [0, 1, 3, 5, 10, 64] {
+            assert_eq!(
+                group_fingerprints(&fingerprints, max_distance),
+                brute_force_groups(&fingerprints, max_distance),
+                "max_distance {}",
+                max_distance
+            );
+        }
+    }
+
+    #[test]
+    fn test_group_duplicates_matches_brute_force_on_images() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut state = 11;
+        let paths: Vec<PathBuf> = (0..300)
+            .map(|i| {
+                // 40 random patterns, each saved several times with a little noise.
+                let mut pattern_state = (i % 40) as u64 + 1;
+                let image = GrayImage::from_fn(16, 16, |_, _| {
+                    let noise = (lcg(&mut state) % 9) as u8;
+                    Luma([(lcg(&mut pattern_state) % 200) as u8 + noise])
+                });
+                let path = temp_dir.path().join(format!("{}.png", i));
+                image.save(&path).unwrap();
+                path
+            })
+            .collect();
+
+        let max_distance = 4;
+        let groups = group_duplicates(&paths, max_distance, HashAlgorithm::Average);
+        let fingerprints: Vec<Option<u64>> = paths
+            .iter()
+            .map(|path| {
+                let image = image::open(path).unwrap();
+                Some(calculate_fingerprint(&image, HashAlgorithm::Average))
+            })
+            .collect();
+        let expected: Vec<Vec<PathBuf>> = brute_force_groups(&fingerprints, max_distance)
+            .into_iter()
+            .map(|members| members.into_iter().map(|i| paths[i].clone()).collect())
+            .collect();
+
+        assert!(!groups.is_empty());
+        assert_eq!(groups, expected);
+    }
+}
@@ -2,9 +2,68 @@
 //!
 //! This module defines the custom error type for the `eros` library.
 //!
-//! The `TaggerError` enum represents all possible errors that can occur
-//! within the library, providing a unified and consistent error-handling mechanism.
-//! It uses the `thiserror` crate to derive the `Error` trait and provide
-//! descriptive error messages.
+//! The `TaggerError` enum represents the kinds of errors that can occur within the
+//! library, so consumers can tell a network failure from a broken config or an ONNX
+//! Runtime failure. It uses the `thiserror` crate to derive the `Error` trait and
+//! provide descriptive error messages.
+//!
+//! Library functions return `anyhow::Result` and attach context as errors propagate;
+//! the originating `TaggerError` can be recovered with `error.downcast_ref::<TaggerError>()`.
+
+use thiserror::Error;
+
+/// The kinds of errors produced by the library.
+#[derive(Debug, Error)]
+pub enum TaggerError {
+    /// A download or other HTTP request failed.
+    #[error("Network error: {0}")]
+    Network(String),
+    /// Reading or writing a local file failed.
+    #[error("IO error: {0}")]
+    Io(String),
+    /// ONNX Runtime failed to build a session or run inference.
+    #[error("ONNX Runtime error: {0}")]
+    Ort(String),
+    /// A configuration or tag file could not be parsed.
+    #[error("Configuration error: {0}")]
+    Config(String),
+    /// The model is not usable, e.g. it has no inputs or outputs.
+    #[error("Model error: {0}")]
+    Model(String),
+    /// The operation was cancelled before it finished.
+    #[error("Operation cancelled")]
+    Cancelled,
+}
+
+/// A `Result` whose error is a `TaggerError`.
+pub type Result<T> = std::result::Result<T, TaggerError>;
+
+impl From<std::io::Error> for TaggerError {
+    fn from(e: std::io::Error) -> Self {
+        TaggerError::Io(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for TaggerError {
+    fn from(e: reqwest::Error) -> Self {
+        TaggerError::Network(e.to_string())
+    }
+}
+
+impl From<ort::Error> for TaggerError {
+    fn from(e: ort::Error) -> Self {
+        TaggerError::Ort(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for TaggerError {
+    fn from(e: serde_json::Error) -> Self {
+        TaggerError::Config(e.to_string())
+    }
+}
 
-pub type TaggerError = anyhow::Error;
\ No newline at end of file
+impl From<csv::Error> for TaggerError {
+    fn from(e: csv::Error) -> Self {
+        TaggerError::Config(e.to_string())
+    }
+}
@@ -0,0 +1,176 @@
+//! # Result Export
+//!
+//! This module writes `TaggingResult`s to portable files, so results can be consumed
+//! without the app's SQLite database.
+//!
+//! `write_json_sidecar` writes one JSON file next to each image, and `write_csv` writes
+//! the results of many images to a single CSV file with one row per tag.
+
+use anyhow::{Context, Result};
+use serde::{Serialize, Serializer};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::TaggerError,
+    pipeline::{Prediction, TaggingResult},
+};
+
+/// The JSON layout of a sidecar file.
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    #[serde(serialize_with = "serialize_prediction")]
+    rating: &'a Prediction,
+    #[serde(serialize_with = "serialize_prediction")]
+    character: &'a Prediction,
+    #[serde(serialize_with = "serialize_prediction")]
+    general: &'a Prediction,
+    #[serde(serialize_with = "serialize_prediction")]
+    artist: &'a Prediction,
+    #[serde(serialize_with = "serialize_prediction")]
+    copyright: &'a Prediction,
+    #[serde(serialize_with = "serialize_prediction")]
+    meta: &'a Prediction,
+}
+
+/// Serializes a prediction as a tag-to-score map, keeping its descending-score order.
+fn serialize_prediction<S: Serializer>(
+    prediction: &&Prediction,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(prediction.iter())
+}
+
+/// Returns the path of the sidecar for `image_path`: the full file name with `.json`
+/// appended, so `cat.jpg` and `cat.png` get different sidecars.
+pub fn sidecar_path(image_path: &Path) -> PathBuf {
+    let mut path = image_path.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+/// Serializes `result` as pretty-printed JSON.
+///
+/// The JSON object has `rating`, `character`, `general`, `artist`, `copyright`, and
+/// `meta` maps from tag to score.
+pub fn to_json(result: &TaggingResult) -> Result<String> {
+    let sidecar = Sidecar {
+        rating: &result.rating,
+        character: &result.character,
+        general: &result.general,
+        artist: &result.artist,
+        copyright: &result.copyright,
+        meta: &result.meta,
+    };
+    Ok(serde_json::to_string_pretty(&sidecar).map_err(TaggerError::from)?)
+}
+
+/// Writes `result` as JSON, laid out like `to_json`, to the sidecar of `image_path`
+/// and returns the sidecar's path.
+pub fn write_json_sidecar(result: &TaggingResult, image_path: &Path) -> Result<PathBuf> {
+    let json = to_json(result)?;
+
+    let path = sidecar_path(image_path);
+    fs::write(&path, json)
+        .map_err(TaggerError::from)
+        .with_context(|| format!("Failed to write sidecar to {:?}", path))?;
+    Ok(path)
+}
+
+/// Writes the results of many images to a CSV file at `out`.
+///
+/// The file has the header `path,category,tag,score` and one row per tag, in the
+/// order of `results` and then of each category.
+pub fn write_csv(results: &[(PathBuf, TaggingResult)], out: &Path) -> Result<()> {
+    let mut writer = csv::Writer::from_path(out)
+        .map_err(TaggerError::from)
+        .with_context(|| format!("Failed to create CSV file at {:?}", out))?;
+    writer
+        .write_record(["path", "category", "tag", "score"])
+        .map_err(TaggerError::from)?;
+
+    for (path, result) in results {
+        let path = path.to_string_lossy();
+        let categories = [
+            ("rating", &result.rating),
+            ("character", &result.character),
+            ("general", &result.general),
+            ("artist", &result.artist),
+            ("copyright", &result.copyright),
+            ("meta", &result.meta),
+        ];
+        for (category, prediction) in categories {
+            for (tag, score) in prediction {
+                let score = score.to_string();
+                writer
+                    .write_record([&*path, category, tag.as_str(), score.as_str()])
+                    .map_err(TaggerError::from)?;
+            }
+        }
+    }
+
+    writer
+        .flush()
+        .map_err(TaggerError::from)
+        .with_context(|| format!("Failed to write CSV file at {:?}", out))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn result() -> TaggingResult {
+        TaggingResult::new(
+            [("general".to_string(), 0.9)].into_iter().collect(),
+            Prediction::new(),
+            [("solo".to_string(), 0.8), ("1girl".to_string(), 0.7)]
+                .into_iter()
+                .collect(),
+            Prediction::new(),
+            [("vocaloid".to_string(), 0.6)].into_iter().collect(),
+            Prediction::new(),
+        )
+    }
+
+    #[test]
+    fn test_write_json_sidecar() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let image_path = temp_dir.path().join("cat.jpg");
+
+        let path = write_json_sidecar(&result(), &image_path).unwrap();
+        assert_eq!(path, temp_dir.path().join("cat.jpg.json"));
+
+        let json = fs::read_to_string(&path).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["rating"]["general"], 0.9);
+        assert_eq!(value["character"], serde_json::json!({}));
+        assert_eq!(value["general"]["1girl"], 0.7);
+        assert_eq!(value["copyright"]["vocaloid"], 0.6);
+        assert_eq!(value["meta"], serde_json::json!({}));
+        // Tags keep their descending-score order.
+        assert!(json.find("\"solo\"").unwrap() < json.find("\"1girl\"").unwrap());
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let out = temp_dir.path().join("results.csv");
+        let results = vec![
+            (PathBuf::from("a.jpg"), result()),
+            (PathBuf::from("b.png"), result()),
+        ];
+
+        write_csv(&results, &out).unwrap();
+        let csv = fs::read_to_string(&out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], "path,category,tag,score");
+        assert_eq!(lines[1], "a.jpg,rating,general,0.9");
+        assert_eq!(lines[2], "a.jpg,general,solo,0.8");
+        assert_eq!(lines[4], "a.jpg,copyright,vocaloid,0.6");
+        assert_eq!(lines[7], "b.png,general,1girl,0.7");
+    }
+}
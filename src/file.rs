@@ -1,80 +1,618 @@
 use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use reqwest::{header::RANGE, StatusCode};
+use sha2::{Digest, Sha256};
 use std::{
-    fs::{self, File},
-    io::Write,
+    env,
+    ffi::OsString,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
     path::{Path, PathBuf},
+    sync::RwLock,
+    time::Duration,
 };
+use tokio_util::sync::CancellationToken;
 
-const MODEL_ROOT: &str = "models";
+use crate::error::TaggerError;
+
+/// The name of the model cache directory, and the cache root itself when no platform
+/// cache directory is available.
+pub const MODEL_ROOT: &str = "models";
+/// The environment variable that overrides the default cache root.
+pub const CACHE_DIR_ENV: &str = "EROS_CACHE_DIR";
+
+/// The cache root set with `set_cache_root`, if any.
+static CACHE_ROOT: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// The environment variable holding a Hugging Face access token.
+pub const HF_TOKEN_ENV: &str = "HF_TOKEN";
+/// The older name of `HF_TOKEN`, read when `HF_TOKEN` is not set.
+pub const HF_HUB_TOKEN_ENV: &str = "HUGGING_FACE_HUB_TOKEN";
+
+/// The Hugging Face token set with `set_hf_token`, if any.
+static HF_TOKEN: RwLock<Option<String>> = RwLock::new(None);
+
+/// The download settings and the client built from them, created on first use or by
+/// `set_download_config`.
+static DOWNLOAD_CLIENT: RwLock<Option<(DownloadConfig, reqwest::Client)>> = RwLock::new(None);
+
+/// The tagger's ONNX model file name.
+pub const MODEL_FILE: &str = "model.onnx";
+/// The tagger's tag list file name.
+pub const TAGS_FILE: &str = "selected_tags.csv";
+/// The tag list file name of repos that ship JSON instead of CSV.
+pub const TAGS_JSON_FILE: &str = "tags.json";
+/// The tagger's model config file name.
+pub const CONFIG_FILE: &str = "config.json";
+/// The revision downloaded when none is given: the head of the repo's main branch.
+pub const DEFAULT_REVISION: &str = "main";
+
+/// A callback for download progress, called after every chunk with the bytes
+/// downloaded so far and the total size, or `None` when the server sent no
+/// `Content-Length`.
+pub type DownloadProgressCallback = dyn Fn(u64, Option<u64>) + Send + Sync;
+
+/// Timeouts and retries of model downloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadConfig {
+    /// How long to wait for the connection to the server.
+    pub connect_timeout: Duration,
+    /// How long to wait for the next chunk of data before giving up on a stalled
+    /// download.
+    pub read_timeout: Duration,
+    /// How many times a download is tried before its error is returned, including the
+    /// first try.
+    pub max_attempts: u32,
+    /// The wait before the first retry, doubled after every failed retry.
+    pub initial_backoff: Duration,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Sets the timeouts and retries of downloads for this process.
+pub fn set_download_config(config: DownloadConfig) -> Result<()> {
+    let client = build_client(&config)?;
+    *DOWNLOAD_CLIENT.write().unwrap() = Some((config, client));
+    Ok(())
+}
+
+/// Returns the download settings and the shared client, building them with the
+/// default settings on first use.
+fn download_client() -> Result<(DownloadConfig, reqwest::Client)> {
+    if let Some(client) = DOWNLOAD_CLIENT.read().unwrap().as_ref() {
+        return Ok(client.clone());
+    }
+    let mut client = DOWNLOAD_CLIENT.write().unwrap();
+    if client.is_none() {
+        let config = DownloadConfig::default();
+        *client = Some((config, build_client(&config)?));
+    }
+    Ok(client.clone().unwrap())
+}
+
+/// Builds an HTTP client with the timeouts of `config`.
+fn build_client(config: &DownloadConfig) -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .connect_timeout(config.connect_timeout)
+        .read_timeout(config.read_timeout)
+        .build()
+        .map_err(TaggerError::from)
+        .context("Failed to create HTTP client")?)
+}
 
 pub async fn download_file(url: &str, dest_path: &Path) -> Result<()> {
+    download_file_with_cancel(url, dest_path, None).await
+}
+
+/// Downloads `url` to `dest_path`, stopping as soon as `cancel` is triggered.
+///
+/// Data is written to a `.part` file next to the destination and only renamed into
+/// place once complete, so a failed download never leaves a corrupt cached file
+/// behind. If the download fails, the `.part` file is kept and the next attempt
+/// resumes from where it stopped; a cancelled download removes it.
+///
+/// Timeouts, dropped connections, and server errors (`5xx`) are retried with the
+/// backoff set with `set_download_config`; other errors, such as a `404`, fail at
+/// once.
+pub async fn download_file_with_cancel(
+    url: &str,
+    dest_path: &Path,
+    cancel: Option<&CancellationToken>,
+) -> Result<()> {
+    download_file_with_progress(url, dest_path, cancel, None).await
+}
+
+/// Like `download_file_with_cancel`, but reports the bytes received to `progress`.
+///
+/// A resumed download counts the bytes already on disk, so progress picks up where
+/// the previous attempt stopped.
+pub async fn download_file_with_progress(
+    url: &str,
+    dest_path: &Path,
+    cancel: Option<&CancellationToken>,
+    progress: Option<&DownloadProgressCallback>,
+) -> Result<()> {
+    download(url, None, dest_path, cancel, progress).await
+}
+
+/// Implements `download_file_with_progress`, sending `token` as a bearer token when
+/// given.
+async fn download(
+    url: &str,
+    token: Option<&str>,
+    dest_path: &Path,
+    cancel: Option<&CancellationToken>,
+    progress: Option<&DownloadProgressCallback>,
+) -> Result<()> {
     if let Some(parent) = dest_path.parent() {
         fs::create_dir_all(parent).context("Failed to create model directory")?;
     }
 
-    let response = reqwest::get(url)
-        .await
-        .with_context(|| format!("Failed to download file from {}", url))?;
+    let (config, client) = download_client()?;
+    let part_path = part_path(dest_path);
+    let result =
+        download_with_retry(&client, &config, url, token, &part_path, cancel, progress).await;
+    if result.is_err() && cancel.is_some_and(|c| c.is_cancelled()) {
+        let _ = fs::remove_file(&part_path);
+    }
+    result?;
 
-    anyhow::ensure!(
-        response.status().is_success(),
-        "Failed to download file: {} ({})",
-        url,
-        response.status()
-    );
+    fs::rename(&part_path, dest_path)
+        .with_context(|| format!("Failed to move download into place at {:?}", dest_path))?;
 
-    let mut dest =
-        File::create(&dest_path).with_context(|| format!("Failed to create file at {:?}", dest_path))?;
+    Ok(())
+}
 
-    let mut response = response;
-    while let Some(chunk) = response
-        .chunk()
-        .await
-        .context("Failed to read chunk from response")?
-    {
+/// Returns the path of the temporary file used while downloading `dest_path`.
+fn part_path(dest_path: &Path) -> PathBuf {
+    let mut name = OsString::from(dest_path.as_os_str());
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Calls `download_to` until it succeeds, fails with an error that is not transient,
+/// or runs out of the attempts of `config`.
+///
+/// Every retry resumes from the bytes the failed attempt left in `dest_path`.
+async fn download_with_retry(
+    client: &reqwest::Client,
+    config: &DownloadConfig,
+    url: &str,
+    token: Option<&str>,
+    dest_path: &Path,
+    cancel: Option<&CancellationToken>,
+    progress: Option<&DownloadProgressCallback>,
+) -> Result<()> {
+    let never_cancelled = CancellationToken::new();
+    let cancel = cancel.unwrap_or(&never_cancelled);
+
+    let mut backoff = config.initial_backoff;
+    let mut attempt = 1;
+    loop {
+        let error = match download_to(client, url, token, dest_path, Some(cancel), progress).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= config.max_attempts || !is_transient(&e) => return Err(e),
+            Err(e) => e,
+        };
+        tracing::warn!(
+            "Download attempt {} of {} failed, retrying in {:?}: {:#}",
+            attempt,
+            config.max_attempts,
+            backoff,
+            error
+        );
+
+        tokio::select! {
+            _ = cancel.cancelled() => {
+                return Err(TaggerError::Cancelled)
+                    .with_context(|| format!("Download cancelled: {}", url));
+            }
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// The HTTP status of a failed request, kept in the error chain so that
+/// `is_transient` can tell server errors from client errors.
+#[derive(Debug)]
+struct FailedStatus(StatusCode);
+
+impl fmt::Display for FailedStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP status {}", self.0)
+    }
+}
+
+impl std::error::Error for FailedStatus {}
+
+/// Returns whether a failed download may succeed when tried again.
+///
+/// Connection errors, timeouts, `408`, `429`, and `5xx` responses are transient; other
+/// statuses, local IO errors, and cancellation are not.
+fn is_transient(error: &anyhow::Error) -> bool {
+    if let Some(FailedStatus(status)) = error.downcast_ref::<FailedStatus>() {
+        return status.is_server_error()
+            || *status == StatusCode::REQUEST_TIMEOUT
+            || *status == StatusCode::TOO_MANY_REQUESTS;
+    }
+    matches!(
+        error.downcast_ref::<TaggerError>(),
+        Some(TaggerError::Network(_))
+    )
+}
+
+/// Downloads `url` into `dest_path`, resuming with a range request when `dest_path`
+/// already holds the start of the file.
+///
+/// Falls back to a full download when the server ignores the range (`200`) or
+/// rejects it (`416`).
+async fn download_to(
+    client: &reqwest::Client,
+    url: &str,
+    token: Option<&str>,
+    dest_path: &Path,
+    cancel: Option<&CancellationToken>,
+    progress: Option<&DownloadProgressCallback>,
+) -> Result<()> {
+    let never_cancelled = CancellationToken::new();
+    let cancel = cancel.unwrap_or(&never_cancelled);
+
+    let mut resume_from = fs::metadata(dest_path).map(|m| m.len()).unwrap_or(0);
+    let mut response = send_request(client, url, token, resume_from, cancel).await?;
+    if resume_from > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        resume_from = 0;
+        response = send_request(client, url, token, 0, cancel).await?;
+    }
+
+    let status = response.status();
+    if !status.is_success() {
+        let message = format!("Failed to download file: {} ({})", url, status);
+        return Err(anyhow::Error::new(FailedStatus(status)).context(TaggerError::Network(message)));
+    }
+
+    let resuming = resume_from > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    let mut dest = if resuming {
+        OpenOptions::new()
+            .append(true)
+            .open(dest_path)
+            .with_context(|| format!("Failed to open file at {:?}", dest_path))?
+    } else {
+        File::create(dest_path)
+            .with_context(|| format!("Failed to create file at {:?}", dest_path))?
+    };
+
+    // A partial response's length covers only the remaining bytes.
+    let mut downloaded = if resuming { resume_from } else { 0 };
+    let total = response.content_length().map(|len| len + downloaded);
+
+    loop {
+        let chunk = tokio::select! {
+            _ = cancel.cancelled() => {
+                return Err(TaggerError::Cancelled)
+                    .with_context(|| format!("Download cancelled: {}", url));
+            }
+            chunk = response.chunk() => chunk
+                .map_err(TaggerError::from)
+                .context("Failed to read chunk from response")?,
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
         dest.write_all(&chunk)
             .with_context(|| format!("Failed to write to file at {:?}", dest_path))?;
+
+        downloaded += chunk.len() as u64;
+        if let Some(progress) = progress {
+            progress(downloaded, total);
+        }
     }
 
     Ok(())
 }
 
-fn get_file_path(repo_id: &str, file_name: &str) -> PathBuf {
-    PathBuf::from(MODEL_ROOT).join(repo_id).join(file_name)
+/// Requests `url`, asking for the bytes from `range_start` on when it is non-zero and
+/// authenticating with `token` when given.
+async fn send_request(
+    client: &reqwest::Client,
+    url: &str,
+    token: Option<&str>,
+    range_start: u64,
+    cancel: &CancellationToken,
+) -> Result<reqwest::Response> {
+    let mut request = client.get(url);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    if range_start > 0 {
+        request = request.header(RANGE, format!("bytes={}-", range_start));
+    }
+
+    tokio::select! {
+        _ = cancel.cancelled() => {
+            Err(TaggerError::Cancelled).with_context(|| format!("Download cancelled: {}", url))
+        }
+        response = request.send() => {
+            Ok(response
+                .map_err(TaggerError::from)
+                .with_context(|| format!("Failed to download file from {}", url))?)
+        }
+    }
 }
 
-pub async fn get(repo_id: &str, file_path: &str) -> Result<PathBuf> {
-    let dest_path = get_file_path(repo_id, file_path);
-    if dest_path.exists() {
-        return Ok(dest_path);
+/// Sets the directory downloaded model files are cached in for this process,
+/// overriding `EROS_CACHE_DIR` and the platform cache directory.
+pub fn set_cache_root(path: PathBuf) {
+    *CACHE_ROOT.write().unwrap() = Some(path);
+}
+
+/// Returns the directory downloaded model files are cached in.
+///
+/// This is the root set with `set_cache_root`, else `$EROS_CACHE_DIR`, else a
+/// `models` directory in the platform cache directory (e.g. `~/.cache/eros/models`).
+pub fn cache_root() -> PathBuf {
+    if let Some(root) = CACHE_ROOT.read().unwrap().as_ref() {
+        return root.clone();
+    }
+    cache_root_from_env(env::var_os(CACHE_DIR_ENV))
+}
+
+/// Resolves the cache root from the value of `EROS_CACHE_DIR`.
+fn cache_root_from_env(value: Option<OsString>) -> PathBuf {
+    match value {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => ProjectDirs::from("", "", "eros")
+            .map(|dirs| dirs.cache_dir().join(MODEL_ROOT))
+            .unwrap_or_else(|| PathBuf::from(MODEL_ROOT)),
+    }
+}
+
+/// Sets the Hugging Face access token used to download from private or gated repos
+/// for this process, overriding `HF_TOKEN` and `HUGGING_FACE_HUB_TOKEN`.
+pub fn set_hf_token(token: String) {
+    *HF_TOKEN.write().unwrap() = Some(token);
+}
+
+/// Returns the Hugging Face access token sent with model downloads, if any.
+///
+/// This is the token set with `set_hf_token`, else `$HF_TOKEN`, else
+/// `$HUGGING_FACE_HUB_TOKEN`. Without a token, downloads are unauthenticated and only
+/// public repos can be read.
+pub fn hf_token() -> Option<String> {
+    if let Some(token) = HF_TOKEN.read().unwrap().as_ref() {
+        return Some(token.clone());
     }
+    hf_token_from_env(env::var(HF_TOKEN_ENV).ok(), env::var(HF_HUB_TOKEN_ENV).ok())
+}
 
-    let url = format!(
-        "https://huggingface.co/{}/resolve/main/{}",
-        repo_id, file_path
-    );
+/// Resolves the token from the values of `HF_TOKEN` and `HUGGING_FACE_HUB_TOKEN`,
+/// ignoring empty values.
+fn hf_token_from_env(token: Option<String>, hub_token: Option<String>) -> Option<String> {
+    [token, hub_token]
+        .into_iter()
+        .flatten()
+        .map(|token| token.trim().to_string())
+        .find(|token| !token.is_empty())
+}
 
-    download_file(&url, &dest_path).await?;
+/// Returns where `file_name` of `repo_id` at `revision` is cached.
+///
+/// Files of `DEFAULT_REVISION` live directly in the repo's directory; other revisions
+/// get their own `revisions/<revision>` directory so they never overwrite each other.
+fn get_file_path(cache_root: &Path, repo_id: &str, revision: &str, file_name: &str) -> PathBuf {
+    let repo_dir = cache_root.join(repo_id);
+    if revision == DEFAULT_REVISION {
+        repo_dir.join(file_name)
+    } else {
+        repo_dir
+            .join("revisions")
+            .join(encode_revision(revision))
+            .join(file_name)
+    }
+}
+
+/// Returns the Hugging Face download URL of `file_path` of `repo_id` at `revision`.
+fn resolve_url(repo_id: &str, revision: &str, file_path: &str) -> String {
+    format!(
+        "https://huggingface.co/{}/resolve/{}/{}",
+        repo_id,
+        encode_revision(revision),
+        file_path
+    )
+}
+
+/// Escapes the slashes of a revision such as `refs/pr/1`, so it stays a single URL
+/// path segment and a single directory name.
+fn encode_revision(revision: &str) -> String {
+    revision.replace('/', "%2F")
+}
+
+pub async fn get(repo_id: &str, file_path: &str) -> Result<PathBuf> {
+    get_with_cancel(repo_id, file_path, None).await
+}
+
+/// Like `get`, but aborts the download when `cancel` is triggered.
+pub async fn get_with_cancel(
+    repo_id: &str,
+    file_path: &str,
+    cancel: Option<&CancellationToken>,
+) -> Result<PathBuf> {
+    get_in(&cache_root(), repo_id, file_path, cancel).await
+}
+
+/// Like `get`, but downloads the file at `revision` (a branch, tag, or commit hash)
+/// instead of `DEFAULT_REVISION`.
+pub async fn get_revision(repo_id: &str, revision: &str, file_path: &str) -> Result<PathBuf> {
+    fetch(
+        &cache_root(),
+        repo_id,
+        revision,
+        file_path,
+        None,
+        None,
+        None,
+    )
+    .await
+}
+
+/// Like `get_with_cancel`, but caches the file under `cache_root` instead of the
+/// configured cache root.
+pub async fn get_in(
+    cache_root: &Path,
+    repo_id: &str,
+    file_path: &str,
+    cancel: Option<&CancellationToken>,
+) -> Result<PathBuf> {
+    get_verified_in(cache_root, repo_id, file_path, None, cancel).await
+}
+
+/// Like `get_in`, but when `expected_sha256` is given, checks the file's SHA-256
+/// (cached or freshly downloaded) and fails with `TaggerError::Io` on a mismatch.
+///
+/// A file that fails the check is removed so the next call downloads it again.
+pub async fn get_verified_in(
+    cache_root: &Path,
+    repo_id: &str,
+    file_path: &str,
+    expected_sha256: Option<&str>,
+    cancel: Option<&CancellationToken>,
+) -> Result<PathBuf> {
+    fetch(
+        cache_root,
+        repo_id,
+        DEFAULT_REVISION,
+        file_path,
+        expected_sha256,
+        cancel,
+        None,
+    )
+    .await
+}
+
+/// Implements `get_verified_in` for any revision, reporting download progress to
+/// `progress`.
+async fn fetch(
+    cache_root: &Path,
+    repo_id: &str,
+    revision: &str,
+    file_path: &str,
+    expected_sha256: Option<&str>,
+    cancel: Option<&CancellationToken>,
+    progress: Option<&DownloadProgressCallback>,
+) -> Result<PathBuf> {
+    let dest_path = get_file_path(cache_root, repo_id, revision, file_path);
+    if !dest_path.exists() {
+        let url = resolve_url(repo_id, revision, file_path);
+        let token = hf_token();
+        download(&url, token.as_deref(), &dest_path, cancel, progress).await?;
+    }
+
+    if let Some(expected) = expected_sha256 {
+        if let Err(e) = verify_sha256(&dest_path, expected) {
+            let _ = fs::remove_file(&dest_path);
+            return Err(e);
+        }
+    }
 
     Ok(dest_path)
 }
 
+/// Computes the SHA-256 of a file as a lowercase hex string.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).map_err(TaggerError::from)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 1024];
+    loop {
+        let count = file.read(&mut buffer).map_err(TaggerError::from)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Fails with `TaggerError::Io` unless the SHA-256 of `path` is `expected`.
+fn verify_sha256(path: &Path, expected: &str) -> Result<()> {
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(TaggerError::Io(format!(
+            "Checksum mismatch for {:?}: expected {}, got {}",
+            path, expected, actual
+        ))
+        .into());
+    }
+    Ok(())
+}
+
 /// Model for the Tagging
 pub struct TaggerModelFile {
     repo_id: String,
     model_path: String,
+    revision: Option<String>,
+    sha256: Option<String>,
 }
 
 impl TaggerModelFile {
     pub fn new(repo_id: &str) -> Self {
         Self {
             repo_id: repo_id.to_string(),
-            model_path: "model.onnx".to_string(),
+            model_path: MODEL_FILE.to_string(),
+            revision: None,
+            sha256: None,
         }
     }
 
+    /// Downloads the model at this revision (a branch, tag, or commit hash) instead of
+    /// `DEFAULT_REVISION`.
+    pub fn with_revision(mut self, revision: &str) -> Self {
+        self.revision = Some(revision.to_string());
+        self
+    }
+
+    /// Verifies the model against this SHA-256 (hex) once it is available.
+    pub fn with_sha256(mut self, sha256: &str) -> Self {
+        self.sha256 = Some(sha256.to_string());
+        self
+    }
+
     pub async fn get(&self) -> Result<PathBuf> {
-        get(&self.repo_id, &self.model_path).await
+        self.get_with_cancel(None).await
+    }
+
+    /// Like `get`, but aborts the download when `cancel` is triggered.
+    pub async fn get_with_cancel(&self, cancel: Option<&CancellationToken>) -> Result<PathBuf> {
+        self.get_with_progress(cancel, None).await
+    }
+
+    /// Like `get_with_cancel`, but reports the bytes downloaded to `progress`. Nothing
+    /// is reported when the model is already cached.
+    pub async fn get_with_progress(
+        &self,
+        cancel: Option<&CancellationToken>,
+        progress: Option<&DownloadProgressCallback>,
+    ) -> Result<PathBuf> {
+        fetch(
+            &cache_root(),
+            &self.repo_id,
+            self.revision.as_deref().unwrap_or(DEFAULT_REVISION),
+            &self.model_path,
+            self.sha256.as_deref(),
+            cancel,
+            progress,
+        )
+        .await
     }
 }
 
@@ -82,42 +620,89 @@ impl TaggerModelFile {
 pub struct TagCSVFile {
     repo_id: String,
     csv_path: String,
+    revision: Option<String>,
 }
 
 impl TagCSVFile {
     pub fn new(repo_id: &str) -> Self {
         Self {
             repo_id: repo_id.to_string(),
-            csv_path: "selected_tags.csv".to_string(),
+            csv_path: TAGS_FILE.to_string(),
+            revision: None,
+        }
+    }
+
+    /// Downloads the file at this revision instead of `DEFAULT_REVISION`.
+    pub fn with_revision(mut self, revision: &str) -> Self {
+        self.revision = Some(revision.to_string());
+        self
+    }
+
+    pub async fn get(&self) -> Result<PathBuf> {
+        let revision = self.revision.as_deref().unwrap_or(DEFAULT_REVISION);
+        get_revision(&self.repo_id, revision, &self.csv_path).await
+    }
+}
+
+/// JSON file that has the list of tags and ids, for repos without a CSV.
+pub struct TagJSONFile {
+    repo_id: String,
+    json_path: String,
+    revision: Option<String>,
+}
+
+impl TagJSONFile {
+    pub fn new(repo_id: &str) -> Self {
+        Self {
+            repo_id: repo_id.to_string(),
+            json_path: TAGS_JSON_FILE.to_string(),
+            revision: None,
         }
     }
 
+    /// Downloads the file at this revision instead of `DEFAULT_REVISION`.
+    pub fn with_revision(mut self, revision: &str) -> Self {
+        self.revision = Some(revision.to_string());
+        self
+    }
+
     pub async fn get(&self) -> Result<PathBuf> {
-        get(&self.repo_id, &self.csv_path).await
+        let revision = self.revision.as_deref().unwrap_or(DEFAULT_REVISION);
+        get_revision(&self.repo_id, revision, &self.json_path).await
     }
 }
 
 pub struct ConfigFile {
     repo_id: String,
     config_path: String,
+    revision: Option<String>,
 }
 
 impl ConfigFile {
     pub fn new(repo_id: &str) -> Self {
         Self {
             repo_id: repo_id.to_string(),
-            config_path: "config.json".to_string(),
+            config_path: CONFIG_FILE.to_string(),
+            revision: None,
         }
     }
 
+    /// Downloads the file at this revision instead of `DEFAULT_REVISION`.
+    pub fn with_revision(mut self, revision: &str) -> Self {
+        self.revision = Some(revision.to_string());
+        self
+    }
+
     pub async fn get(&self) -> Result<PathBuf> {
-        get(&self.repo_id, &self.config_path).await
+        let revision = self.revision.as_deref().unwrap_or(DEFAULT_REVISION);
+        get_revision(&self.repo_id, revision, &self.config_path).await
     }
 }
 
 pub struct PreprocessFile {
     repo_id: String,
     preprocess_path: String,
+    revision: Option<String>,
 }
 
 impl PreprocessFile {
@@ -125,11 +710,19 @@ impl PreprocessFile {
         Self {
             repo_id: repo_id.to_string(),
             preprocess_path: "preprocessor_config.json".to_string(),
+            revision: None,
         }
     }
 
+    /// Downloads the file at this revision instead of `DEFAULT_REVISION`.
+    pub fn with_revision(mut self, revision: &str) -> Self {
+        self.revision = Some(revision.to_string());
+        self
+    }
+
     pub async fn get(&self) -> Result<PathBuf> {
-        get(&self.repo_id, &self.preprocess_path).await
+        let revision = self.revision.as_deref().unwrap_or(DEFAULT_REVISION);
+        get_revision(&self.repo_id, revision, &self.preprocess_path).await
     }
 }
 
@@ -172,6 +765,395 @@ mod test {
         Runtime::new().unwrap().block_on(future)
     }
 
+    /// Serves an endless response slowly so a download never finishes on its own.
+    async fn spawn_slow_server() -> String {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let header = "HTTP/1.1 200 OK\r\nContent-Length: 1048576\r\n\r\n";
+                    if socket.write_all(header.as_bytes()).await.is_err() {
+                        return;
+                    }
+                    loop {
+                        if socket.write_all(&[0u8; 16]).await.is_err() {
+                            return;
+                        }
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    }
+                });
+            }
+        });
+        format!("http://{}/model.onnx", addr)
+    }
+
+    /// Serves `content`, answering range requests with `206` when `honor_range` is set.
+    /// Returns the URL and the range start of every request received.
+    async fn spawn_range_server(
+        content: Vec<u8>,
+        honor_range: bool,
+    ) -> (String, std::sync::Arc<std::sync::Mutex<Vec<Option<usize>>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let range_start = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+                recorded.lock().unwrap().push(range_start);
+
+                let (status, body) = match range_start {
+                    Some(start) if honor_range => ("206 Partial Content", &content[start..]),
+                    _ => ("200 OK", &content[..]),
+                };
+                let header = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                socket.write_all(header.as_bytes()).await.unwrap();
+                socket.write_all(body).await.unwrap();
+            }
+        });
+        (format!("http://{}/model.onnx", addr), requests)
+    }
+
+    /// Answers the first requests with `failures` in turn (with an empty body), then
+    /// serves `content`. Returns the URL and the lowercased head of every request
+    /// received.
+    async fn spawn_flaky_server(
+        failures: Vec<&'static str>,
+        content: Vec<u8>,
+    ) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let index = {
+                    let mut recorded = recorded.lock().unwrap();
+                    recorded.push(String::from_utf8_lossy(&request).to_lowercase());
+                    recorded.len() - 1
+                };
+
+                let (status, body) = match failures.get(index) {
+                    Some(status) => (*status, &[][..]),
+                    None => ("200 OK", &content[..]),
+                };
+                let header = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                socket.write_all(header.as_bytes()).await.unwrap();
+                socket.write_all(body).await.unwrap();
+            }
+        });
+        (format!("http://{}/model.onnx", addr), requests)
+    }
+
+    fn fast_retry_config(max_attempts: u32) -> DownloadConfig {
+        DownloadConfig {
+            max_attempts,
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_download_retries_server_errors() {
+        run_async(async {
+            let content = b"model".to_vec();
+            let failures = vec!["500 Internal Server Error", "503 Service Unavailable"];
+            let (url, requests) = spawn_flaky_server(failures, content.clone()).await;
+            let temp_dir = tempfile::tempdir().unwrap();
+            let dest_path = temp_dir.path().join("model.onnx");
+
+            let config = fast_retry_config(3);
+            let client = build_client(&config).unwrap();
+            download_with_retry(&client, &config, &url, None, &dest_path, None, None)
+                .await
+                .unwrap();
+            assert_eq!(fs::read(&dest_path).unwrap(), content);
+            assert_eq!(requests.lock().unwrap().len(), 3);
+
+            // Gives up once the attempts run out.
+            let failures = vec!["502 Bad Gateway"; 3];
+            let (url, requests) = spawn_flaky_server(failures, content).await;
+            let config = fast_retry_config(2);
+            let err = download_with_retry(&client, &config, &url, None, &dest_path, None, None)
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<TaggerError>(),
+                Some(TaggerError::Network(_))
+            ));
+            assert_eq!(requests.lock().unwrap().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_download_does_not_retry_not_found() {
+        run_async(async {
+            let (url, requests) = spawn_flaky_server(vec!["404 Not Found"], Vec::new()).await;
+            let temp_dir = tempfile::tempdir().unwrap();
+            let dest_path = temp_dir.path().join("model.onnx");
+
+            let config = fast_retry_config(3);
+            let client = build_client(&config).unwrap();
+            let err = download_with_retry(&client, &config, &url, None, &dest_path, None, None)
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<TaggerError>(),
+                Some(TaggerError::Network(_))
+            ));
+            assert!(!is_transient(&err));
+            assert_eq!(requests.lock().unwrap().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_download_sends_bearer_token() {
+        run_async(async {
+            let (url, requests) = spawn_flaky_server(Vec::new(), b"model".to_vec()).await;
+            let temp_dir = tempfile::tempdir().unwrap();
+
+            download(
+                &url,
+                Some("hf_secret"),
+                &temp_dir.path().join("a"),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+            download(&url, None, &temp_dir.path().join("b"), None, None)
+                .await
+                .unwrap();
+
+            let requests = requests.lock().unwrap();
+            assert!(requests[0].contains("authorization: bearer hf_secret\r\n"));
+            assert!(!requests[1].contains("authorization"));
+        });
+    }
+
+    #[test]
+    fn test_download_resumes_part_file() {
+        run_async(async {
+            let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+            for honor_range in [true, false] {
+                let (url, requests) = spawn_range_server(content.clone(), honor_range).await;
+                let temp_dir = tempfile::tempdir().unwrap();
+                let dest_path = temp_dir.path().join("model.onnx");
+                fs::write(part_path(&dest_path), &content[..4_000]).unwrap();
+
+                download_file(&url, &dest_path).await.unwrap();
+
+                assert_eq!(fs::read(&dest_path).unwrap(), content);
+                assert!(!part_path(&dest_path).exists());
+                assert_eq!(*requests.lock().unwrap(), vec![Some(4_000)]);
+            }
+        });
+    }
+
+    #[test]
+    fn test_download_reports_progress() {
+        run_async(async {
+            let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+            let (url, _) = spawn_range_server(content.clone(), true).await;
+            let temp_dir = tempfile::tempdir().unwrap();
+            let dest_path = temp_dir.path().join("model.onnx");
+            fs::write(part_path(&dest_path), &content[..4_000]).unwrap();
+
+            let updates = std::sync::Mutex::new(Vec::new());
+            let progress = |downloaded: u64, total: Option<u64>| {
+                updates.lock().unwrap().push((downloaded, total));
+            };
+            download_file_with_progress(&url, &dest_path, None, Some(&progress))
+                .await
+                .unwrap();
+
+            // The resumed bytes count towards both the progress and the total.
+            let updates = updates.into_inner().unwrap();
+            assert!(updates.iter().all(|&(downloaded, _)| downloaded > 4_000));
+            assert!(updates.iter().all(|&(_, total)| total == Some(10_000)));
+            assert!(updates.windows(2).all(|w| w[0].0 < w[1].0));
+            assert_eq!(updates.last(), Some(&(10_000, Some(10_000))));
+        });
+    }
+
+    #[test]
+    fn test_sha256_verification() {
+        run_async(async {
+            let cache_root = tempfile::tempdir().unwrap();
+            let cached = cache_root.path().join("owner/repo/model.onnx");
+            fs::create_dir_all(cached.parent().unwrap()).unwrap();
+            fs::write(&cached, b"abc").unwrap();
+
+            let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+            assert_eq!(sha256_file(&cached).unwrap(), sha256);
+
+            let path = get_verified_in(
+                cache_root.path(),
+                "owner/repo",
+                "model.onnx",
+                Some(sha256),
+                None,
+            )
+            .await
+            .unwrap();
+            assert_eq!(path, cached);
+
+            let err = get_verified_in(
+                cache_root.path(),
+                "owner/repo",
+                "model.onnx",
+                Some("00"),
+                None,
+            )
+            .await
+            .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<TaggerError>(),
+                Some(TaggerError::Io(_))
+            ));
+            assert!(!cached.exists());
+        });
+    }
+
+    #[test]
+    fn test_download_cancelled() {
+        run_async(async {
+            let url = spawn_slow_server().await;
+            let temp_dir = tempfile::tempdir().unwrap();
+            let dest_path = temp_dir.path().join("model.onnx");
+
+            let cancel = CancellationToken::new();
+            let canceller = cancel.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                canceller.cancel();
+            });
+
+            let result = download_file_with_cancel(&url, &dest_path, Some(&cancel)).await;
+
+            assert!(matches!(
+                result.unwrap_err().downcast_ref::<TaggerError>(),
+                Some(TaggerError::Cancelled)
+            ));
+            assert!(!dest_path.exists());
+            assert!(!part_path(&dest_path).exists());
+        });
+    }
+
+    #[test]
+    fn test_cache_root_from_env() {
+        assert_eq!(
+            cache_root_from_env(Some(OsString::from("/tmp/eros-cache"))),
+            PathBuf::from("/tmp/eros-cache")
+        );
+        assert!(cache_root_from_env(None).ends_with(MODEL_ROOT));
+        assert!(cache_root_from_env(Some(OsString::new())).ends_with(MODEL_ROOT));
+    }
+
+    #[test]
+    fn test_revision_paths() {
+        let root = Path::new("/cache");
+        assert_eq!(
+            get_file_path(root, "owner/repo", DEFAULT_REVISION, "model.onnx"),
+            PathBuf::from("/cache/owner/repo/model.onnx")
+        );
+        assert_eq!(
+            get_file_path(root, "owner/repo", "v1.0", "model.onnx"),
+            PathBuf::from("/cache/owner/repo/revisions/v1.0/model.onnx")
+        );
+        assert_eq!(
+            get_file_path(root, "owner/repo", "refs/pr/1", "model.onnx"),
+            PathBuf::from("/cache/owner/repo/revisions/refs%2Fpr%2F1/model.onnx")
+        );
+        assert_eq!(
+            resolve_url("owner/repo", DEFAULT_REVISION, "onnx/model.onnx"),
+            "https://huggingface.co/owner/repo/resolve/main/onnx/model.onnx"
+        );
+        assert_eq!(
+            resolve_url("owner/repo", "refs/pr/1", "model.onnx"),
+            "https://huggingface.co/owner/repo/resolve/refs%2Fpr%2F1/model.onnx"
+        );
+    }
+
+    #[test]
+    fn test_revisions_are_cached_separately() {
+        run_async(async {
+            let cache_root = tempfile::tempdir().unwrap();
+            for (revision, content) in [(DEFAULT_REVISION, "main"), ("abc123", "pinned")] {
+                let path = get_file_path(cache_root.path(), "owner/repo", revision, "tags.csv");
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, content).unwrap();
+            }
+
+            for (revision, content) in [(DEFAULT_REVISION, "main"), ("abc123", "pinned")] {
+                let path = fetch(
+                    cache_root.path(),
+                    "owner/repo",
+                    revision,
+                    "tags.csv",
+                    None,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+                assert_eq!(fs::read_to_string(path).unwrap(), content);
+            }
+        });
+    }
+
+    #[test]
+    fn test_hf_token_from_env() {
+        let token = |value: &str| Some(value.to_string());
+        assert_eq!(
+            hf_token_from_env(token("hf_a"), token("hf_b")),
+            token("hf_a")
+        );
+        assert_eq!(hf_token_from_env(None, token("hf_b")), token("hf_b"));
+        assert_eq!(
+            hf_token_from_env(token(" "), token("hf_b\n")),
+            token("hf_b")
+        );
+        assert_eq!(hf_token_from_env(None, None), None);
+    }
+
     #[test]
     fn test_get_model() {
         let repo_id = "SmilingWolf/wd-swinv2-tagger-v3";
@@ -180,7 +1162,7 @@ mod test {
         assert!(path.exists());
         assert_eq!(
             path,
-            PathBuf::from("models/SmilingWolf/wd-swinv2-tagger-v3/model.onnx")
+            cache_root().join("SmilingWolf/wd-swinv2-tagger-v3/model.onnx")
         );
     }
 
@@ -192,7 +1174,7 @@ mod test {
         assert!(path.exists());
         assert_eq!(
             path,
-            PathBuf::from("models/SmilingWolf/wd-swinv2-tagger-v3/selected_tags.csv")
+            cache_root().join("SmilingWolf/wd-swinv2-tagger-v3/selected_tags.csv")
         );
     }
 
@@ -204,7 +1186,7 @@ mod test {
         assert!(path.exists());
         assert_eq!(
             path,
-            PathBuf::from("models/SmilingWolf/wd-swinv2-tagger-v3/config.json")
+            cache_root().join("SmilingWolf/wd-swinv2-tagger-v3/config.json")
         );
     }
 
@@ -214,7 +1196,7 @@ mod test {
         assert!(path.exists());
         assert_eq!(
             path,
-            PathBuf::from("models/AdamCodd/vit-base-nsfw-detector/onnx/model.onnx")
+            cache_root().join("AdamCodd/vit-base-nsfw-detector/onnx/model.onnx")
         );
     }
-}
\ No newline at end of file
+}
@@ -17,12 +17,19 @@
 //! - `pipeline`: The main entry point for using the tagging functionality.
 //! - `tagger`: Handles the ONNX model and session management.
 //! - `processor`: Provides tools for image preprocessing.
-//! - `tags`: Manages tag labels and their categories.
+//! - `tags`: Manages tag labels and their categories, and formats them as captions.
 //! - `config`: Defines the data structures for model configuration.
+//! - `dedup`: Groups near-duplicate images by perceptual hash.
+//! - `similarity`: Finds images with similar embeddings by cosine similarity.
+//! - `export`: Writes tagging results to JSON sidecars and CSV files.
 //! - `error`: Contains the error types for the library.
 //! - `prelude`: A collection of the most commonly used types.
+//! - `xmp`: Writes tags into image XMP metadata.
 
 pub mod config;
+pub mod dedup;
+pub mod error;
+pub mod export;
 pub mod file;
 pub mod pipeline;
 pub mod prelude;
@@ -30,5 +37,7 @@ pub mod prelude;
 pub mod optimizer;
 pub mod processor;
 pub mod rating;
+pub mod similarity;
 pub mod tagger;
 pub mod tags;
+pub mod xmp;
@@ -1,26 +1,30 @@
 //! This module provides functions for optimizing media files.
 //!
-//! It includes utilities for compressing images (PNG, JPEG) and videos (MP4)
+//! It includes utilities for compressing images (PNG, JPEG, WebP) and videos (MP4)
 //! to reduce their file size while maintaining quality. The optimizations are
 //! designed to be applied after all other processing is complete.
 
 use anyhow::{Context, Result};
+use image::{codecs::webp::WebPDecoder, DynamicImage, ImageFormat};
 use mozjpeg::{ColorSpace, Compress, Decompress};
 use oxipng::{optimize, InFile, Options, OutFile};
 use rayon::prelude::*;
 use std::{
     fs,
+    io::Cursor,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 use tempfile::NamedTempFile;
-use walkdir::WalkDir;
+
+use crate::prelude::walk_files;
 
 /// Optimizes a single image file.
 ///
-/// This function will re-compress JPEGs and PNGs to reduce their file size.
+/// This function will re-compress JPEGs, PNGs, and WebPs to reduce their file size.
 /// It saves the optimized file to a temporary location and then replaces the original
 /// to ensure the operation is atomic.
-fn optimize_image(path: &Path) -> Result<()> {
+fn optimize_image(path: &Path, options: &OptimizerOptions) -> Result<()> {
     let extension = path
         .extension()
         .and_then(|s| s.to_str())
@@ -28,14 +32,15 @@ fn optimize_image(path: &Path) -> Result<()> {
         .to_lowercase();
 
     match extension.as_str() {
-        "jpg" | "jpeg" => optimize_jpeg(path),
-        "png" => optimize_png(path),
+        "jpg" | "jpeg" => optimize_jpeg(path, options.jpeg_quality, options.chroma_subsampling),
+        "png" => optimize_png(path, options.png_level),
+        "webp" => optimize_webp(path, options.webp_quality),
         _ => Ok(()),
     }
 }
 
 /// Optimizes a JPEG file by re-compressing it.
-fn optimize_jpeg(path: &Path) -> Result<()> {
+fn optimize_jpeg(path: &Path, quality: f32, chroma_subsampling: ChromaSubsampling) -> Result<()> {
     let file_data =
         fs::read(path).with_context(|| format!("Failed to read image file: {:?}", path))?;
 
@@ -45,8 +50,10 @@ fn optimize_jpeg(path: &Path) -> Result<()> {
     let (width, height) = (image.width(), image.height());
 
     let mut compress = Compress::new(ColorSpace::JCS_RGB);
-    compress.set_quality(75.0);
+    compress.set_quality(quality);
     compress.set_size(width, height);
+    let chroma_pixel_size = chroma_subsampling.chroma_pixel_size();
+    compress.set_chroma_sampling_pixel_sizes(chroma_pixel_size, chroma_pixel_size);
 
     let mut comp = compress
         .start_compress(Vec::new())
@@ -70,9 +77,9 @@ fn optimize_jpeg(path: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Optimizes a PNG file using `oxipng`.
-fn optimize_png(path: &Path) -> Result<()> {
-    let options = Options::from_preset(2);
+/// Optimizes a PNG file using `oxipng` at the given preset level (0-6).
+fn optimize_png(path: &Path, level: u8) -> Result<()> {
+    let options = Options::from_preset(level);
     let in_file = InFile::Path(path.to_path_buf());
     let temp_file = NamedTempFile::new_in(
         path.parent()
@@ -94,6 +101,41 @@ fn optimize_png(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Optimizes a WebP file by re-encoding it lossily with `libwebp`.
+///
+/// Animated WebPs are left untouched, since only their first frame would survive.
+fn optimize_webp(path: &Path, quality: f32) -> Result<()> {
+    let file_data =
+        fs::read(path).with_context(|| format!("Failed to read image file: {:?}", path))?;
+
+    if WebPDecoder::new(Cursor::new(&file_data))?.has_animation() {
+        return Ok(());
+    }
+
+    let image = image::load_from_memory_with_format(&file_data, ImageFormat::WebP)
+        .with_context(|| "Failed to decode WebP")?;
+    let image = if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(image.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+    };
+    let compressed_data = webp::Encoder::from_image(&image)
+        .map_err(|e| anyhow::anyhow!("Failed to create WebP encoder: {}", e))?
+        .encode(quality);
+
+    let temp_file = NamedTempFile::new_in(
+        path.parent()
+            .ok_or_else(|| anyhow::anyhow!("Failed to get parent directory"))?,
+    )?;
+    fs::write(temp_file.path(), &*compressed_data)?;
+    temp_file
+        .persist(path)
+        .map_err(|e| e.error)
+        .with_context(|| format!("Failed to replace original file: {:?}", path))?;
+
+    Ok(())
+}
+
 use ffmpeg_next as ffmpeg;
 
 /// Encodes a single frame to the output context.
@@ -130,8 +172,12 @@ fn flush_encoder(
 }
 
 
-/// Optimizes a single video file by re-encoding it with H.264 and AAC.
-fn optimize_video(path: &Path) -> Result<()> {
+/// Optimizes a single video file by re-encoding its video stream with
+/// `options.video_codec`.
+///
+/// Audio streams are copied through without re-encoding when `options.copy_audio`
+/// is set and dropped otherwise. Other streams (e.g. subtitles) are always copied.
+pub fn optimize_video(path: &Path, options: &OptimizerOptions) -> Result<()> {
     let temp_file = tempfile::Builder::new()
         .suffix(".mp4")
         .tempfile_in(path.parent().ok_or_else(|| anyhow::anyhow!("Invalid path"))?)
@@ -147,13 +193,19 @@ fn optimize_video(path: &Path) -> Result<()> {
         .map(|s| s.index());
 
     let (stream_mapping, mut video_encoder, mut sws_context) =
-        setup_streams(&mut ictx, &mut octx, best_video_stream_index)?;
+        setup_streams(&mut ictx, &mut octx, best_video_stream_index, options)?;
 
     octx.write_header()?;
 
+    // The muxer may change the output time bases while writing the header.
+    let output_time_bases: Vec<ffmpeg::Rational> =
+        octx.streams().map(|stream| stream.time_base()).collect();
+
     for (stream, packet) in ictx.packets() {
         let istream_index = stream.index();
-        let ostream_index = stream_mapping[istream_index];
+        let Some(ostream_index) = stream_mapping[istream_index] else {
+            continue;
+        };
 
         if Some(istream_index) == best_video_stream_index {
             if let (Some((ref mut enc, ref mut dec)), Some(ref mut scaler)) =
@@ -171,14 +223,18 @@ fn optimize_video(path: &Path) -> Result<()> {
             }
         } else {
             let mut p = packet.clone();
+            p.rescale_ts(stream.time_base(), output_time_bases[ostream_index]);
+            p.set_position(-1);
             p.set_stream(ostream_index);
             p.write_interleaved(&mut octx)?;
         }
     }
 
-    if let (Some((ref mut enc, ref mut dec)), Some(ref mut scaler)) =
-        (video_encoder.as_mut(), sws_context.as_mut())
-    {
+    if let (Some((ref mut enc, ref mut dec)), Some(ref mut scaler), Some(ostream_index)) = (
+        video_encoder.as_mut(),
+        sws_context.as_mut(),
+        best_video_stream_index.and_then(|index| stream_mapping[index]),
+    ) {
         dec.send_eof()?;
         let mut decoded = ffmpeg::frame::Video::empty();
         let time_base = dec.time_base();
@@ -186,19 +242,9 @@ fn optimize_video(path: &Path) -> Result<()> {
             let mut scaled = ffmpeg::frame::Video::empty();
             scaler.run(&decoded, &mut scaled)?;
             scaled.set_pts(decoded.pts());
-            encode_frame(
-                enc,
-                &scaled,
-                &mut octx,
-                stream_mapping[best_video_stream_index.unwrap()],
-                time_base,
-            )?;
+            encode_frame(enc, &scaled, &mut octx, ostream_index, time_base)?;
         }
-        flush_encoder(
-            enc,
-            &mut octx,
-            stream_mapping[best_video_stream_index.unwrap()],
-        )?;
+        flush_encoder(enc, &mut octx, ostream_index)?;
     }
 
     octx.write_trailer()?;
@@ -216,15 +262,16 @@ fn setup_streams(
     ictx: &mut ffmpeg::format::context::Input,
     octx: &mut ffmpeg::format::context::Output,
     best_video_stream_index: Option<usize>,
+    options: &OptimizerOptions,
 ) -> Result<(
-    Vec<usize>,
+    Vec<Option<usize>>,
     Option<(
         ffmpeg::encoder::Video,
         ffmpeg::decoder::video::Video,
     )>,
     Option<ffmpeg::software::scaling::Context>,
 )> {
-    let mut stream_mapping = vec![0; ictx.nb_streams() as usize];
+    let mut stream_mapping = vec![None; ictx.nb_streams() as usize];
     let mut video_encoder = None;
     let mut sws_context = None;
 
@@ -235,7 +282,7 @@ fn setup_streams(
 
     for (istream_index, istream) in ictx.streams().enumerate() {
         if Some(istream_index) == best_video_stream_index {
-            let mut ostream = octx.add_stream(ffmpeg::encoder::find(ffmpeg::codec::Id::MPEG4))?;
+            let mut ostream = octx.add_stream(ffmpeg::encoder::find(options.video_codec))?;
             let mut enc = ffmpeg::codec::context::Context::from_parameters(ostream.parameters())?
                 .encoder()
                 .video()?;
@@ -259,7 +306,7 @@ fn setup_streams(
                 enc.set_flags(ffmpeg::codec::flag::Flags::GLOBAL_HEADER);
             }
 
-            let opened_encoder = enc.open_as(ffmpeg::codec::Id::MPEG4)?;
+            let opened_encoder = enc.open_as(options.video_codec)?;
             ostream.set_parameters(&opened_encoder);
 
             let scaler = ffmpeg::software::scaling::Context::get(
@@ -272,47 +319,205 @@ fn setup_streams(
                 ffmpeg::software::scaling::flag::Flags::BILINEAR,
             )?;
 
-            stream_mapping[istream_index] = ostream.index();
+            stream_mapping[istream_index] = Some(ostream.index());
             video_encoder = Some((opened_encoder, dec));
             sws_context = Some(scaler);
+        } else if istream.parameters().medium() == ffmpeg::media::Type::Audio
+            && !options.copy_audio
+        {
+            continue;
         } else {
             let mut ostream = octx.add_stream(None)?;
             ostream.set_parameters(istream.parameters());
-            stream_mapping[istream_index] = ostream.index();
+            // Let the muxer pick the tag; the input container's may not be valid here.
+            unsafe {
+                (*ostream.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+            stream_mapping[istream_index] = Some(ostream.index());
         }
     }
 
     Ok((stream_mapping, video_encoder, sws_context))
 }
 
+/// The chroma subsampling used when re-encoding JPEGs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChromaSubsampling {
+    /// No subsampling; best for text, line art, and fine colored detail.
+    Yuv444,
+    /// Chroma halved horizontally.
+    Yuv422,
+    /// Chroma halved in both directions (the encoder's default).
+    #[default]
+    Yuv420,
+}
+
+impl ChromaSubsampling {
+    /// The size of one chroma sample in luma pixels, as `(horizontal, vertical)`.
+    fn chroma_pixel_size(self) -> (u8, u8) {
+        match self {
+            ChromaSubsampling::Yuv444 => (1, 1),
+            ChromaSubsampling::Yuv422 => (2, 1),
+            ChromaSubsampling::Yuv420 => (2, 2),
+        }
+    }
+}
+
+/// Options controlling how media files are optimized.
+#[derive(Debug, Clone)]
+pub struct OptimizerOptions {
+    /// The maximum number of files optimized at the same time.
+    pub max_parallel: usize,
+    /// The chroma subsampling used for JPEG output.
+    pub chroma_subsampling: ChromaSubsampling,
+    /// The JPEG quality (0-100).
+    pub jpeg_quality: f32,
+    /// The `oxipng` preset level (0-6); higher is slower and smaller.
+    pub png_level: u8,
+    /// The lossy WebP quality (0-100).
+    pub webp_quality: f32,
+    /// The codec the video stream is re-encoded with.
+    pub video_codec: ffmpeg::codec::Id,
+    /// Copy audio streams into optimized videos unchanged; when unset they are dropped.
+    pub copy_audio: bool,
+    /// Only list the files that would be optimized, without touching any.
+    pub dry_run: bool,
+    /// Directories whose files are left untouched.
+    pub exclude: Vec<PathBuf>,
+}
+
+impl Default for OptimizerOptions {
+    fn default() -> Self {
+        Self {
+            max_parallel: num_cpus::get(),
+            chroma_subsampling: ChromaSubsampling::default(),
+            jpeg_quality: 75.0,
+            png_level: 2,
+            webp_quality: 75.0,
+            video_codec: ffmpeg::codec::Id::MPEG4,
+            copy_audio: true,
+            dry_run: false,
+            exclude: Vec::new(),
+        }
+    }
+}
+
 /// Optimizes all media files in the given directories.
 pub async fn optimize_media_in_dirs(dirs: &[PathBuf]) -> Result<()> {
-    let media_files: Vec<PathBuf> = dirs
-        .par_iter()
-        .flat_map(|dir| {
-            WalkDir::new(dir)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|e| e.path().is_file())
-                .map(|e| e.path().to_path_buf())
-                .collect::<Vec<PathBuf>>()
-        })
-        .collect();
-
-    media_files.par_iter().try_for_each(|path| {
+    optimize_media_in_dirs_with_options(dirs, &OptimizerOptions::default()).await?;
+    Ok(())
+}
+
+/// Optimizes all media files in the given directories using the given options,
+/// returning the paths of the files that were optimized.
+///
+/// Images that fail to optimize are logged and left as they were.
+///
+/// Work runs on a dedicated thread pool of `max_parallel` threads rather than the
+/// global Rayon pool, so optimization can be throttled on a shared machine.
+pub async fn optimize_media_in_dirs_with_options(
+    dirs: &[PathBuf],
+    options: &OptimizerOptions,
+) -> Result<Vec<PathBuf>> {
+    optimize_media_in_dirs_with_progress(dirs, options, |_, _| {}).await
+}
+
+/// Optimizes all media files in the given directories on a pool of `max_threads`
+/// threads, calling `progress(done, total)` as each file completes.
+pub async fn optimize_media_in_dirs_with(
+    dirs: &[PathBuf],
+    max_threads: usize,
+    progress: impl Fn(usize, usize) + Sync,
+) -> Result<Vec<PathBuf>> {
+    let options = OptimizerOptions {
+        max_parallel: max_threads,
+        ..Default::default()
+    };
+    optimize_media_in_dirs_with_progress(dirs, &options, progress).await
+}
+
+/// Like `optimize_media_in_dirs_with_options`, but calls `progress(done, total)`
+/// from the worker threads as each file completes.
+///
+/// With `options.dry_run`, returns the files that would be optimized without calling
+/// `progress`.
+pub async fn optimize_media_in_dirs_with_progress(
+    dirs: &[PathBuf],
+    options: &OptimizerOptions,
+    progress: impl Fn(usize, usize) + Sync,
+) -> Result<Vec<PathBuf>> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.max_parallel.max(1))
+        .build()
+        .context("Failed to build optimizer thread pool")?;
+
+    pool.install(|| {
+        let media_files: Vec<(PathBuf, MediaKind)> = dirs
+            .par_iter()
+            .flat_map(|dir| {
+                walk_files(dir, &options.exclude)
+                    .into_iter()
+                    .filter_map(|path| {
+                        let kind = MediaKind::of(&path)?;
+                        Some((path, kind))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        if options.dry_run {
+            return Ok(media_files.into_iter().map(|(path, _)| path).collect());
+        }
+
+        let total = media_files.len();
+        let done = AtomicUsize::new(0);
+
+        let optimized: Vec<Option<PathBuf>> = media_files
+            .into_par_iter()
+            .map(|(path, kind)| -> Result<Option<PathBuf>> {
+                let optimized = match kind {
+                    // Images are replaced atomically, so one that fails (e.g. because
+                    // it is corrupt) is left as it was rather than ending the pass.
+                    MediaKind::Image => match optimize_image(&path, options) {
+                        Ok(()) => Some(path),
+                        Err(e) => {
+                            tracing::warn!("Failed to optimize image {:?}: {:#}", path, e);
+                            None
+                        }
+                    },
+                    MediaKind::Video => {
+                        optimize_video(&path, options)
+                            .with_context(|| format!("Failed to optimize video: {:?}", path))?;
+                        Some(path)
+                    }
+                };
+                progress(done.fetch_add(1, Ordering::Relaxed) + 1, total);
+                Ok(optimized)
+            })
+            .collect::<Result<_>>()?;
+        Ok(optimized.into_iter().flatten().collect())
+    })
+}
+
+/// The kinds of files the optimizer handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MediaKind {
+    Image,
+    Video,
+}
+
+impl MediaKind {
+    /// Classifies `path` by its extension, or `None` if it is not optimized.
+    fn of(path: &Path) -> Option<Self> {
         let extension = path
             .extension()
             .and_then(|s| s.to_str())
             .unwrap_or_default()
             .to_lowercase();
         match extension.as_str() {
-            "jpg" | "jpeg" | "png" => {
-                optimize_image(path).with_context(|| format!("Failed to optimize image: {:?}", path))
-            }
-            "mp4" | "mov" | "avi" | "mkv" | "webm" => {
-                optimize_video(path).with_context(|| format!("Failed to optimize video: {:?}", path))
-            }
-            _ => Ok(()),
+            "jpg" | "jpeg" | "png" | "webp" => Some(Self::Image),
+            "mp4" | "mov" | "avi" | "mkv" | "webm" => Some(Self::Video),
+            _ => None,
         }
-    })
-}
\ No newline at end of file
+    }
+}
@@ -8,22 +8,78 @@
 //! for representing the output.
 
 use anyhow::{Context, Result};
-use image::DynamicImage;
+use image::{imageops::FilterType, DynamicImage};
 use indexmap::IndexMap;
-use itertools::Itertools;
+use ndarray::{Array, Ix4};
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio_util::sync::CancellationToken;
 
 use crate::{
-    processor::{ImagePreprocessor, ImageProcessor},
+    config::ModelConfig,
+    error::TaggerError,
+    file::{self, TaggerModelFile},
+    processor::{
+        decode_image, open_image, CenterCropPreprocessor, ImagePreprocessor, ImageProcessor,
+        ResizeMode,
+    },
+    rating::{softmax, Rating, RatingModel},
     tagger::{Device, TaggerModel},
-    tags::{LabelTags, TagCategory},
+    tags::{implications::TagImplications, LabelTags, TagCategory},
 };
 
+/// The rating tags of WD taggers that mark an image as not safe for work.
+pub const EXPLICIT_RATING_TAGS: [&str; 2] = ["questionable", "explicit"];
+
+/// The default `TaggingPipeline::max_batch_size`.
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 16;
+
+/// The number of bytes in a megabyte, for download progress messages.
+const MEGABYTE: u64 = 1024 * 1024;
+
 /// A callback function for reporting progress.
 ///
 /// The first argument is the progress percentage (0.0 to 1.0), and the second
 /// is a status message.
 pub type ProgressCallback = Box<dyn Fn(f32, String) + Send + Sync>;
 
+/// The preprocessing settings for `TaggingPipeline::from_local`.
+#[derive(Debug, Clone)]
+pub enum LocalPreprocessor {
+    /// A `preprocessor_config.json` (or model `config.json`) on disk.
+    ConfigFile(PathBuf),
+    /// An already-configured preprocessor.
+    Values(ImagePreprocessor),
+}
+
+impl From<PathBuf> for LocalPreprocessor {
+    fn from(path: PathBuf) -> Self {
+        Self::ConfigFile(path)
+    }
+}
+
+impl From<&Path> for LocalPreprocessor {
+    fn from(path: &Path) -> Self {
+        Self::ConfigFile(path.to_path_buf())
+    }
+}
+
+impl From<ImagePreprocessor> for LocalPreprocessor {
+    fn from(preprocessor: ImagePreprocessor) -> Self {
+        Self::Values(preprocessor)
+    }
+}
+
+impl From<CenterCropPreprocessor> for LocalPreprocessor {
+    fn from(preprocessor: CenterCropPreprocessor) -> Self {
+        Self::Values(preprocessor.into_inner())
+    }
+}
+
 /// An end-to-end pipeline for image tagging.
 #[derive(Debug)]
 pub struct TaggingPipeline {
@@ -34,12 +90,279 @@ pub struct TaggingPipeline {
     /// The set of labels the model can predict.
     pub tags: LabelTags,
     /// The confidence threshold for including a tag in the results.
+    ///
+    /// A threshold of `0.0` (or below) disables filtering, so every tag of each
+    /// category is returned, sorted by descending confidence.
     pub threshold: f32,
+    /// Per-category thresholds that take precedence over `threshold` when set.
+    pub thresholds: Option<CategoryThresholds>,
+    /// When set, the general-tag cutoff is derived from each image's own scores
+    /// instead of `threshold`/`thresholds`.
+    pub adaptive_threshold: Option<AdaptiveThreshold>,
+    /// The most images `predict_batch` runs through the model at once. Larger batches
+    /// are split, which bounds the size of the input tensor.
+    pub max_batch_size: usize,
+    /// The function applied to the model's outputs to turn them into the per-tag
+    /// probabilities that thresholds are compared against.
+    pub activation: Activation,
+    /// Tags that are never returned, whatever their confidence. Names are as in the
+    /// tag list, e.g. `simple_background`.
+    pub ignore_tags: HashSet<String>,
+    /// When set, only these tags are returned, whatever the confidence of the others.
+    pub only_tags: Option<HashSet<String>>,
+}
+
+/// The function that turns a model's raw outputs into probabilities.
+///
+/// WD taggers already end in a sigmoid, so their outputs are used as they are. Models
+/// exported without their final activation produce logits, which must be converted
+/// before a threshold means anything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Activation {
+    /// Uses the outputs as they are.
+    #[default]
+    None,
+    /// Maps each output to `0.0..=1.0` independently, for multi-label models.
+    Sigmoid,
+    /// Normalizes each image's outputs to sum to `1.0`, for multi-class models.
+    Softmax,
+}
+
+impl Activation {
+    /// Applies the activation to the outputs of one image.
+    pub fn apply(&self, outputs: Vec<f32>) -> Vec<f32> {
+        match self {
+            Activation::None => outputs,
+            Activation::Sigmoid => outputs
+                .into_iter()
+                .map(|x| 1.0 / (1.0 + (-x).exp()))
+                .collect(),
+            Activation::Softmax => softmax(outputs.into_iter()),
+        }
+    }
+}
+
+/// A per-image cutoff derived from the distribution of that image's scores.
+///
+/// This keeps tag counts more consistent across images whose scores are spread
+/// very differently, where a single fixed threshold would over- or under-tag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdaptiveThreshold {
+    /// Keeps tags scoring at least `mean + k * stddev`.
+    MeanStdDev { k: f32 },
+    /// Keeps tags scoring at or above the given percentile (`0.0` to `1.0`).
+    Percentile(f32),
+}
+
+impl AdaptiveThreshold {
+    /// Returns the cutoff for an image whose scores are `scores`.
+    pub fn cutoff(&self, scores: &[f32]) -> f32 {
+        if scores.is_empty() {
+            return 0.0;
+        }
+        match *self {
+            AdaptiveThreshold::MeanStdDev { k } => {
+                let n = scores.len() as f32;
+                let mean = scores.iter().sum::<f32>() / n;
+                let variance = scores.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / n;
+                mean + k * variance.sqrt()
+            }
+            AdaptiveThreshold::Percentile(p) => {
+                let mut sorted = scores.to_vec();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                let idx = (p.clamp(0.0, 1.0) * (sorted.len() - 1) as f32).round() as usize;
+                sorted[idx]
+            }
+        }
+    }
+}
+
+/// Confidence thresholds for each tag category.
+///
+/// As with `TaggingPipeline::threshold`, a value of `0.0` (or below) disables
+/// filtering for that category.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CategoryThresholds {
+    pub rating: f32,
+    pub character: f32,
+    /// Also applies to the artist, copyright, and meta categories.
+    pub general: f32,
+}
+
+impl CategoryThresholds {
+    /// Returns the threshold for `category`. Categories without their own field
+    /// (artist, copyright, and meta) use the general threshold.
+    pub fn for_category(&self, category: &TagCategory) -> f32 {
+        match category {
+            TagCategory::Rating => self.rating,
+            TagCategory::Character => self.character,
+            _ => self.general,
+        }
+    }
+}
+
+impl From<f32> for CategoryThresholds {
+    /// Applies the same threshold to every category.
+    fn from(threshold: f32) -> Self {
+        Self {
+            rating: threshold,
+            character: threshold,
+            general: threshold,
+        }
+    }
+}
+
+/// Configures and builds a `TaggingPipeline` from a model on the Hugging Face Hub.
+///
+/// Unset options keep the defaults of `from_pretrained`.
+pub struct TaggingPipelineBuilder {
+    model_repo: Option<String>,
+    devices: Vec<Device>,
+    threshold: f32,
+    thresholds: Option<CategoryThresholds>,
+    max_batch_size: usize,
+    resize_filter: Option<FilterType>,
+    resize_mode: ResizeMode,
+    activation: Activation,
+    ignore_tags: HashSet<String>,
+    only_tags: Option<HashSet<String>>,
+    progress_callback: Option<ProgressCallback>,
+    cancel: Option<CancellationToken>,
+}
+
+impl Default for TaggingPipelineBuilder {
+    fn default() -> Self {
+        Self {
+            model_repo: None,
+            devices: Device::cpu(),
+            threshold: 0.5,
+            thresholds: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            resize_filter: None,
+            resize_mode: ResizeMode::Pad,
+            activation: Activation::None,
+            ignore_tags: HashSet::new(),
+            only_tags: None,
+            progress_callback: None,
+            cancel: None,
+        }
+    }
+}
+
+impl TaggingPipelineBuilder {
+    /// Creates a builder that runs on the CPU with a threshold of `0.5`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the Hugging Face repository of the model. Required.
+    pub fn model_repo(mut self, repo_id: &str) -> Self {
+        self.model_repo = Some(repo_id.to_string());
+        self
+    }
+
+    /// Sets the execution devices, in order of preference.
+    pub fn device(mut self, devices: Vec<Device>) -> Self {
+        self.devices = devices;
+        self
+    }
+
+    /// Sets `TaggingPipeline::threshold`.
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets `TaggingPipeline::thresholds`, which take precedence over `threshold`.
+    pub fn category_thresholds(mut self, thresholds: impl Into<CategoryThresholds>) -> Self {
+        self.thresholds = Some(thresholds.into());
+        self
+    }
+
+    /// Sets `TaggingPipeline::max_batch_size`.
+    pub fn max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// Overrides the filter the preprocessor resizes images with.
+    pub fn resize_filter(mut self, filter: FilterType) -> Self {
+        self.resize_filter = Some(filter);
+        self
+    }
+
+    /// Sets whether the preprocessor pads or center-crops images to the model's input
+    /// size (padded by default).
+    pub fn resize_mode(mut self, resize_mode: ResizeMode) -> Self {
+        self.resize_mode = resize_mode;
+        self
+    }
+
+    /// Sets `TaggingPipeline::activation`.
+    pub fn activation(mut self, activation: Activation) -> Self {
+        self.activation = activation;
+        self
+    }
+
+    /// Sets `TaggingPipeline::ignore_tags`.
+    pub fn ignore_tags<S: Into<String>>(mut self, tags: impl IntoIterator<Item = S>) -> Self {
+        self.ignore_tags = tags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets `TaggingPipeline::only_tags`.
+    pub fn only_tags<S: Into<String>>(mut self, tags: impl IntoIterator<Item = S>) -> Self {
+        self.only_tags = Some(tags.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Reports download and loading progress to `callback`.
+    pub fn progress_callback(mut self, callback: ProgressCallback) -> Self {
+        self.progress_callback = Some(callback);
+        self
+    }
+
+    /// Aborts the model download when `cancel` is triggered.
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Downloads the model files and builds the pipeline.
+    ///
+    /// Fails with `TaggerError::Config` when no model repository was set.
+    pub async fn build(self) -> Result<TaggingPipeline> {
+        let model_repo = self.model_repo.ok_or_else(|| {
+            TaggerError::Config("No model repository set on the pipeline builder".to_string())
+        })?;
+        let mut pipeline = TaggingPipeline::from_pretrained_with_cancel(
+            &model_repo,
+            self.devices,
+            self.progress_callback,
+            self.cancel.as_ref(),
+        )
+        .await?;
+
+        pipeline.threshold = self.threshold;
+        pipeline.thresholds = self.thresholds;
+        pipeline.max_batch_size = self.max_batch_size;
+        if let Some(filter) = self.resize_filter {
+            pipeline.preprocessor.resize_filter = filter;
+        }
+        pipeline.preprocessor.resize_mode = self.resize_mode;
+        pipeline.activation = self.activation;
+        pipeline.ignore_tags = self.ignore_tags;
+        pipeline.only_tags = self.only_tags;
+        Ok(pipeline)
+    }
 }
 
 /// A type alias for a map of tag predictions, from tag name to confidence score.
 pub type Prediction = IndexMap<String, f32>;
 
+/// A compact form of `Prediction` that stores tag indices instead of tag names.
+pub type IndexedPrediction = Vec<(usize, f32)>;
+
 /// The result of a tagging operation, with tags categorized and sorted by confidence.
 #[derive(Debug, Clone)]
 pub struct TaggingResult {
@@ -49,17 +372,138 @@ pub struct TaggingResult {
     pub character: Prediction,
     /// General-purpose tags.
     pub general: Prediction,
+    /// Artist tags.
+    pub artist: Prediction,
+    /// Copyright tags, naming the series or franchise.
+    pub copyright: Prediction,
+    /// Meta tags (e.g., "highres", "translated").
+    pub meta: Prediction,
 }
 
 impl TaggingResult {
     /// Creates a new `TaggingResult` from categorized predictions.
-    fn new(rating: Prediction, character: Prediction, general: Prediction) -> Self {
+    pub fn new(
+        rating: Prediction,
+        character: Prediction,
+        general: Prediction,
+        artist: Prediction,
+        copyright: Prediction,
+        meta: Prediction,
+    ) -> Self {
         Self {
             rating,
             character,
             general,
+            artist,
+            copyright,
+            meta,
         }
     }
+
+    /// Returns the highest-scoring rating tag and its score, if any rating passed
+    /// the threshold.
+    pub fn top_rating(&self) -> Option<(&str, f32)> {
+        // Each category is already sorted by descending confidence.
+        self.rating
+            .first()
+            .map(|(tag, &score)| (tag.as_str(), score))
+    }
+
+    /// Returns whether any of `EXPLICIT_RATING_TAGS` scored at least `threshold`.
+    pub fn is_explicit(&self, threshold: f32) -> bool {
+        EXPLICIT_RATING_TAGS
+            .iter()
+            .filter_map(|tag| self.rating.get(*tag))
+            .any(|&score| score >= threshold)
+    }
+
+    /// Returns the number of rating, character, and general tags, in that order.
+    ///
+    /// See `counts_by_category` for the artist, copyright, and meta categories too.
+    pub fn category_counts(&self) -> (usize, usize, usize) {
+        (self.rating.len(), self.character.len(), self.general.len())
+    }
+
+    /// Returns the number of tags in every category, in the order of `categories`.
+    pub fn counts_by_category(&self) -> IndexMap<TagCategory, usize> {
+        self.categories()
+            .map(|(category, prediction)| (category, prediction.len()))
+            .collect()
+    }
+
+    /// Returns the tags of `category`.
+    pub fn filter(&self, category: TagCategory) -> &Prediction {
+        match category {
+            TagCategory::Rating => &self.rating,
+            TagCategory::Character => &self.character,
+            TagCategory::General => &self.general,
+            TagCategory::Artist => &self.artist,
+            TagCategory::Copyright => &self.copyright,
+            TagCategory::Meta => &self.meta,
+        }
+    }
+
+    /// Iterates over the tags of every category, each paired with its category.
+    pub fn categories(&self) -> impl Iterator<Item = (TagCategory, &Prediction)> {
+        [
+            (TagCategory::Rating, &self.rating),
+            (TagCategory::Character, &self.character),
+            (TagCategory::General, &self.general),
+            (TagCategory::Artist, &self.artist),
+            (TagCategory::Copyright, &self.copyright),
+            (TagCategory::Meta, &self.meta),
+        ]
+        .into_iter()
+    }
+
+    /// Adds every tag implied by a present tag, so `cat_ears` also brings in
+    /// `animal_ears`.
+    ///
+    /// An implied tag already present in any category keeps the higher of its own
+    /// score and the implied one. Otherwise it is added to the category of the tag
+    /// implying it. Categories stay sorted by descending confidence.
+    pub fn apply_implications(&mut self, implications: &TagImplications) {
+        let mut implied: Vec<(usize, String, f32)> = Vec::new();
+        for (idx, (_, prediction)) in self.categories().enumerate() {
+            for (tag, &score) in prediction.iter() {
+                let score = implications.implied_score(score);
+                for parent in implications.implied(tag) {
+                    implied.push((idx, parent.to_string(), score));
+                }
+            }
+        }
+        if implied.is_empty() {
+            return;
+        }
+
+        let mut predictions = self.predictions_mut();
+        for (idx, tag, score) in implied {
+            let present = predictions
+                .iter_mut()
+                .find_map(|prediction| prediction.get_mut(&tag));
+            match present {
+                Some(existing) => *existing = existing.max(score),
+                None => {
+                    predictions[idx].insert(tag, score);
+                }
+            }
+        }
+        for prediction in predictions {
+            prediction.sort_by(|_, a, _, b| b.total_cmp(a));
+        }
+    }
+
+    /// Returns the predictions of every category, in the order of `categories`.
+    fn predictions_mut(&mut self) -> [&mut Prediction; 6] {
+        [
+            &mut self.rating,
+            &mut self.character,
+            &mut self.general,
+            &mut self.artist,
+            &mut self.copyright,
+            &mut self.meta,
+        ]
+    }
 }
 
 impl TaggingPipeline {
@@ -75,6 +519,12 @@ impl TaggingPipeline {
             preprocessor,
             tags,
             threshold: *threshold,
+            thresholds: None,
+            adaptive_threshold: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            activation: Activation::None,
+            ignore_tags: HashSet::new(),
+            only_tags: None,
         }
     }
 
@@ -83,24 +533,74 @@ impl TaggingPipeline {
         model_name: &str,
         devices: Vec<Device>,
         progress_callback: Option<ProgressCallback>,
+    ) -> Result<Self> {
+        Self::from_pretrained_with_cancel(model_name, devices, progress_callback, None).await
+    }
+
+    /// Creates a new `TaggingPipeline` like `from_pretrained`, but aborts the model
+    /// download when `cancel` is triggered, leaving no partial file in the cache.
+    pub async fn from_pretrained_with_cancel(
+        model_name: &str,
+        devices: Vec<Device>,
+        progress_callback: Option<ProgressCallback>,
+        cancel: Option<&CancellationToken>,
     ) -> Result<Self> {
         let progress_callback = progress_callback.as_ref();
 
         Self::report_progress(progress_callback, 0.0, "Initializing Tagger...");
         TaggerModel::init(devices)?;
 
-        Self::report_progress(
-            progress_callback,
-            0.2,
-            &format!("Downloading model: {}", model_name),
-        );
-        let model = TaggerModel::from_pretrained(model_name).await?;
+        // The model download fills the stage between the setup messages and loading,
+        // reported once per percent, or once per megabyte when the size is unknown.
+        let last_step = AtomicU64::new(u64::MAX);
+        let on_download = |downloaded: u64, total: Option<u64>| {
+            let megabytes = downloaded as f32 / MEGABYTE as f32;
+            let (step, progress, message) = match total.filter(|&total| total > 0) {
+                Some(total) => (
+                    downloaded * 100 / total,
+                    0.4 + 0.4 * (downloaded as f32 / total as f32),
+                    format!(
+                        "Downloading model: {} ({:.1}/{:.1} MB)",
+                        model_name,
+                        megabytes,
+                        total as f32 / MEGABYTE as f32
+                    ),
+                ),
+                None => (
+                    downloaded / MEGABYTE,
+                    0.4,
+                    format!("Downloading model: {} ({:.1} MB)", model_name, megabytes),
+                ),
+            };
+            if last_step.swap(step, Ordering::Relaxed) != step {
+                Self::report_progress(progress_callback, progress, &message);
+            }
+        };
 
-        Self::report_progress(progress_callback, 0.5, "Setting up preprocessor...");
-        let preprocessor = ImagePreprocessor::from_pretrained(model_name).await?;
+        // The small config and tag files are fetched while the model downloads, so the
+        // model is the only download the pipeline waits on.
+        let model_path = async {
+            Self::report_progress(
+                progress_callback,
+                0.2,
+                &format!("Downloading model: {}", model_name),
+            );
+            TaggerModelFile::new(model_name)
+                .get_with_progress(cancel, Some(&on_download))
+                .await
+        };
+        let preprocessor = async {
+            Self::report_progress(progress_callback, 0.3, "Setting up preprocessor...");
+            ImagePreprocessor::from_pretrained(model_name).await
+        };
+        let tags = async {
+            Self::report_progress(progress_callback, 0.4, "Downloading tags...");
+            LabelTags::from_pretrained(model_name).await
+        };
+        let (model_path, preprocessor, tags) = tokio::try_join!(model_path, preprocessor, tags)?;
 
-        Self::report_progress(progress_callback, 0.8, "Downloading tags...");
-        let tags = LabelTags::from_pretrained(model_name).await?;
+        Self::report_progress(progress_callback, 0.8, "Loading model...");
+        let model = TaggerModel::load(&model_path)?;
 
         Self::report_progress(progress_callback, 1.0, "Pipeline ready.");
 
@@ -109,9 +609,85 @@ impl TaggingPipeline {
             preprocessor,
             tags,
             threshold: 0.5,
+            thresholds: None,
+            adaptive_threshold: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            activation: Activation::None,
+            ignore_tags: HashSet::new(),
+            only_tags: None,
         })
     }
 
+    /// Creates a new `TaggingPipeline` from files already on disk, without touching
+    /// the network.
+    ///
+    /// Unlike `from_pretrained`, this does not initialize ONNX Runtime; call
+    /// `TaggerModel::init` first to pick execution devices other than the default.
+    pub fn from_local<M: AsRef<Path>, T: AsRef<Path>>(
+        model_path: M,
+        tags_path: T,
+        preprocessor: impl Into<LocalPreprocessor>,
+    ) -> Result<Self> {
+        let preprocessor = match preprocessor.into() {
+            LocalPreprocessor::ConfigFile(path) => ImagePreprocessor::from_config_file(&path)
+                .with_context(|| format!("Invalid preprocessor config at {:?}", path))?,
+            LocalPreprocessor::Values(preprocessor) => preprocessor,
+        };
+        let tags = LabelTags::load(tags_path)?;
+        let model = TaggerModel::load(model_path)?;
+
+        Ok(Self {
+            model,
+            preprocessor,
+            tags,
+            threshold: 0.5,
+            thresholds: None,
+            adaptive_threshold: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            activation: Activation::None,
+            ignore_tags: HashSet::new(),
+            only_tags: None,
+        })
+    }
+
+    /// Downloads and verifies the model, tags, and model config into the default
+    /// cache without creating an ONNX session.
+    pub async fn prefetch(repo_id: &str) -> Result<()> {
+        Self::prefetch_into(repo_id, &file::cache_root()).await
+    }
+
+    /// Like `prefetch`, but caches the files under `cache_root`.
+    pub async fn prefetch_into(repo_id: &str, cache_root: &Path) -> Result<()> {
+        let model_path = file::get_in(cache_root, repo_id, file::MODEL_FILE, None).await?;
+        anyhow::ensure!(
+            fs::metadata(&model_path)?.len() > 0,
+            "Downloaded model is empty: {:?}",
+            model_path
+        );
+
+        // Like `LabelTags::from_pretrained`, fall back to `tags.json` for repositories
+        // without a CSV tag list.
+        match file::get_in(cache_root, repo_id, file::TAGS_FILE, None).await {
+            Ok(csv_path) => {
+                LabelTags::load(&csv_path)
+                    .with_context(|| format!("Invalid tag list at {:?}", csv_path))?;
+            }
+            Err(csv_err) => {
+                let json_path = file::get_in(cache_root, repo_id, file::TAGS_JSON_FILE, None)
+                    .await
+                    .map_err(|_| csv_err)?;
+                LabelTags::load_json(&json_path)
+                    .with_context(|| format!("Invalid tag list at {:?}", json_path))?;
+            }
+        }
+
+        let config_path = file::get_in(cache_root, repo_id, file::CONFIG_FILE, None).await?;
+        ModelConfig::load(&config_path)
+            .with_context(|| format!("Invalid model config at {:?}", config_path))?;
+
+        Ok(())
+    }
+
     /// Reports progress using the provided callback.
     fn report_progress(
         progress_callback: Option<&ProgressCallback>,
@@ -123,21 +699,53 @@ impl TaggingPipeline {
         }
     }
 
+    /// Sets per-category thresholds, overriding `threshold`.
+    pub fn set_thresholds(&mut self, thresholds: CategoryThresholds) {
+        self.thresholds = Some(thresholds);
+    }
+
+    /// Returns the threshold that applies to `category`.
+    fn threshold_for(&self, category: &TagCategory) -> f32 {
+        self.thresholds
+            .map_or(self.threshold, |thresholds| thresholds.for_category(category))
+    }
+
     /// Filters and sorts tags for a specific category from a set of predictions.
+    ///
+    /// When the category's threshold is `0.0` or lower, no tag is filtered out.
     fn get_tags_for_category(&self, pairs: &Prediction, category: TagCategory) -> Prediction {
-        pairs
-            .iter()
-            .filter(|(tag, &prob)| {
-                prob >= self.threshold
-                    && self
-                        .tags
-                        .label2tag()
-                        .get(*tag)
-                        .map_or(false, |t| t.category() == category)
-            })
-            .sorted_by(|a, b| b.1.partial_cmp(a.1).unwrap_or(std::cmp::Ordering::Equal))
-            .map(|(tag, &prob)| (tag.clone(), prob))
-            .collect()
+        let threshold = match (&category, self.adaptive_threshold) {
+            (TagCategory::General, Some(adaptive)) => {
+                let scores: Vec<f32> = self
+                    .tags
+                    .category_indices(&category)
+                    .iter()
+                    .filter_map(|&idx| pairs.get_index(idx))
+                    .map(|(_, &prob)| prob)
+                    .collect();
+                adaptive.cutoff(&scores)
+            }
+            _ => self.threshold_for(&category),
+        };
+        let mut tags = self.tags.filter_category(pairs, &category, threshold);
+        tags.retain(|tag, _| self.is_allowed(tag));
+        tags
+    }
+
+    /// Returns whether `tag` passes `ignore_tags` and `only_tags`.
+    fn is_allowed(&self, tag: &str) -> bool {
+        let only = self.only_tags.as_ref();
+        !self.ignore_tags.contains(tag) && only.is_none_or(|only| only.contains(tag))
+    }
+
+    /// Converts a prediction into its compact, index-based form, preserving order.
+    pub fn index_prediction(&self, prediction: &Prediction) -> Result<IndexedPrediction> {
+        self.tags.to_indexed(prediction)
+    }
+
+    /// Expands an index-based prediction back into tag names.
+    pub fn expand_prediction(&self, indexed: &[(usize, f32)]) -> Result<Prediction> {
+        self.tags.expand_indexed(indexed)
     }
 
     /// Predicts tags for a single image.
@@ -152,35 +760,267 @@ impl TaggingPipeline {
             .context("Prediction batch returned no results for a single image")
     }
 
+    /// Predicts tags for an encoded image (JPEG, PNG, WebP, ...) held in memory.
+    ///
+    /// Data that cannot be decoded is reported as a `TaggerError::Io`.
+    pub fn predict_bytes(
+        &mut self,
+        data: &[u8],
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<TaggingResult> {
+        let image = decode_image(data)?;
+        self.predict(image, progress_callback)
+    }
+
+    /// Tags an encoded image end to end: decode, preprocess, run the model, and
+    /// categorize the predictions.
+    pub fn tag_bytes(&mut self, data: &[u8]) -> Result<TaggingResult> {
+        self.predict_bytes(data, None)
+    }
+
+    /// Predicts tags for a single image, keeping at most the `k` highest-scoring tags
+    /// of each category that pass the threshold.
+    pub fn predict_top_k(&mut self, image: DynamicImage, k: usize) -> Result<TaggingResult> {
+        let mut result = self.predict(image, None)?;
+        // Each category is already sorted by descending confidence.
+        result.rating.truncate(k);
+        result.character.truncate(k);
+        result.general.truncate(k);
+        result.artist.truncate(k);
+        result.copyright.truncate(k);
+        result.meta.truncate(k);
+        Ok(result)
+    }
+
     /// Predicts tags for a batch of images.
+    ///
+    /// The images are run through the model `max_batch_size` at a time, and the
+    /// results are returned in the order of `images`.
     pub fn predict_batch(
         &mut self,
         images: Vec<&DynamicImage>,
         progress_callback: Option<ProgressCallback>,
     ) -> Result<Vec<TaggingResult>> {
         let progress_callback = progress_callback.as_ref();
-        Self::report_progress(progress_callback, 0.0, "Preprocessing images...");
-        let tensor = self.preprocessor.process_batch(images)?;
+        let max_batch_size = self.max_batch_size.max(1);
+        let chunk_count = images.len().div_ceil(max_batch_size) as f32;
+        let mut results = Vec::with_capacity(images.len());
+
+        // Only one sub-batch is held as a tensor at a time, so large batches do not
+        // need one enormous input allocation.
+        for (i, chunk) in images.chunks(max_batch_size).enumerate() {
+            let done = i as f32 / chunk_count;
+            Self::report_progress(progress_callback, done, "Preprocessing images...");
+            let tensor = self.preprocessor.process_batch(chunk.to_vec())?;
+
+            Self::report_progress(
+                progress_callback,
+                done + 0.3 / chunk_count,
+                "Running model prediction...",
+            );
+            results.extend(self.predict_from_tensor(tensor)?);
+        }
+
+        Self::report_progress(progress_callback, 1.0, "Prediction complete.");
+
+        Ok(results)
+    }
+
+    /// Tags the images at `paths`, pairing each path with its own result in the order
+    /// of `paths`.
+    ///
+    /// A file that cannot be opened or decoded gets an error without stopping the
+    /// others, so callers decide how to handle partial failures. Images are decoded
+    /// and run `max_batch_size` at a time, so only one sub-batch is held in memory.
+    pub fn predict_paths(&mut self, paths: &[PathBuf]) -> Vec<(PathBuf, Result<TaggingResult>)> {
+        let mut results = Vec::with_capacity(paths.len());
+        for chunk in paths.chunks(self.max_batch_size.max(1)) {
+            let images: Vec<Result<DynamicImage>> =
+                chunk.iter().map(|path| open_image(path)).collect();
+            let decoded: Vec<&DynamicImage> = images
+                .iter()
+                .filter_map(|image| image.as_ref().ok())
+                .collect();
+
+            let predictions: Vec<Result<TaggingResult>> =
+                match self.predict_batch(decoded.clone(), None) {
+                    Ok(predictions) => predictions.into_iter().map(Ok).collect(),
+                    // Retried one by one, so a failure is pinned on the image causing it.
+                    Err(_) => decoded
+                        .into_iter()
+                        .map(|image| {
+                            self.predict_batch(vec![image], None)?
+                                .pop()
+                                .context("Prediction batch returned no results for a single image")
+                        })
+                        .collect(),
+                };
+
+            let mut predictions = predictions.into_iter();
+            for (path, image) in chunk.iter().zip(images) {
+                let result = image.and_then(|_| {
+                    predictions
+                        .next()
+                        .context("Prediction batch returned fewer results than images")?
+                });
+                results.push((path.clone(), result));
+            }
+        }
+        results
+    }
+
+    /// Predicts tags for a batch of shared images, such as `Arc<DynamicImage>`.
+    ///
+    /// This behaves like `predict_batch` but lets callers pass images owned elsewhere
+    /// without collecting borrows that must outlive the call site.
+    pub fn predict_batch_shared<T: AsRef<DynamicImage>>(
+        &mut self,
+        images: &[T],
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<Vec<TaggingResult>> {
+        self.predict_batch(images.iter().map(AsRef::as_ref).collect(), progress_callback)
+    }
 
-        Self::report_progress(progress_callback, 0.3, "Running model prediction...");
-        let probs = self.model.predict(tensor)?;
+    /// Lazily tags a stream of images, yielding each result as soon as its batch finishes.
+    ///
+    /// Images are pulled from `images` and run through the model `batch_size` at a time,
+    /// so only one batch is held in memory. If a batch fails, a single error is yielded
+    /// in place of its results and the stream continues with the next batch.
+    pub fn predict_stream<'a, I>(
+        &'a mut self,
+        images: I,
+        batch_size: usize,
+    ) -> impl Iterator<Item = Result<TaggingResult>> + 'a
+    where
+        I: IntoIterator<Item = DynamicImage>,
+        I::IntoIter: 'a,
+    {
+        let mut images = images.into_iter();
+        let batch_size = batch_size.max(1);
+        let mut pending = VecDeque::new();
 
-        Self::report_progress(progress_callback, 0.6, "Processing results...");
+        std::iter::from_fn(move || {
+            if pending.is_empty() {
+                let batch: Vec<DynamicImage> = images.by_ref().take(batch_size).collect();
+                if batch.is_empty() {
+                    return None;
+                }
+                match self.predict_batch(batch.iter().collect(), None) {
+                    Ok(results) => pending.extend(results.into_iter().map(Ok)),
+                    Err(e) => pending.push_back(Err(e)),
+                }
+            }
+            pending.pop_front()
+        })
+    }
+
+    /// Returns the model output for `image` projected through the tag CSV's
+    /// `embedding__*` columns, before it is paired with tag names.
+    ///
+    /// The vector can be compared between images for nearest-neighbor retrieval.
+    /// Fails with `TaggerError::Config` when the tag CSV has no embedding columns.
+    pub fn embed(&mut self, image: &DynamicImage) -> Result<Vec<f32>> {
+        anyhow::ensure!(
+            self.tags.has_embeddings(),
+            TaggerError::Config("The tag CSV has no embedding__* columns".to_string())
+        );
+        let tensor = self.preprocessor.process(image)?;
+        let features = self
+            .model
+            .predict(tensor)?
+            .pop()
+            .context("Prediction returned no results for a single image")?;
+        self.tags.project_embedding(features)
+    }
+
+    /// Rates and tags a single image in one pass.
+    ///
+    /// The decoded image is shared between both models, and when the rating model
+    /// preprocesses images the same way as the tagger, so is the preprocessed tensor.
+    pub fn predict_with_rating(
+        &mut self,
+        image: &DynamicImage,
+        rating_model: &mut RatingModel,
+    ) -> Result<(Rating, TaggingResult)> {
+        let tensor = self.preprocessor.process(image)?;
+        let rating = if rating_model.preprocessor() == &self.preprocessor {
+            rating_model
+                .rate_tensor(tensor.clone())?
+                .pop()
+                .context("Rating returned no results for a single image")?
+        } else {
+            rating_model.rate(image)?
+        };
+
+        let result = self
+            .predict_from_tensor(tensor)?
+            .pop()
+            .context("Prediction returned no results for a single image")?;
+        Ok((rating, result))
+    }
+
+    /// Rates and tags a batch of images in one pass, like `predict_with_rating`.
+    ///
+    /// The images are run through both models `max_batch_size` at a time, and the
+    /// results are returned in the order of `images`.
+    pub fn predict_batch_with_rating(
+        &mut self,
+        images: &[&DynamicImage],
+        rating_model: &mut RatingModel,
+    ) -> Result<Vec<(Rating, TaggingResult)>> {
+        let mut results = Vec::with_capacity(images.len());
+        for chunk in images.chunks(self.max_batch_size.max(1)) {
+            let tensor = self.preprocessor.process_batch(chunk.to_vec())?;
+            let ratings = if rating_model.preprocessor() == &self.preprocessor {
+                rating_model.rate_tensor(tensor.clone())?
+            } else {
+                rating_model.rate_batch(chunk)?
+            };
+            results.extend(ratings.into_iter().zip(self.predict_from_tensor(tensor)?));
+        }
+        Ok(results)
+    }
+
+    /// Runs the model on an already preprocessed batch and categorizes the results,
+    /// one per image of the batch.
+    ///
+    /// `tensor` must be laid out like the output of `preprocessor.process_batch`. It is
+    /// run as a single batch, without the `max_batch_size` split of `predict_batch`,
+    /// so a prepared tensor can be reused, e.g. to benchmark the model alone.
+    pub fn predict_from_tensor(&mut self, tensor: Array<f32, Ix4>) -> Result<Vec<TaggingResult>> {
+        let outputs = self.model.predict(tensor)?;
+        let probs = outputs
+            .into_iter()
+            .map(|row| self.activation.apply(row))
+            .collect();
         let pairs_batch = self.tags.create_probality_pairs(probs)?;
 
-        let results = pairs_batch
+        Ok(pairs_batch
             .iter()
             .map(|pairs| {
-                let rating = self.get_tags_for_category(pairs, TagCategory::Rating);
-                let character = self.get_tags_for_category(pairs, TagCategory::Character);
-                let general = self.get_tags_for_category(pairs, TagCategory::General);
-                TaggingResult::new(rating, character, general)
+                let tags_for = |category| self.get_tags_for_category(pairs, category);
+                TaggingResult::new(
+                    tags_for(TagCategory::Rating),
+                    tags_for(TagCategory::Character),
+                    tags_for(TagCategory::General),
+                    tags_for(TagCategory::Artist),
+                    tags_for(TagCategory::Copyright),
+                    tags_for(TagCategory::Meta),
+                )
             })
-            .collect();
+            .collect())
+    }
+}
 
-        Self::report_progress(progress_callback, 1.0, "Prediction complete.");
+/// Tags batches of images behind a trait object, so callers can hold any backend,
+/// such as a remote inference server or a mock in tests, as a `Box<dyn Tagger>`.
+pub trait Tagger {
+    /// Predicts tags for `images`, returning one result per image in the same order.
+    fn predict(&mut self, images: Vec<&DynamicImage>) -> Result<Vec<TaggingResult>>;
+}
 
-        Ok(results)
+impl Tagger for TaggingPipeline {
+    fn predict(&mut self, images: Vec<&DynamicImage>) -> Result<Vec<TaggingResult>> {
+        self.predict_batch(images, None)
     }
 }
-
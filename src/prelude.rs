@@ -1,10 +1,15 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use ffmpeg_next as ffmpeg;
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageFormat};
 use std::{
-    fs,
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::BufWriter,
     path::{Path, PathBuf},
 };
-use walkdir::WalkDir;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::processor::open_image;
 
 const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "bmp", "webp"];
 const VIDEO_EXTENSIONS: &[&str] = &["mp4", "avi", "mkv", "mov", "webm"];
@@ -31,55 +36,381 @@ pub fn suggest_media_directories(start_path: &Path) -> Result<Vec<PathBuf>> {
     Ok(media_dirs)
 }
 
-pub fn rename_files_in_selected_dirs(selected_dirs: &[PathBuf]) -> Result<()> {
+/// The file written to each renamed directory, mapping the new file names to the
+/// original ones so `undo_rename` can restore them.
+pub const RENAME_MANIFEST_FILE: &str = "rename_manifest.json";
+
+/// Files are moved to a temporary name before their final one, so a final name
+/// never clobbers a file that has not been renamed yet.
+const RENAME_TEMP_PREFIX: &str = ".eros-rename-";
+
+/// Maps file names, relative to the renamed directory, from new to original.
+type RenameManifest = BTreeMap<String, String>;
+
+/// Returns a `WalkDir::filter_entry` predicate for a walk of `dir` that prunes the
+/// directories in `exclude` and everything under them.
+///
+/// `dir` and `exclude` are resolved once, up front, so `./media/quarantine` is pruned
+/// from a walk of `media` without resolving every entry. Excluded directories that do
+/// not exist yet have nothing to prune.
+pub fn skip_excluded(dir: &Path, exclude: &[PathBuf]) -> impl FnMut(&DirEntry) -> bool {
+    let exclude: Vec<PathBuf> = exclude
+        .iter()
+        .filter_map(|excluded| fs::canonicalize(excluded).ok())
+        .collect();
+    let root = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let dir = dir.to_path_buf();
+    move |entry| {
+        if exclude.is_empty() || !entry.file_type().is_dir() {
+            return true;
+        }
+        let resolved = match entry.path().strip_prefix(&dir) {
+            Ok(relative) => root.join(relative),
+            Err(_) => entry.path().to_path_buf(),
+        };
+        !exclude
+            .iter()
+            .any(|excluded| resolved.starts_with(excluded))
+    }
+}
+
+/// Returns the files under `dir`, skipping the directories in `exclude` and everything
+/// under them.
+pub(crate) fn walk_files(dir: &Path, exclude: &[PathBuf]) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(skip_excluded(dir, exclude))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(DirEntry::into_path)
+        .collect()
+}
+
+/// Options for `rename_files_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct RenameOptions {
+    /// Only work out which files would be renamed, without touching any.
+    pub dry_run: bool,
+    /// Directories whose files are left untouched.
+    pub exclude: Vec<PathBuf>,
+}
+
+/// Renames every file in the selected directories to a sequential number.
+///
+/// Files already named with a number, like those renamed by an earlier run, keep their
+/// names, and new files are numbered around them, so renaming the same directory again
+/// only renames the files added since.
+///
+/// Each directory gets a `RENAME_MANIFEST_FILE` recording the original names, which
+/// `undo_rename` uses to restore them.
+///
+/// Returns the `(old, new)` path of each renamed file.
+pub fn rename_files_in_selected_dirs(selected_dirs: &[PathBuf]) -> Result<Vec<(PathBuf, PathBuf)>> {
+    rename_files_with_options(selected_dirs, &RenameOptions::default())
+}
+
+/// Renames files like `rename_files_in_selected_dirs`, according to `options`.
+///
+/// With `options.dry_run`, returns the same paths as `plan_renames` without renaming
+/// anything.
+pub fn rename_files_with_options(
+    selected_dirs: &[PathBuf],
+    options: &RenameOptions,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let exclude = &options.exclude;
+    if options.dry_run {
+        let mut counter = 1;
+        return Ok(selected_dirs
+            .iter()
+            .flat_map(|dir| plan_dir_renames(dir, exclude, &mut counter))
+            .collect());
+    }
+
+    let mut renamed = Vec::new();
     let mut counter = 1;
     for dir in selected_dirs {
-        let mut entries: Vec<_> = WalkDir::new(dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .collect();
+        let manifest_path = dir.join(RENAME_MANIFEST_FILE);
+        let mut manifest = read_rename_manifest(&manifest_path)?.unwrap_or_default();
+
+        let mut pending = Vec::new();
+        for (old_path, new_path) in plan_dir_renames(dir, exclude, &mut counter) {
+            let temp_name = format!(
+                "{}{}",
+                RENAME_TEMP_PREFIX,
+                new_path.file_name().unwrap_or_default().to_string_lossy()
+            );
+            let temp_path = old_path.with_file_name(temp_name);
+            fs::rename(&old_path, &temp_path)?;
+            pending.push((old_path, temp_path, new_path));
+        }
+        if pending.is_empty() {
+            continue;
+        }
 
-        // Sort entries to ensure deterministic renaming
-        entries.sort_by_key(|e| e.path().to_path_buf());
+        // Files renamed by earlier runs keep their entries.
+        for (old_path, temp_path, new_path) in pending {
+            fs::rename(&temp_path, &new_path)?;
+            let old_name = relative_name(dir, &old_path);
+            let original = manifest.remove(&old_name).unwrap_or(old_name);
+            manifest.insert(relative_name(dir, &new_path), original);
+            renamed.push((old_path, new_path));
+        }
+        fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)
+            .with_context(|| format!("Failed to write rename manifest {:?}", manifest_path))?;
+    }
+    Ok(renamed)
+}
 
-        for entry in entries {
-            if let Some(ext) = entry.path().extension().and_then(|s| s.to_str()) {
-                let new_name = format!("{}.{}", counter, ext);
-                let new_path = entry.path().with_file_name(new_name);
-                fs::rename(entry.path(), new_path)?;
-                counter += 1;
+/// Returns the `(old, new)` path of each file `rename_files_in_selected_dirs` would
+/// rename, without renaming anything.
+pub fn plan_renames(selected_dirs: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+    let mut counter = 1;
+    selected_dirs
+        .iter()
+        .flat_map(|dir| plan_dir_renames(dir, &[], &mut counter))
+        .collect()
+}
+
+/// Returns the `(old, new)` path of each file to rename in `dir` outside of `exclude`,
+/// numbering them from `counter` in path order.
+fn plan_dir_renames(
+    dir: &Path,
+    exclude: &[PathBuf],
+    counter: &mut usize,
+) -> Vec<(PathBuf, PathBuf)> {
+    let manifest_path = dir.join(RENAME_MANIFEST_FILE);
+    let mut entries: Vec<_> = walk_files(dir, exclude)
+        .into_iter()
+        .filter(|path| *path != manifest_path)
+        .collect();
+
+    // Sort entries to ensure deterministic renaming
+    entries.sort();
+
+    // Files already numbered, e.g. by an earlier run, keep their names, and their
+    // numbers are not handed out again.
+    let taken: HashSet<usize> = entries
+        .iter()
+        .map(PathBuf::as_path)
+        .filter_map(file_number)
+        .collect();
+
+    let mut planned = Vec::new();
+    for path in entries {
+        if file_number(&path).is_some() {
+            continue;
+        }
+        if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
+            while taken.contains(counter) {
+                *counter += 1;
             }
+            let new_path = path.with_file_name(format!("{}.{}", counter, ext));
+            planned.push((path, new_path));
+            *counter += 1;
+        }
+    }
+    planned
+}
+
+/// Returns the number a file is named with, like `3` for `3.jpg`, if any.
+fn file_number(path: &Path) -> Option<usize> {
+    path.extension()?;
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// Restores the names recorded in the `RENAME_MANIFEST_FILE` of `dir` by
+/// `rename_files_in_selected_dirs`, then removes the manifest.
+///
+/// Files that no longer exist are skipped. Fails without renaming anything if an
+/// original name is now taken by a file that the manifest does not cover. Returns the `(renamed, restored)` path of each file.
+pub fn undo_rename(dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let manifest_path = dir.join(RENAME_MANIFEST_FILE);
+    let manifest = read_rename_manifest(&manifest_path)?
+        .with_context(|| format!("No rename manifest found in {:?}", dir))?;
+
+    for original in manifest.values() {
+        let original_path = dir.join(original);
+        anyhow::ensure!(
+            !original_path.exists() || manifest.contains_key(original),
+            "Cannot undo rename: {:?} already exists",
+            original_path
+        );
+    }
+
+    let mut pending = Vec::new();
+    for (i, (name, original)) in manifest.iter().enumerate() {
+        let path = dir.join(name);
+        if !path.exists() {
+            // Converted or deleted since it was renamed.
+            tracing::warn!("Skipping {:?}: the file no longer exists", path);
+            continue;
+        }
+        let temp_path = path.with_file_name(format!("{}undo-{}", RENAME_TEMP_PREFIX, i));
+        fs::rename(&path, &temp_path)
+            .with_context(|| format!("Failed to restore the name of {:?}", path))?;
+        pending.push((path, temp_path, dir.join(original)));
+    }
+
+    let mut restored = Vec::new();
+    for (path, temp_path, original_path) in pending {
+        fs::rename(&temp_path, &original_path)?;
+        restored.push((path, original_path));
+    }
+    fs::remove_file(&manifest_path)?;
+    Ok(restored)
+}
+
+/// Reads a rename manifest, returning `None` if it does not exist.
+fn read_rename_manifest(path: &Path) -> Result<Option<RenameManifest>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read rename manifest {:?}", path))?;
+    let manifest = serde_json::from_str(&json)
+        .with_context(|| format!("Failed to parse rename manifest {:?}", path))?;
+    Ok(Some(manifest))
+}
+
+/// Returns `path` relative to `dir` as a manifest key.
+fn relative_name(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The format `convert_and_strip_metadata_with_options` re-encodes images to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TargetFormat {
+    /// Lossless PNG, which is much larger than the source for photos.
+    #[default]
+    Png,
+    /// Lossy JPEG at `ConvertOptions::quality`. Transparency is dropped.
+    Jpeg,
+    /// Lossy WebP at `ConvertOptions::quality`.
+    WebP,
+}
+
+impl TargetFormat {
+    /// Returns the file extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            TargetFormat::Png => "png",
+            TargetFormat::Jpeg => "jpg",
+            TargetFormat::WebP => "webp",
+        }
+    }
+}
+
+/// The directory, inside each converted directory, that `ConvertOptions::originals_dir`
+/// usually names.
+pub const ORIGINALS_DIR: &str = "originals";
+
+/// Options for `convert_and_strip_metadata_with_options`.
+#[derive(Debug, Clone)]
+pub struct ConvertOptions {
+    /// Keep the source file after converting it to a new format.
+    ///
+    /// Defaults to `true` so conversion never deletes data unless asked to.
+    pub keep_originals: bool,
+    /// Where kept originals are moved, relative to the converted directory, keeping
+    /// their path inside it. The directory is never converted itself, so later passes
+    /// that skip it see each file once. Kept originals stay beside the converted file
+    /// when unset.
+    pub originals_dir: Option<PathBuf>,
+    /// The format images are re-encoded to.
+    pub target_format: TargetFormat,
+    /// The quality (0-100) of lossy target formats.
+    pub quality: f32,
+    /// Only work out which files would be converted, without touching any.
+    pub dry_run: bool,
+    /// Directories whose files are left untouched.
+    pub exclude: Vec<PathBuf>,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            keep_originals: true,
+            originals_dir: None,
+            target_format: TargetFormat::Png,
+            quality: 90.0,
+            dry_run: false,
+            exclude: Vec::new(),
         }
     }
-    Ok(())
 }
 
+/// Converts images to PNG and videos to MP4, stripping their metadata.
+///
+/// Originals are kept; use `convert_and_strip_metadata_with_options` to delete them
+/// or to choose another image format.
 pub fn convert_and_strip_metadata(selected_dirs: &[PathBuf]) -> Result<()> {
+    convert_and_strip_metadata_with_options(selected_dirs, &ConvertOptions::default())?;
+    Ok(())
+}
+
+/// Converts images to `options.target_format` and videos to MP4, stripping their
+/// metadata.
+///
+/// Images are rotated according to their EXIF orientation before the metadata is
+/// dropped, so they stay upright. Images that cannot be decoded are logged and left
+/// untouched for the tagging step to report.
+///
+/// Returns the `(source, converted)` path of each file, which are the same for files
+/// only stripped in place. With `options.dry_run`, returns the same paths without
+/// converting anything.
+pub fn convert_and_strip_metadata_with_options(
+    selected_dirs: &[PathBuf],
+    options: &ConvertOptions,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut converted = Vec::new();
     for dir in selected_dirs {
-        let entries: Vec<_> = WalkDir::new(dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .collect();
-
-        for entry in entries {
-            let path = entry.path();
+        let originals_dir = options.originals_dir.as_ref().map(|name| dir.join(name));
+        let mut exclude = options.exclude.clone();
+        exclude.extend(originals_dir.clone());
+        let entries = walk_files(dir, &exclude);
+        let handle_original = |path: &Path| -> Result<()> {
+            if !options.keep_originals {
+                fs::remove_file(path)?;
+            } else if let Some(originals_dir) = &originals_dir {
+                move_original(dir, path, originals_dir)?;
+            }
+            Ok(())
+        };
+
+        for path in entries.iter().map(PathBuf::as_path) {
             if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
                 let ext_lower = ext.to_lowercase();
 
                 if IMAGE_EXTENSIONS.contains(&ext_lower.as_str()) {
-                    let img = image::open(path)?;
-                    let new_path = path.with_extension("png");
-                    img.save(&new_path)?;
+                    let new_path = path.with_extension(options.target_format.extension());
+                    if options.dry_run {
+                        converted.push((path.to_path_buf(), new_path));
+                        continue;
+                    }
+                    let img = match open_image(path) {
+                        Ok(img) => img,
+                        Err(e) => {
+                            tracing::warn!("Not converting {:?}: {:#}", path, e);
+                            continue;
+                        }
+                    };
+                    save_stripped(&img, &new_path, options)?;
                     if path != new_path {
-                        fs::remove_file(path)?;
+                        handle_original(path)?;
                     }
+                    converted.push((path.to_path_buf(), new_path));
                 } else if VIDEO_EXTENSIONS.contains(&ext_lower.as_str()) {
                     let new_path = path.with_extension("mp4");
+                    converted.push((path.to_path_buf(), new_path.clone()));
+                    if options.dry_run {
+                        continue;
+                    }
                     if path.as_os_str() != new_path.as_os_str() {
                         remux(path, &new_path)?;
-                        fs::remove_file(path)?;
+                        handle_original(path)?;
                     } else {
                         // It's already an MP4, but we need to strip metadata.
                         let temp_output_path = path.with_extension("temp.mp4");
@@ -91,6 +422,48 @@ pub fn convert_and_strip_metadata(selected_dirs: &[PathBuf]) -> Result<()> {
             }
         }
     }
+    Ok(converted)
+}
+
+/// Moves the converted source `path` from `dir` into `originals_dir`, keeping its path
+/// relative to `dir`. Never overwrites an original moved there earlier.
+fn move_original(dir: &Path, path: &Path, originals_dir: &Path) -> Result<()> {
+    let destination = originals_dir.join(path.strip_prefix(dir).unwrap_or(path));
+    anyhow::ensure!(
+        !destination.exists(),
+        "Cannot move the original {:?} over {:?}",
+        path,
+        destination
+    );
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(path, &destination)
+        .with_context(|| format!("Failed to move {:?} to {:?}", path, destination))
+}
+
+/// Encodes `img` to `path` in `options.target_format`. The encoders write no EXIF or
+/// other metadata.
+fn save_stripped(img: &DynamicImage, path: &Path, options: &ConvertOptions) -> Result<()> {
+    match options.target_format {
+        TargetFormat::Png => img.save_with_format(path, ImageFormat::Png)?,
+        TargetFormat::Jpeg => {
+            let file = File::create(path)?;
+            JpegEncoder::new_with_quality(BufWriter::new(file), options.quality as u8)
+                .encode_image(&img.to_rgb8())?;
+        }
+        TargetFormat::WebP => {
+            let img = if img.color().has_alpha() {
+                DynamicImage::ImageRgba8(img.to_rgba8())
+            } else {
+                DynamicImage::ImageRgb8(img.to_rgb8())
+            };
+            let encoded = webp::Encoder::from_image(&img)
+                .map_err(|e| anyhow::anyhow!("Failed to create WebP encoder: {}", e))?
+                .encode(options.quality);
+            fs::write(path, &*encoded)?;
+        }
+    }
     Ok(())
 }
 
@@ -123,23 +496,66 @@ fn remux(from: &Path, to: &Path) -> Result<(), ffmpeg::Error> {
     Ok(())
 }
 
-use std::process::{Command, Stdio};
+use std::{
+    ffi::OsString,
+    process::{Command, Stdio},
+};
 
 pub fn resize_media(selected_dirs: &[PathBuf], size: (u32, u32)) -> Result<()> {
+    resize_media_with_min_dimension(selected_dirs, size, 0)
+}
+
+/// Resizes media like `resize_media`, but leaves images whose width or height is
+/// below `min_dimension` untouched so they can still be recognized and skipped later.
+pub fn resize_media_with_min_dimension(
+    selected_dirs: &[PathBuf],
+    size: (u32, u32),
+    min_dimension: u32,
+) -> Result<()> {
+    let options = ResizeOptions {
+        min_dimension,
+        ..Default::default()
+    };
+    resize_media_with_options(selected_dirs, size, &options)
+}
+
+/// Options for `resize_media_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct ResizeOptions {
+    /// Images whose width or height is below this many pixels are left untouched
+    /// (0 resizes every image).
+    pub min_dimension: u32,
+    /// Directories whose files are left untouched.
+    pub exclude: Vec<PathBuf>,
+}
+
+/// Resizes media like `resize_media`, according to `options`.
+///
+/// Images that cannot be decoded are logged and left untouched.
+pub fn resize_media_with_options(
+    selected_dirs: &[PathBuf],
+    size: (u32, u32),
+    options: &ResizeOptions,
+) -> Result<()> {
     for dir in selected_dirs {
-        let entries: Vec<_> = WalkDir::new(dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .collect();
-
-        for entry in entries {
-            let path = entry.path();
+        let entries = walk_files(dir, &options.exclude);
+
+        for path in entries.iter().map(PathBuf::as_path) {
             if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
                 let ext_lower = ext.to_lowercase();
 
                 if IMAGE_EXTENSIONS.contains(&ext_lower.as_str()) {
-                    let img = image::open(path)?;
+                    let img = match open_image(path) {
+                        Ok(img) => img,
+                        Err(e) => {
+                            tracing::warn!("Not resizing {:?}: {:#}", path, e);
+                            continue;
+                        }
+                    };
+                    let min_dimension = options.min_dimension;
+                    if img.width() < min_dimension || img.height() < min_dimension {
+                        continue;
+                    }
                     let resized_img = img.resize_exact(size.0, size.1, image::imageops::FilterType::Triangle);
                     resized_img.save(path)?;
                 } else if VIDEO_EXTENSIONS.contains(&ext_lower.as_str()) {
@@ -156,23 +572,54 @@ pub fn resize_media(selected_dirs: &[PathBuf], size: (u32, u32)) -> Result<()> {
 
 fn resize_video(from: &Path, to: &Path, size: (u32, u32)) -> anyhow::Result<()> {
     let (width, height) = size;
-    let vf_param = format!("scale={}:{}", width, height);
+    let vf_param = format!(
+        "scale=w={}:h={}",
+        escape_filter_value(&width.to_string()),
+        escape_filter_value(&height.to_string())
+    );
 
+    // Paths are passed as discrete arguments (never through a shell) and prefixed
+    // with the `file:` protocol so names containing `:` or starting with `-` are
+    // not mistaken for protocols or options.
     let status = Command::new("ffmpeg")
+        .arg("-nostdin")
+        .arg("-y")
         .arg("-i")
-        .arg(from)
+        .arg(ffmpeg_file_arg(from))
         .arg("-vf")
         .arg(&vf_param)
         .arg("-c:a")
         .arg("copy")
-        .arg(to)
+        .arg(ffmpeg_file_arg(to))
         .stdout(Stdio::null())
         .stderr(Stdio::null())
         .status()?;
 
     if !status.success() {
-        return Err(anyhow::anyhow!("ffmpeg failed to resize video"));
+        return Err(anyhow::anyhow!(
+            "ffmpeg failed to resize video: {:?}",
+            from
+        ));
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Builds an ffmpeg path argument that always refers to a local file.
+fn ffmpeg_file_arg(path: &Path) -> OsString {
+    let mut arg = OsString::from("file:");
+    arg.push(path.as_os_str());
+    arg
+}
+
+/// Escapes a value for use inside an ffmpeg filtergraph option.
+fn escape_filter_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '\'' | ':' | ',' | ';' | '[' | ']' | '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -5,11 +5,24 @@
 //! resizing, padding, normalization, and color channel ordering.
 
 use anyhow::{Context, Result};
-use image::{DynamicImage, Rgb, RgbImage};
+use image::{
+    codecs::{gif::GifDecoder, webp::WebPDecoder},
+    imageops::FilterType,
+    AnimationDecoder, DynamicImage, ImageBuffer, ImageDecoder, ImageReader, Pixel, Rgb,
+};
 use ndarray::{Array, Axis, Ix4};
 use rayon::prelude::*;
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Seek},
+    path::Path,
+};
 
-use crate::config::{ModelConfig, PreprocessConfig};
+use crate::{
+    config::{ModelConfig, PreprocessConfig},
+    error::TaggerError,
+};
 
 /// A trait for processing images into tensors suitable for model input.
 pub trait ImageProcessor {
@@ -33,34 +46,121 @@ pub trait ImageProcessor {
     }
 }
 
+/// The memory layout of the tensor a model takes as input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TensorLayout {
+    /// Batch, channels, height, width: the layout of newer models.
+    #[default]
+    Nchw,
+    /// Batch, height, width, channels: the layout of older models.
+    Nhwc,
+}
+
+/// Maps the legacy `bgr` flag of `ImagePreprocessor::new`, which has always selected
+/// the layout: `true` is `Nhwc` and `false` is `Nchw`.
+///
+/// Deprecated: pass a `TensorLayout` instead, and use `ChannelOrder::Bgr` for models
+/// that take blue, green, red channels.
+impl From<bool> for TensorLayout {
+    fn from(bgr: bool) -> Self {
+        if bgr {
+            TensorLayout::Nhwc
+        } else {
+            TensorLayout::Nchw
+        }
+    }
+}
+
+/// The order of the color channels a model takes as input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChannelOrder {
+    #[default]
+    Rgb,
+    Bgr,
+}
+
+/// How images are fitted to the model's square input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResizeMode {
+    /// Fits the whole image within the input and pads the rest with `pad_color`.
+    #[default]
+    Pad,
+    /// Fills the input, scaling the shorter side to fit and cropping the center of
+    /// the longer one.
+    CenterCrop,
+}
+
 /// A preprocessor that resizes, pads, and normalizes images.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ImagePreprocessor {
     pub height: u32,
     pub width: u32,
     pub mean: Vec<f32>,
     pub std: Vec<f32>,
-    pub bgr: bool,
+    /// The layout of the output tensor.
+    pub layout: TensorLayout,
+    /// The order of the color channels in the output tensor. `mean` and `std` are
+    /// always given in RGB order.
+    pub channel_order: ChannelOrder,
+    /// Process 16-bit sources at full precision instead of truncating them to 8 bits.
+    pub high_bit_depth: bool,
+    /// The color used to pad images to a square.
+    pub pad_color: Rgb<u8>,
+    /// The filter used to resize images to the model's input size.
+    pub resize_filter: FilterType,
+    /// Scale images smaller than the input size in both dimensions up to fill it,
+    /// instead of centering them at their own size in the padding.
+    pub allow_upscale: bool,
+    /// Whether images are padded or center-cropped to the input size.
+    pub resize_mode: ResizeMode,
 }
 
 impl ImagePreprocessor {
-    /// Creates a new `ImagePreprocessor`.
+    /// Creates a new `ImagePreprocessor` with RGB channels.
+    ///
+    /// `layout` also accepts the deprecated `bgr` flag, where `true` means
+    /// `TensorLayout::Nhwc`.
     pub fn new(
         height: u32,
         width: u32,
         mean: Vec<f32>,
         std: Vec<f32>,
-        bgr: bool,
+        layout: impl Into<TensorLayout>,
     ) -> Self {
         Self {
             height,
             width,
             mean,
             std,
-            bgr,
+            layout: layout.into(),
+            channel_order: ChannelOrder::Rgb,
+            high_bit_depth: false,
+            pad_color: Rgb([128, 128, 128]),
+            resize_filter: FilterType::Lanczos3,
+            allow_upscale: false,
+            resize_mode: ResizeMode::Pad,
         }
     }
 
+    /// Sets the color used to pad images to a square (mid-gray by default).
+    pub fn with_pad_color(mut self, pad_color: Rgb<u8>) -> Self {
+        self.pad_color = pad_color;
+        self
+    }
+
+    /// Sets whether images are padded or center-cropped to the input size (padded by
+    /// default).
+    pub fn with_resize_mode(mut self, resize_mode: ResizeMode) -> Self {
+        self.resize_mode = resize_mode;
+        self
+    }
+
+    /// Sets the order of the color channels in the output tensor (RGB by default).
+    pub fn with_channel_order(mut self, channel_order: ChannelOrder) -> Self {
+        self.channel_order = channel_order;
+        self
+    }
+
     /// Creates a preprocessor from a pretrained model's configuration on the Hugging Face Hub.
     pub async fn from_pretrained(repo_id: &str) -> Result<Self> {
         if let Ok(config) = PreprocessConfig::from_pretrained(repo_id).await {
@@ -70,6 +170,17 @@ impl ImagePreprocessor {
         }
     }
 
+    /// Creates a preprocessor from a local config file, without touching the network.
+    ///
+    /// Like `from_pretrained`, this reads a `preprocessor_config.json` and falls back to
+    /// treating the file as a model `config.json`.
+    pub fn from_config_file<P: AsRef<Path>>(config_path: P) -> Result<Self> {
+        match PreprocessConfig::load(&config_path) {
+            Ok(config) => Self::from_preprocess_config(config),
+            Err(_) => Self::from_model_config_values(ModelConfig::load(&config_path)?),
+        }
+    }
+
     /// Creates a preprocessor from a `PreprocessConfig`.
     fn from_preprocess_config(config: PreprocessConfig) -> Result<Self> {
         let (height, width) = config
@@ -101,12 +212,16 @@ impl ImagePreprocessor {
             })
             .unwrap_or((vec![0.5, 0.5, 0.5], vec![0.5, 0.5, 0.5]));
 
-        Ok(Self::new(height, width, mean, std, false))
+        Ok(Self::new(height, width, mean, std, TensorLayout::Nchw))
     }
 
     /// Creates a preprocessor from a `ModelConfig` as a fallback.
     async fn from_model_config(repo_id: &str) -> Result<Self> {
-        let model_config = ModelConfig::from_pretrained(repo_id).await?;
+        Self::from_model_config_values(ModelConfig::from_pretrained(repo_id).await?)
+    }
+
+    /// Creates a preprocessor from the input size of a `ModelConfig`.
+    fn from_model_config_values(model_config: ModelConfig) -> Result<Self> {
         let input_size = &model_config.pretrained_cfg.input_size;
         anyhow::ensure!(input_size.len() == 3, "Invalid input size");
 
@@ -118,63 +233,235 @@ impl ImagePreprocessor {
             input_size[2],
             mean,
             std,
-            true,
+            TensorLayout::Nhwc,
         ))
     }
 
+    /// Processes every frame of an animated GIF or WebP into one batched tensor, with one
+    /// slice per frame. Still images produce a single slice.
+    ///
+    /// All decoded frames are held in memory at full resolution (4 bytes per pixel)
+    /// before being preprocessed, plus one `height * width * 3` float slice each in the
+    /// output, so long animations are capped at `max_frames`.
+    pub fn process_animated(&self, path: &Path, max_frames: usize) -> Result<Array<f32, Ix4>> {
+        let frames = decode_frames(path, max_frames)?;
+        anyhow::ensure!(!frames.is_empty(), "No frames decoded from {:?}", path);
+        self.process_batch(frames.iter().collect())
+    }
+
     /// Normalizes the pixel values and arranges them in the required tensor format.
-    fn normalize_and_to_tensor(&self, image: &RgbImage) -> Array<f32, Ix4> {
-        let mut tensor = if self.bgr {
-            // NHWC layout for older models
-            Array::zeros((self.height as usize, self.width as usize, 3))
-        } else {
-            // NCHW layout for newer models
-            Array::zeros((3, self.height as usize, self.width as usize))
+    ///
+    /// The tensor has one channel per channel of `P` (e.g. 1 for luma, 4 for RGBA), and
+    /// `mean` and `std` must have one value per channel.
+    fn normalize_and_to_tensor<P>(
+        &self,
+        image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    ) -> Result<Array<f32, Ix4>>
+    where
+        P: Pixel,
+        P::Subpixel: Into<f32>,
+    {
+        let channels = P::CHANNEL_COUNT as usize;
+        if self.mean.len() != channels || self.std.len() != channels {
+            return Err(TaggerError::Config(format!(
+                "Expected {} mean and std values for a {}-channel image, got {} and {}",
+                channels,
+                channels,
+                self.mean.len(),
+                self.std.len()
+            ))
+            .into());
+        }
+
+        let max_value: f32 = P::Subpixel::DEFAULT_MAX_VALUE.into();
+        let (height, width) = (self.height as usize, self.width as usize);
+        let mut tensor = match self.layout {
+            TensorLayout::Nhwc => Array::zeros((height, width, channels)),
+            TensorLayout::Nchw => Array::zeros((channels, height, width)),
         };
 
         for (x, y, pixel) in image.enumerate_pixels() {
-            let [r, g, b] = pixel.0;
-
-            let r_norm = (r as f32 / 255.0 - self.mean[0]) / self.std[0];
-            let g_norm = (g as f32 / 255.0 - self.mean[1]) / self.std[1];
-            let b_norm = (b as f32 / 255.0 - self.mean[2]) / self.std[2];
-
-            if self.bgr {
-                // NHWC layout
-                tensor[[y as usize, x as usize, 0]] = r_norm;
-                tensor[[y as usize, x as usize, 1]] = g_norm;
-                tensor[[y as usize, x as usize, 2]] = b_norm;
-            } else {
-                // NCHW layout
-                tensor[[0, y as usize, x as usize]] = r_norm;
-                tensor[[1, y as usize, x as usize]] = g_norm;
-                tensor[[2, y as usize, x as usize]] = b_norm;
+            for (c, &value) in pixel.channels().iter().enumerate() {
+                let normalized = (value.into() / max_value - self.mean[c]) / self.std[c];
+                // Only the color channels are swapped; alpha stays last.
+                let out = match self.channel_order {
+                    ChannelOrder::Bgr if channels >= 3 && c < 3 => 2 - c,
+                    _ => c,
+                };
+                match self.layout {
+                    TensorLayout::Nhwc => tensor[[y as usize, x as usize, out]] = normalized,
+                    TensorLayout::Nchw => tensor[[out, y as usize, x as usize]] = normalized,
+                }
             }
         }
 
-        tensor.insert_axis(Axis(0))
+        Ok(tensor.insert_axis(Axis(0)))
+    }
+
+    /// Scales `image` to cover the input size and crops its center, then normalizes it.
+    fn process_center_crop(&self, image: &DynamicImage) -> Result<Array<f32, Ix4>> {
+        let cropped = image.resize_to_fill(self.width, self.height, self.resize_filter);
+        let color = image.color();
+
+        if self.high_bit_depth && color.bytes_per_pixel() > color.channel_count() {
+            self.normalize_and_to_tensor(&cropped.to_rgb16())
+        } else {
+            self.normalize_and_to_tensor(&cropped.to_rgb8())
+        }
+    }
+
+    /// Centers `thumbnail` on a `width` x `height` canvas filled with `fill`.
+    fn pad<S>(
+        &self,
+        thumbnail: &ImageBuffer<Rgb<S>, Vec<S>>,
+        fill: Rgb<S>,
+    ) -> ImageBuffer<Rgb<S>, Vec<S>>
+    where
+        Rgb<S>: Pixel<Subpixel = S>,
+        S: image::Primitive,
+    {
+        let (thumb_width, thumb_height) = thumbnail.dimensions();
+        let mut padded_image = ImageBuffer::from_pixel(self.width, self.height, fill);
+
+        let pad_left = (self.width - thumb_width) / 2;
+        let pad_top = (self.height - thumb_height) / 2;
+        image::imageops::overlay(&mut padded_image, thumbnail, pad_left as i64, pad_top as i64);
+
+        padded_image
     }
 }
 
+/// Opens the image at `path` and applies its EXIF orientation, so photos that
+/// are stored sideways and flagged to be rotated are returned upright.
+///
+/// `image::open` ignores the orientation flag, and re-saving the image drops it,
+/// so the rotation has to be baked into the pixels before the image is used.
+pub fn open_image(path: &Path) -> Result<DynamicImage> {
+    let reader = ImageReader::open(path)
+        .with_context(|| format!("Failed to open {:?}", path))?
+        .with_guessed_format()?;
+    decode_oriented(reader)
+}
+
+/// Decodes an encoded image (JPEG, PNG, WebP, ...) held in memory and applies its
+/// EXIF orientation, like `open_image`.
+///
+/// Data that is not a supported image is reported as a `TaggerError::Io` rather than
+/// the decoder's own error.
+pub fn decode_image(data: &[u8]) -> Result<DynamicImage> {
+    ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(anyhow::Error::from)
+        .and_then(decode_oriented)
+        .map_err(|e| TaggerError::Io(format!("Failed to decode image bytes: {}", e)).into())
+}
+
+/// Decodes the image of `reader`, rotating it upright according to its orientation flag.
+fn decode_oriented<R: BufRead + Seek>(reader: ImageReader<R>) -> Result<DynamicImage> {
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Decodes up to `max_frames` frames from an animated GIF or WebP, or the single
+/// frame of any other image.
+fn decode_frames(path: &Path, max_frames: usize) -> Result<Vec<DynamicImage>> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    let open_reader = || -> Result<BufReader<File>> {
+        let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        Ok(BufReader::new(file))
+    };
+
+    let frames = match extension.as_deref() {
+        Some("gif") => GifDecoder::new(open_reader()?)?.into_frames(),
+        Some("webp") => {
+            let decoder = WebPDecoder::new(open_reader()?)?;
+            if !decoder.has_animation() {
+                return Ok(vec![open_image(path)?]);
+            }
+            decoder.into_frames()
+        }
+        _ => return Ok(vec![open_image(path)?]),
+    };
+
+    frames
+        .take(max_frames)
+        .map(|frame| Ok(DynamicImage::ImageRgba8(frame?.into_buffer())))
+        .collect()
+}
+
 impl ImageProcessor for ImagePreprocessor {
     /// Preprocesses the image for model input by handling transparency, padding, resizing, and normalization.
+    ///
+    /// Grayscale and RGBA sources are converted to RGB first, so `mean` and `std` must
+    /// have three values; otherwise a `TaggerError::Config` is returned.
+    ///
+    /// With `ResizeMode::Pad`, images smaller than the input size are only scaled up
+    /// when `allow_upscale` is set. With `ResizeMode::CenterCrop`, they always are.
+    ///
+    /// When `high_bit_depth` is set and the source has more than 8 bits per channel,
+    /// the image is kept at 16 bits and normalized from the 0–65535 range.
     fn process(&self, image: &DynamicImage) -> Result<Array<f32, Ix4>> {
-        let thumbnail = image.thumbnail(self.width, self.height);
-        let thumbnail_rgb = thumbnail.to_rgb8();
-        let (thumb_width, thumb_height) = thumbnail_rgb.dimensions();
+        if self.resize_mode == ResizeMode::CenterCrop {
+            return self.process_center_crop(image);
+        }
 
-        let mut padded_image =
-            RgbImage::from_pixel(self.width, self.height, Rgb([128, 128, 128]));
+        // `resize` preserves the aspect ratio, fitting the image within the target size,
+        // but also scales small images up, so those are kept as is unless allowed.
+        let fits = image.width() <= self.width && image.height() <= self.height;
+        let thumbnail = if fits && !self.allow_upscale {
+            Cow::Borrowed(image)
+        } else {
+            Cow::Owned(image.resize(self.width, self.height, self.resize_filter))
+        };
+        let color = image.color();
 
-        let pad_left = (self.width - thumb_width) / 2;
-        let pad_top = (self.height - thumb_height) / 2;
-        image::imageops::overlay(
-            &mut padded_image,
-            &thumbnail_rgb,
-            pad_left as i64,
-            pad_top as i64,
-        );
-
-        Ok(self.normalize_and_to_tensor(&padded_image))
+        if self.high_bit_depth && color.bytes_per_pixel() > color.channel_count() {
+            // Scale the pad color to 16 bits so padding matches the 8-bit path.
+            let pad_color = Rgb(self.pad_color.0.map(|c| c as u16 * 257));
+            let padded_image = self.pad(&thumbnail.to_rgb16(), pad_color);
+            self.normalize_and_to_tensor(&padded_image)
+        } else {
+            let padded_image = self.pad(&thumbnail.to_rgb8(), self.pad_color);
+            self.normalize_and_to_tensor(&padded_image)
+        }
+    }
+}
+
+/// A preprocessor that center-crops images to the input size instead of padding them,
+/// for models trained on square crops.
+///
+/// It shares the settings of `ImagePreprocessor`, which it wraps with its
+/// `resize_mode` set to `ResizeMode::CenterCrop`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CenterCropPreprocessor(ImagePreprocessor);
+
+impl CenterCropPreprocessor {
+    /// Creates a center-cropping preprocessor with the settings of `preprocessor`.
+    pub fn new(preprocessor: ImagePreprocessor) -> Self {
+        Self(preprocessor.with_resize_mode(ResizeMode::CenterCrop))
+    }
+
+    /// Returns the wrapped preprocessor, which center-crops as well.
+    pub fn into_inner(self) -> ImagePreprocessor {
+        self.0
+    }
+}
+
+impl From<ImagePreprocessor> for CenterCropPreprocessor {
+    fn from(preprocessor: ImagePreprocessor) -> Self {
+        Self::new(preprocessor)
+    }
+}
+
+impl ImageProcessor for CenterCropPreprocessor {
+    /// Scales the image so its shorter side matches the input size, crops the center,
+    /// and normalizes it like `ImagePreprocessor::process`.
+    fn process(&self, image: &DynamicImage) -> Result<Array<f32, Ix4>> {
+        self.0.process_center_crop(image)
     }
 }
@@ -2,7 +2,8 @@
 //!
 //! This module provides the `RatingModel` for content rating of images.
 //!
-//! The `RatingModel` uses a pretrained ONNX model to classify images as "safe" or "nsfw".
+//! The `RatingModel` uses a pretrained ONNX model to classify images as "safe" or "nsfw",
+//! or to report the raw label of models with finer classes (e.g. safe/questionable/explicit).
 //! It handles the downloading of the model and its configuration from the Hugging Face Hub,
 //! image preprocessing, and inference.
 //!
@@ -11,17 +12,18 @@
 
 use anyhow::{Context, Result};
 use image::DynamicImage;
+use ndarray::{Array, Axis, Ix4};
 use ort::{
     session::{builder::GraphOptimizationLevel, Session},
     value::Value,
 };
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
 use crate::{
     file::{RatingConfigFile, RatingModelFile, RatingPreprocessorConfigFile},
-    processor::{ImagePreprocessor, ImageProcessor},
+    processor::{decode_image, ImagePreprocessor, ImageProcessor, TensorLayout},
 };
 
 /// The result of a rating operation.
@@ -32,12 +34,13 @@ pub enum Rating {
 }
 
 impl Rating {
-    /// Creates a new `Rating` from a label string.
-    fn from_label(label: &str) -> Result<Self> {
-        match label {
-            "nsfw" => Ok(Rating::Nsfw),
-            "sfw" => Ok(Rating::Sfw),
-            _ => anyhow::bail!("Unknown rating label: {}", label),
+    /// Maps a model label to the binary decision: `Nsfw` when `nsfw_labels` contains it,
+    /// `Sfw` otherwise.
+    pub fn classify(label: &str, nsfw_labels: &HashSet<String>) -> Self {
+        if nsfw_labels.contains(label) {
+            Rating::Nsfw
+        } else {
+            Rating::Sfw
         }
     }
 
@@ -101,6 +104,7 @@ pub struct RatingModel {
     config: RatingModelConfig,
     input_name: String,
     output_name: String,
+    nsfw_labels: HashSet<String>,
 }
 
 impl RatingModel {
@@ -125,7 +129,7 @@ impl RatingModel {
             preprocessor_config.size.width,
             preprocessor_config.image_mean,
             preprocessor_config.image_std,
-            false,
+            TensorLayout::Nchw,
         );
 
         let config = RatingModelConfig::from_json(config_path).await?;
@@ -136,33 +140,136 @@ impl RatingModel {
             config,
             input_name,
             output_name,
+            nsfw_labels: HashSet::from(["nsfw".to_string()]),
         })
     }
 
+    /// Sets which of the model's labels count as NSFW (only `"nsfw"` by default).
+    ///
+    /// For multi-class models this decides, e.g., whether "questionable" is NSFW.
+    pub fn set_nsfw_labels<I, S>(&mut self, labels: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.nsfw_labels = labels.into_iter().map(Into::into).collect();
+    }
+
+    /// Returns whether `label` counts as NSFW.
+    pub fn is_nsfw(&self, label: &str) -> bool {
+        self.nsfw_labels.contains(label)
+    }
+
+    /// Returns the preprocessor used to prepare images for this model.
+    pub fn preprocessor(&self) -> &ImagePreprocessor {
+        &self.preprocessor
+    }
+
     /// Rates a single image.
     pub fn rate(&mut self, image: &DynamicImage) -> Result<Rating> {
+        self.rate_with_score(image).map(|(rating, _)| rating)
+    }
+
+    /// Rates an encoded image (JPEG, PNG, WebP, ...) held in memory.
+    ///
+    /// Data that cannot be decoded is reported as a `TaggerError::Io`.
+    pub fn rate_bytes(&mut self, data: &[u8]) -> Result<Rating> {
+        let image = decode_image(data)?;
+        self.rate(&image)
+    }
+
+    /// Rates a single image, also returning the softmax probability of the winning label.
+    pub fn rate_with_score(&mut self, image: &DynamicImage) -> Result<(Rating, f32)> {
+        let tensor = self.preprocessor.process(image)?;
+        self.rate_tensor_with_scores(tensor)?
+            .pop()
+            .context("Rating returned no results for a single image")
+    }
+
+    /// Rates a batch of images with a single session run, one `Rating` per image.
+    pub fn rate_batch(&mut self, images: &[&DynamicImage]) -> Result<Vec<Rating>> {
+        if images.is_empty() {
+            return Ok(Vec::new());
+        }
+        let tensor = self.preprocessor.process_batch(images.to_vec())?;
+        self.rate_tensor(tensor)
+    }
+
+    /// Rates a batch that has already been preprocessed with `preprocessor()`,
+    /// one `Rating` per row.
+    pub fn rate_tensor(&mut self, tensor: Array<f32, Ix4>) -> Result<Vec<Rating>> {
+        Ok(self
+            .rate_tensor_with_scores(tensor)?
+            .into_iter()
+            .map(|(rating, _)| rating)
+            .collect())
+    }
+
+    /// Rates a single image with the model's own label rather than the binary
+    /// decision, returning the label and its softmax probability.
+    ///
+    /// Useful for models with finer classes such as safe/questionable/explicit.
+    pub fn rate_label(&mut self, image: &DynamicImage) -> Result<(String, f32)> {
         let tensor = self.preprocessor.process(image)?;
+        self.rate_tensor_labels(tensor)?
+            .pop()
+            .context("Rating returned no results for a single image")
+    }
+
+    /// Like `rate_tensor`, but pairs each `Rating` with the softmax probability of
+    /// the winning label.
+    ///
+    /// The label is looked up in the model's `id2label` map, so models with more
+    /// than two classes are supported.
+    pub fn rate_tensor_with_scores(
+        &mut self,
+        tensor: Array<f32, Ix4>,
+    ) -> Result<Vec<(Rating, f32)>> {
+        Ok(self
+            .rate_tensor_labels(tensor)?
+            .into_iter()
+            .map(|(label, score)| (Rating::classify(&label, &self.nsfw_labels), score))
+            .collect())
+    }
+
+    /// Rates a preprocessed batch, returning the winning `id2label` label and its
+    /// softmax probability for each row.
+    pub fn rate_tensor_labels(&mut self, tensor: Array<f32, Ix4>) -> Result<Vec<(String, f32)>> {
         let value = Value::from_array(tensor)?;
         let outputs = self
             .session
             .run(ort::inputs![self.input_name.as_str() => value])?;
 
-        let output_tensor = outputs[self.output_name.as_str()].try_extract_tensor::<f32>()?;
-        let probabilities = output_tensor.1;
+        let probabilities = outputs[self.output_name.as_str()].try_extract_array::<f32>()?;
 
-        let argmax = probabilities
-            .iter()
-            .enumerate()
-            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
-            .map(|(i, _)| i)
-            .context("Failed to find argmax of probabilities")?;
+        probabilities
+            .axis_iter(Axis(0))
+            .map(|row| {
+                // The raw outputs may be logits, so normalize them first.
+                let probabilities = softmax(row.iter().copied());
+                let (argmax, score) = probabilities
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .context("Failed to find argmax of probabilities")?;
 
-        let label = self
-            .config
-            .id2label
-            .get(&argmax.to_string())
-            .with_context(|| format!("Label not found for index: {}", argmax))?;
+                let label = self
+                    .config
+                    .id2label
+                    .get(&argmax.to_string())
+                    .with_context(|| format!("Label not found for index: {}", argmax))?;
 
-        Rating::from_label(label)
+                Ok((label.clone(), score))
+            })
+            .collect()
     }
 }
+
+/// Numerically stable softmax.
+pub(crate) fn softmax(logits: impl Iterator<Item = f32> + Clone) -> Vec<f32> {
+    let max = logits.clone().fold(f32::NEG_INFINITY, f32::max);
+    let exps: Vec<f32> = logits.map(|x| (x - max).exp()).collect();
+    let sum: f32 = exps.iter().sum();
+    exps.into_iter().map(|e| e / sum).collect()
+}
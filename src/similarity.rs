@@ -0,0 +1,107 @@
+//! # Similarity Search
+//!
+//! This module compares image embeddings, such as those returned by
+//! `TaggingPipeline::embed`, by cosine similarity.
+//!
+//! `TopKSearch` is a brute-force index: every query is compared against every stored
+//! embedding, which is fast enough for a personal collection of tagged images.
+
+/// Returns the cosine of the angle between `a` and `b`, from `-1.0` to `1.0`.
+///
+/// Returns `0.0` when either vector has zero length or the lengths differ.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+/// Stores embeddings under a key, such as an image path, and finds the ones most
+/// similar to a query.
+#[derive(Debug, Clone)]
+pub struct TopKSearch<K> {
+    entries: Vec<(K, Vec<f32>)>,
+}
+
+impl<K> Default for TopKSearch<K> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<K> TopKSearch<K> {
+    /// Creates an empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the embedding of the image identified by `key`.
+    pub fn insert(&mut self, key: K, embedding: Vec<f32>) {
+        self.entries.push((key, embedding));
+    }
+
+    /// Returns the number of stored embeddings.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether no embeddings are stored.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the keys of the `k` embeddings most similar to `query` with their
+    /// cosine similarity, most similar first.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<(&K, f32)> {
+        let mut scored: Vec<(&K, f32)> = self
+            .entries
+            .iter()
+            .map(|(key, embedding)| (key, cosine_similarity(query, embedding)))
+            .collect();
+        scored.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        scored.truncate(k);
+        scored
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!((cosine_similarity(&[1.0, 2.0, 3.0], &[1.0, 2.0, 3.0]) - 1.0).abs() < 1e-6);
+        assert!((cosine_similarity(&[1.0, 2.0], &[2.0, 4.0]) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+        assert!((cosine_similarity(&[1.0, 0.0], &[-1.0, 0.0]) + 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_top_k_ordering() {
+        let mut index = TopKSearch::new();
+        index.insert("orthogonal", vec![0.0, 1.0, 0.0]);
+        index.insert("identical", vec![1.0, 0.0, 0.0]);
+        index.insert("close", vec![1.0, 1.0, 0.0]);
+        index.insert("opposite", vec![-1.0, 0.0, 0.0]);
+        assert_eq!(index.len(), 4);
+
+        let results = index.search(&[1.0, 0.0, 0.0], 3);
+        let keys: Vec<&str> = results.iter().map(|(key, _)| **key).collect();
+        assert_eq!(keys, vec!["identical", "close", "orthogonal"]);
+        assert!((results[0].1 - 1.0).abs() < 1e-6);
+        assert!((results[1].1 - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+
+        assert_eq!(index.search(&[1.0, 0.0, 0.0], 10).len(), 4);
+        assert!(TopKSearch::<&str>::new().search(&[1.0], 3).is_empty());
+    }
+}
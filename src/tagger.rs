@@ -8,12 +8,17 @@
 //! The `Device` enum allows for specifying the hardware to run the model on,
 //! and the `TaggerModel` handles the ONNX Runtime session and prediction logic.
 
-use std::path::Path;
+use std::{path::Path, sync::RwLock};
 
 use anyhow::{Context, Result};
 use ndarray::{Array, Axis, Ix4};
 use num_cpus;
-use ort::{session::Session, value::Tensor, execution_providers::CPUExecutionProvider};
+use ort::{
+    execution_providers::{CPUExecutionProvider, ExecutionProvider, ExecutionProviderDispatch},
+    session::Session,
+    value::Tensor,
+};
+use tokio_util::sync::CancellationToken;
 
 #[cfg(feature = "cuda")]
 use ort::execution_providers::CUDAExecutionProvider;
@@ -24,7 +29,7 @@ use ort::execution_providers::TensorRTExecutionProvider;
 #[cfg(feature = "coreml")]
 use ort::execution_providers::CoreMLExecutionProvider;
 
-use crate::file::TaggerModelFile;
+use crate::{error::TaggerError, file::TaggerModelFile};
 
 /// Represents the execution device for the ONNX model.
 ///
@@ -68,14 +73,60 @@ impl Device {
     pub fn coreml() -> Vec<Self> {
         vec![Self::CoreML]
     }
+
+    /// Returns the name of the device's execution provider, e.g. `"CUDA"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Device::Cpu => "CPU",
+            #[cfg(feature = "cuda")]
+            Device::Cuda(_) => "CUDA",
+            #[cfg(feature = "tensorrt")]
+            Device::TensorRT(_) => "TensorRT",
+            #[cfg(feature = "coreml")]
+            Device::CoreML => "CoreML",
+        }
+    }
+
+    /// Builds the device's execution provider, along with whether ONNX Runtime can
+    /// use it on this machine.
+    fn provider(&self) -> (ExecutionProviderDispatch, bool) {
+        match self {
+            Device::Cpu => with_availability(CPUExecutionProvider::default()),
+            #[cfg(feature = "cuda")]
+            Device::Cuda(device_id) => with_availability(
+                CUDAExecutionProvider::default()
+                    .with_device_id(*device_id)
+                    .with_unified_memory(true),
+            ),
+            #[cfg(feature = "tensorrt")]
+            Device::TensorRT(device_id) => {
+                with_availability(TensorRTExecutionProvider::default().with_device_id(*device_id))
+            }
+            #[cfg(feature = "coreml")]
+            Device::CoreML => with_availability(CoreMLExecutionProvider::default()),
+        }
+    }
+}
+
+/// Pairs `provider` with whether ONNX Runtime was built with it.
+fn with_availability<E>(provider: E) -> (ExecutionProviderDispatch, bool)
+where
+    E: ExecutionProvider + Into<ExecutionProviderDispatch>,
+{
+    let available = provider.is_available().unwrap_or(false);
+    (provider.into(), available)
 }
 
+/// The name of the execution provider selected by the last `TaggerModel::init`.
+static ACTIVE_PROVIDER: RwLock<&str> = RwLock::new("CPU");
+
 /// A wrapper around an ONNX Runtime session for image tagging.
 ///
 /// This struct handles loading the model, managing the session, and running predictions.
 #[derive(Debug)]
 pub struct TaggerModel {
     session: Session,
+    input_name: String,
     output_name: String,
 }
 
@@ -83,60 +134,91 @@ impl TaggerModel {
     /// Initializes the ONNX Runtime with a list of execution providers.
     ///
     /// This function should be called once before creating any `TaggerModel` instances.
-    /// It configures the global ONNX Runtime environment with the specified devices.
+    /// It configures the global ONNX Runtime environment with the specified devices,
+    /// tried in order. Devices whose provider is unavailable (e.g. CUDA without a
+    /// usable driver) are skipped, and the CPU is always appended as the last resort.
+    /// The first usable device is logged and returned by `active_provider`.
     pub fn init(devices: Vec<Device>) -> Result<()> {
         // Suppress verbose logging from ONNX Runtime
         let _ = tracing_subscriber::fmt::try_init();
 
-        let providers: Vec<_> = devices.into_iter().map(|device| match device {
-            Device::Cpu => CPUExecutionProvider::default().build(),
-            #[cfg(feature = "cuda")]
-            Device::Cuda(device_id) => CUDAExecutionProvider::default()
-                .with_device_id(device_id)
-                .with_unified_memory(true)
-                .build(),
-            #[cfg(feature = "tensorrt")]
-            Device::TensorRT(device_id) => TensorRTExecutionProvider::default()
-                .with_device_id(device_id)
-                .build(),
-            #[cfg(feature = "coreml")]
-            Device::CoreML => CoreMLExecutionProvider::default().build(),
-        }).collect();
+        let fallback = (!devices.contains(&Device::Cpu)).then_some(Device::Cpu);
+        let mut providers = Vec::with_capacity(devices.len() + 1);
+        let mut active = None;
+        for device in devices.into_iter().chain(fallback) {
+            let (provider, available) = device.provider();
+            if available {
+                active.get_or_insert(device.name());
+                providers.push(provider);
+            } else {
+                tracing::warn!("The {} execution provider is unavailable", device.name());
+            }
+        }
+        let active = active.unwrap_or("CPU");
+        tracing::info!("Using the {} execution provider", active);
 
-        ort::init()
-            .with_execution_providers(providers)
-            .commit()?;
+        ort::init().with_execution_providers(providers).commit()?;
+        *ACTIVE_PROVIDER.write().unwrap() = active;
         Ok(())
     }
 
+    /// Returns the name of the execution provider selected by `init`, e.g. `"CUDA"`
+    /// or `"CPU"`.
+    pub fn active_provider() -> &'static str {
+        *ACTIVE_PROVIDER.read().unwrap()
+    }
+
     /// Loads a model from a local file path.
     ///
     /// The path should point to a valid `.onnx` model file.
     pub fn load<P: AsRef<Path>>(model_path: P) -> Result<Self> {
         let threads = num_cpus::get();
-        let session = Session::builder()?
-            .with_parallel_execution(true)?
-            .with_inter_threads(1)?
-            .with_intra_threads(threads)?
-            .commit_from_file(model_path.as_ref())?;
+        let session = Session::builder()
+            .and_then(|b| b.with_parallel_execution(true))
+            .and_then(|b| b.with_inter_threads(1))
+            .and_then(|b| b.with_intra_threads(threads))
+            .and_then(|b| b.commit_from_file(model_path.as_ref()))
+            .map_err(TaggerError::from)
+            .with_context(|| format!("Failed to load model from {:?}", model_path.as_ref()))?;
+
+        let input_name = session
+            .inputs
+            .first()
+            .map(|i| i.name.clone())
+            .ok_or_else(|| TaggerError::Model("Model has no inputs".to_string()))?;
 
         let output_name = session
             .outputs
             .first()
             .map(|o| o.name.clone())
-            .context("Model has no outputs")?;
+            .ok_or_else(|| TaggerError::Model("Model has no outputs".to_string()))?;
 
         Ok(Self {
             session,
+            input_name,
             output_name,
         })
     }
 
+    /// Returns the name of the model's input tensor.
+    pub fn input_name(&self) -> &str {
+        &self.input_name
+    }
+
     /// Loads a model from a Hugging Face repository.
     ///
     /// This will download the model file if it's not already cached.
     pub async fn from_pretrained(repo_id: &str) -> Result<Self> {
-        let model_path = TaggerModelFile::new(repo_id).get().await?;
+        Self::from_pretrained_with_cancel(repo_id, None).await
+    }
+
+    /// Loads a model from a Hugging Face repository, aborting the download when
+    /// `cancel` is triggered.
+    pub async fn from_pretrained_with_cancel(
+        repo_id: &str,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Self> {
+        let model_path = TaggerModelFile::new(repo_id).get_with_cancel(cancel).await?;
         Self::load(&model_path)
     }
 
@@ -155,7 +237,8 @@ impl TaggerModel {
 
         let outputs = self
             .session
-            .run(ort::inputs!["input" => input_tensor])
+            .run(ort::inputs![self.input_name.as_str() => input_tensor])
+            .map_err(TaggerError::from)
             .context("Failed to run model prediction")?;
 
         let preds = outputs[self.output_name.as_str()]
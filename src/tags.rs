@@ -1,11 +1,19 @@
-use crate::file::TagCSVFile;
+use crate::{
+    error::TaggerError,
+    file::{TagCSVFile, TagJSONFile},
+    pipeline::{CategoryThresholds, TaggingResult},
+};
 use anyhow::{Context, Result};
 use indexmap::IndexMap;
+use itertools::Itertools;
 use ndarray::{Array1, Array2};
-use serde::Deserialize;
+use serde::{de::IntoDeserializer, Deserialize, Deserializer};
 use std::collections::HashMap;
 use std::path::Path;
 
+pub mod format;
+pub mod implications;
+
 /// Each record in the CSV file
 #[derive(Debug, Deserialize, Clone)]
 pub struct Tag {
@@ -15,8 +23,32 @@ pub struct Tag {
     count: i32,
 }
 
+/// A record of a `tags.json` file.
+#[derive(Debug, Deserialize)]
+struct JsonTag {
+    tag_id: i32,
+    name: String,
+    #[serde(deserialize_with = "deserialize_json_category")]
+    category: TagCategory,
+    count: i32,
+    #[serde(default)]
+    embedding: Option<Vec<f32>>,
+}
+
+/// Deserializes a category written either as a number (`4`) or, like the CSV, as a
+/// string (`"4"`).
+fn deserialize_json_category<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<TagCategory, D::Error> {
+    let id = match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(id) => id,
+        value => value.to_string(),
+    };
+    TagCategory::deserialize(id.as_str().into_deserializer())
+}
+
 /// Tag category
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum TagCategory {
     #[serde(rename = "0")]
     General,
@@ -55,16 +87,35 @@ impl Tag {
 pub struct LabelTags {
     label2tag: HashMap<String, Tag>,
     idx2tag: HashMap<usize, Tag>,
+    label2idx: HashMap<String, usize>,
+    /// The output indices of each category's tags, in ascending order.
+    category_indices: HashMap<TagCategory, Vec<usize>>,
     embeddings: Option<Array2<f32>>,
+    unknown_tag_policy: UnknownTagPolicy,
+}
+
+/// What to do when a model outputs more classes than the tag CSV has rows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownTagPolicy {
+    /// Fail with a length mismatch error.
+    #[default]
+    Error,
+    /// Name the extra outputs `unknown_<idx>`. They belong to no category, so they
+    /// appear in the raw pairs but never in categorized results.
+    Synthesize,
 }
 
 impl LabelTags {
     /// Load from the local CSV file
     pub fn load<P: AsRef<Path>>(csv_path: P) -> Result<Self> {
         let mut reader = csv::Reader::from_path(csv_path.as_ref())
+            .map_err(TaggerError::from)
             .with_context(|| format!("Failed to read CSV file at {:?}", csv_path.as_ref()))?;
-        let headers = reader.headers()?.clone();
-        let records: Vec<_> = reader.records().collect::<Result<_, _>>()?;
+        let headers = reader.headers().map_err(TaggerError::from)?.clone();
+        let records: Vec<_> = reader
+            .records()
+            .collect::<Result<_, _>>()
+            .map_err(TaggerError::from)?;
 
         let embedding_cols: Vec<_> = headers
             .iter()
@@ -73,16 +124,14 @@ impl LabelTags {
             .map(|(i, _)| i)
             .collect();
 
-        let mut label2tag = HashMap::with_capacity(records.len());
-        let mut idx2tag = HashMap::with_capacity(records.len());
+        let mut tags = Vec::with_capacity(records.len());
         let mut embeddings_vec = Vec::with_capacity(records.len());
 
-        for (i, record) in records.iter().enumerate() {
+        for record in &records {
             let tag: Tag = record
                 .deserialize(Some(&headers))
                 .context("Failed to deserialize tag record")?;
-            label2tag.insert(tag.name.clone(), tag.clone());
-            idx2tag.insert(i, tag);
+            tags.push(tag);
 
             if !embedding_cols.is_empty() {
                 let embedding_row: Result<Vec<f32>, _> = embedding_cols
@@ -97,6 +146,66 @@ impl LabelTags {
             }
         }
 
+        Self::from_tags(tags, embeddings_vec)
+    }
+
+    /// Load from a local JSON file holding an array of tag records with the same
+    /// fields as the CSV (`tag_id`, `name`, `category`, `count`).
+    ///
+    /// `category` may be a number or a string. When every record has an `embedding`
+    /// array, predictions are projected through them like the CSV's `embedding__*`
+    /// columns.
+    pub fn load_json<P: AsRef<Path>>(json_path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(json_path.as_ref())
+            .map_err(TaggerError::from)
+            .with_context(|| format!("Failed to read JSON file at {:?}", json_path.as_ref()))?;
+        let records: Vec<JsonTag> = serde_json::from_str(&content)
+            .map_err(TaggerError::from)
+            .context("Failed to deserialize tag records")?;
+
+        let with_embedding = records.iter().filter(|r| r.embedding.is_some()).count();
+        if with_embedding != 0 && with_embedding != records.len() {
+            return Err(TaggerError::Config(format!(
+                "Only {} of {} tag records have an embedding",
+                with_embedding,
+                records.len()
+            ))
+            .into());
+        }
+
+        let (tags, embeddings_vec): (Vec<Tag>, Vec<Option<Vec<f32>>>) = records
+            .into_iter()
+            .map(|record| {
+                let tag = Tag {
+                    tag_id: record.tag_id,
+                    name: record.name,
+                    category: record.category,
+                    count: record.count,
+                };
+                (tag, record.embedding)
+            })
+            .unzip();
+        Self::from_tags(tags, embeddings_vec.into_iter().flatten().collect())
+    }
+
+    /// Builds the lookup maps from tags in model output order, with one embedding row
+    /// per tag or none at all.
+    fn from_tags(tags: Vec<Tag>, embeddings_vec: Vec<Vec<f32>>) -> Result<Self> {
+        let mut label2tag = HashMap::with_capacity(tags.len());
+        let mut idx2tag = HashMap::with_capacity(tags.len());
+        let mut label2idx = HashMap::with_capacity(tags.len());
+        let mut category_indices: HashMap<TagCategory, Vec<usize>> = HashMap::new();
+
+        for (i, tag) in tags.into_iter().enumerate() {
+            label2tag.insert(tag.name.clone(), tag.clone());
+            label2idx.insert(tag.name.clone(), i);
+            category_indices
+                .entry(tag.category.clone())
+                .or_default()
+                .push(i);
+            idx2tag.insert(i, tag);
+        }
+
         let embeddings = if !embeddings_vec.is_empty() {
             let rows = embeddings_vec.len();
             let cols = embeddings_vec[0].len();
@@ -112,13 +221,23 @@ impl LabelTags {
         Ok(Self {
             label2tag,
             idx2tag,
+            label2idx,
+            category_indices,
             embeddings,
+            unknown_tag_policy: UnknownTagPolicy::default(),
         })
     }
 
+    /// Loads the tags of `repo_id`, from its `selected_tags.csv` or, when the repo has
+    /// none, from its `tags.json`.
     pub async fn from_pretrained(repo_id: &str) -> Result<Self> {
-        let csv_path = TagCSVFile::new(repo_id).get().await?;
-        Self::load(csv_path)
+        match TagCSVFile::new(repo_id).get().await {
+            Ok(csv_path) => Self::load(csv_path),
+            Err(csv_err) => match TagJSONFile::new(repo_id).get().await {
+                Ok(json_path) => Self::load_json(json_path),
+                Err(_) => Err(csv_err),
+            },
+        }
     }
 
     /// Create pairs of tag and probability with given tensor
@@ -133,31 +252,153 @@ impl LabelTags {
                 Ok(probs_vec
                     .into_iter()
                     .enumerate()
-                    .map(|(idx, prob)| (self.idx2tag[&idx].name(), prob))
+                    .map(|(idx, prob)| (self.tag_name(idx), prob))
                     .collect())
             })
             .collect()
     }
 
+    /// Returns whether the CSV has `embedding__*` columns that predictions are
+    /// projected through.
+    pub fn has_embeddings(&self) -> bool {
+        self.embeddings.is_some()
+    }
+
+    /// Projects raw model outputs through the CSV's embedding columns.
+    ///
+    /// Fails with `TaggerError::Config` when the CSV has no embedding columns.
+    pub fn project_embedding(&self, features: Vec<f32>) -> Result<Vec<f32>> {
+        let embeddings = self.embeddings.as_ref().ok_or_else(|| {
+            TaggerError::Config("The tag CSV has no embedding__* columns".to_string())
+        })?;
+        anyhow::ensure!(
+            features.len() == embeddings.shape()[1],
+            "Prediction feature size ({}) mismatch with embedding dimension ({})",
+            features.len(),
+            embeddings.shape()[1]
+        );
+        Ok(embeddings.dot(&Array1::from_vec(features)).to_vec())
+    }
+
     fn get_probs_vec(&self, probs: Vec<f32>) -> Result<Vec<f32>> {
-        if let Some(embeddings) = &self.embeddings {
-            anyhow::ensure!(
-                probs.len() == embeddings.shape()[1],
-                "Prediction feature size ({}) mismatch with embedding dimension ({})",
-                probs.len(),
-                embeddings.shape()[1]
-            );
-            let pred_array = Array1::from_vec(probs);
-            Ok(embeddings.dot(&pred_array).to_vec())
+        if self.has_embeddings() {
+            self.project_embedding(probs)
         } else {
+            let extra_outputs_allowed = self.unknown_tag_policy == UnknownTagPolicy::Synthesize
+                && probs.len() > self.idx2tag.len();
             anyhow::ensure!(
-                probs.len() == self.idx2tag.len(),
+                probs.len() == self.idx2tag.len() || extra_outputs_allowed,
                 "Tags and probabilities length mismatch"
             );
             Ok(probs)
         }
     }
 
+    /// Returns the name of the tag at `idx`, or a synthetic `unknown_<idx>` name for
+    /// outputs beyond the end of the tag list.
+    fn tag_name(&self, idx: usize) -> String {
+        self.idx2tag
+            .get(&idx)
+            .map_or_else(|| format!("unknown_{}", idx), |tag| tag.name())
+    }
+
+    /// Sets how model outputs without a matching tag are handled.
+    pub fn set_unknown_tag_policy(&mut self, policy: UnknownTagPolicy) {
+        self.unknown_tag_policy = policy;
+    }
+
+    /// Returns the output indices of the tags of `category`, in ascending order.
+    ///
+    /// This lets callers pick a category's scores out of a model output by position
+    /// instead of looking up every tag name.
+    pub fn category_indices(&self, category: &TagCategory) -> &[usize] {
+        self.category_indices
+            .get(category)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Keeps the tags of `category` whose probability is at least `threshold`, sorted by
+    /// descending probability. A threshold of `0.0` or lower keeps every tag.
+    ///
+    /// `pairs` must be in model output order, as `create_probality_pairs` returns them,
+    /// since the category's tags are found by position.
+    pub fn filter_category(
+        &self,
+        pairs: &IndexMap<String, f32>,
+        category: &TagCategory,
+        threshold: f32,
+    ) -> IndexMap<String, f32> {
+        let keep_all = threshold <= 0.0;
+        self.category_indices(category)
+            .iter()
+            .filter_map(|&idx| pairs.get_index(idx))
+            .filter(|(_, &prob)| keep_all || prob >= threshold)
+            .sorted_by(|a, b| b.1.total_cmp(a.1))
+            .map(|(tag, &prob)| (tag.clone(), prob))
+            .collect()
+    }
+
+    /// Turns one image's raw model output into categorized, sorted tags, the same way
+    /// `TaggingPipeline::predict_batch` does.
+    ///
+    /// `thresholds`, when given, takes precedence over the uniform `threshold`. This lets
+    /// callers re-tune thresholds on cached raw outputs without running the model again.
+    ///
+    /// `probs` are compared against the thresholds as they are, so outputs of a pipeline
+    /// with an `Activation` other than `None` must go through `Activation::apply` first.
+    pub fn categorize(
+        &self,
+        probs: &[f32],
+        threshold: f32,
+        thresholds: Option<&CategoryThresholds>,
+    ) -> Result<TaggingResult> {
+        let pairs = self
+            .create_probality_pairs(vec![probs.to_vec()])?
+            .pop()
+            .context("No probabilities to categorize")?;
+        let threshold_for =
+            |category: &TagCategory| thresholds.map_or(threshold, |t| t.for_category(category));
+        let filter = |category: TagCategory| {
+            self.filter_category(&pairs, &category, threshold_for(&category))
+        };
+
+        Ok(TaggingResult::new(
+            filter(TagCategory::Rating),
+            filter(TagCategory::Character),
+            filter(TagCategory::General),
+            filter(TagCategory::Artist),
+            filter(TagCategory::Copyright),
+            filter(TagCategory::Meta),
+        ))
+    }
+
+    /// Converts tag-name/probability pairs into compact tag-index/probability pairs.
+    pub fn to_indexed(&self, pairs: &IndexMap<String, f32>) -> Result<Vec<(usize, f32)>> {
+        pairs
+            .iter()
+            .map(|(name, &prob)| {
+                self.label2idx
+                    .get(name)
+                    .map(|&idx| (idx, prob))
+                    .with_context(|| format!("Unknown tag: {}", name))
+            })
+            .collect()
+    }
+
+    /// Expands tag-index/probability pairs back into tag-name/probability pairs.
+    pub fn expand_indexed(&self, pairs: &[(usize, f32)]) -> Result<IndexMap<String, f32>> {
+        pairs
+            .iter()
+            .map(|&(idx, prob)| {
+                self.idx2tag
+                    .get(&idx)
+                    .map(|tag| (tag.name(), prob))
+                    .with_context(|| format!("Unknown tag index: {}", idx))
+            })
+            .collect()
+    }
+
     pub fn label2tag(&self) -> &HashMap<String, Tag> {
         &self.label2tag
     }
@@ -200,6 +441,19 @@ mod test {
         assert_eq!(*pairs[1].get("1girl").unwrap(), 0.2);
     }
 
+    #[test]
+    fn test_indexed_round_trip() {
+        let tags = run_async(LabelTags::from_pretrained("SmilingWolf/wd-swinv2-tagger-v3")).unwrap();
+        let num_tags = tags.idx2tag().len();
+        let probabilities = vec![(0..num_tags).map(|i| i as f32 / num_tags as f32).collect()];
+        let pairs = tags.create_probality_pairs(probabilities).unwrap().remove(0);
+
+        let indexed = tags.to_indexed(&pairs).unwrap();
+        assert_eq!(indexed.len(), num_tags);
+        assert_eq!(tags.expand_indexed(&indexed).unwrap(), pairs);
+        assert!(tags.expand_indexed(&[(num_tags, 0.5)]).is_err());
+    }
+
     #[test]
     fn test_create_probability_pairs_mismatch() {
         let tags = run_async(LabelTags::from_pretrained("SmilingWolf/wd-swinv2-tagger-v3")).unwrap();
@@ -213,4 +467,149 @@ mod test {
             "Tags and probabilities length mismatch"
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_unknown_tag_policy_synthesize() {
+        let mut tags =
+            run_async(LabelTags::from_pretrained("SmilingWolf/wd-swinv2-tagger-v3")).unwrap();
+        let num_tags = tags.idx2tag().len();
+        tags.set_unknown_tag_policy(UnknownTagPolicy::Synthesize);
+
+        let pairs = tags
+            .create_probality_pairs(vec![vec![0.1; num_tags + 2]])
+            .unwrap()
+            .remove(0);
+        assert_eq!(pairs.len(), num_tags + 2);
+        assert_eq!(pairs[&format!("unknown_{}", num_tags)], 0.1);
+        assert_eq!(pairs[&format!("unknown_{}", num_tags + 1)], 0.1);
+
+        // Unknown tags are not assigned to any category.
+        let result = tags.categorize(&vec![0.9; num_tags + 2], 0.5, None).unwrap();
+        assert!(!result.general.keys().any(|name| name.starts_with("unknown_")));
+
+        // Fewer outputs than tags is still an error.
+        assert!(tags.create_probality_pairs(vec![vec![0.1; num_tags - 1]]).is_err());
+    }
+
+    #[test]
+    fn test_categorize_all_categories() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let csv_path = temp_dir.path().join("selected_tags.csv");
+        std::fs::write(
+            &csv_path,
+            "tag_id,name,category,count\n\
+             0,general,9,10\n\
+             1,1girl,0,10\n\
+             2,some_artist,1,10\n\
+             3,vocaloid,3,10\n\
+             4,hatsune_miku,4,10\n\
+             5,highres,5,10\n",
+        )
+        .unwrap();
+        let tags = LabelTags::load(&csv_path).unwrap();
+
+        let result = tags
+            .categorize(&[0.9, 0.8, 0.7, 0.6, 0.5, 0.1], 0.3, None)
+            .unwrap();
+        assert_eq!(result.rating["general"], 0.9);
+        assert_eq!(result.general["1girl"], 0.8);
+        assert_eq!(result.artist["some_artist"], 0.7);
+        assert_eq!(result.copyright["vocaloid"], 0.6);
+        assert_eq!(result.character["hatsune_miku"], 0.5);
+        // Below the threshold.
+        assert!(result.meta.is_empty());
+
+        assert_eq!(tags.category_indices(&TagCategory::Copyright), &[3]);
+        assert_eq!(tags.category_indices(&TagCategory::Meta), &[5]);
+    }
+
+    #[test]
+    fn test_project_embedding() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let csv_path = temp_dir.path().join("selected_tags.csv");
+        std::fs::write(
+            &csv_path,
+            "tag_id,name,category,count,embedding__0,embedding__1\n\
+             0,1girl,0,10,1.0,0.0\n\
+             1,solo,0,5,0.5,2.0\n",
+        )
+        .unwrap();
+        let tags = LabelTags::load(&csv_path).unwrap();
+
+        assert!(tags.has_embeddings());
+        assert_eq!(
+            tags.project_embedding(vec![2.0, 1.0]).unwrap(),
+            vec![2.0, 3.0]
+        );
+        assert!(tags.project_embedding(vec![1.0]).is_err());
+
+        let plain = run_async(LabelTags::from_pretrained(
+            "SmilingWolf/wd-swinv2-tagger-v3",
+        ))
+        .unwrap();
+        let err = plain.project_embedding(vec![1.0]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TaggerError>(),
+            Some(TaggerError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_json() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let json_path = temp_dir.path().join("tags.json");
+        std::fs::write(
+            &json_path,
+            r#"[
+                {"tag_id": 9999999, "name": "general", "category": 9, "count": 100},
+                {"tag_id": 1, "name": "hatsune_miku", "category": "4", "count": 50},
+                {"tag_id": 2, "name": "1girl", "category": 0, "count": 80}
+            ]"#,
+        )
+        .unwrap();
+
+        let tags = LabelTags::load_json(&json_path).unwrap();
+        assert!(!tags.has_embeddings());
+        assert_eq!(tags.idx2tag()[&1].name(), "hatsune_miku");
+        assert_eq!(
+            tags.label2tag()["hatsune_miku"].category(),
+            TagCategory::Character
+        );
+        assert_eq!(tags.label2tag()["general"].category(), TagCategory::Rating);
+        assert_eq!(tags.label2tag()["1girl"].count(), 80);
+    }
+
+    #[test]
+    fn test_load_json_embeddings() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let json_path = temp_dir.path().join("tags.json");
+
+        std::fs::write(
+            &json_path,
+            r#"[
+                {"tag_id": 1, "name": "1girl", "category": 0, "count": 10, "embedding": [1.0, 0.0]},
+                {"tag_id": 2, "name": "solo", "category": 0, "count": 5, "embedding": [0.5, 2.0]}
+            ]"#,
+        )
+        .unwrap();
+        let tags = LabelTags::load_json(&json_path).unwrap();
+        assert_eq!(
+            tags.project_embedding(vec![2.0, 1.0]).unwrap(),
+            vec![2.0, 3.0]
+        );
+
+        std::fs::write(
+            &json_path,
+            r#"[
+                {"tag_id": 1, "name": "1girl", "category": 0, "count": 10, "embedding": [1.0, 0.0]},
+                {"tag_id": 2, "name": "solo", "category": 0, "count": 5}
+            ]"#,
+        )
+        .unwrap();
+        let err = LabelTags::load_json(&json_path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TaggerError>(),
+            Some(TaggerError::Config(_))
+        ));
+    }
+}
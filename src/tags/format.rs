@@ -0,0 +1,154 @@
+//! # Tag Formatting
+//!
+//! Turns a `TaggingResult` into a Danbooru-style caption string, such as the
+//! prompts used to train or condition image models.
+
+use crate::pipeline::TaggingResult;
+
+/// Tags whose underscores are part of the tag itself (mostly emoticons), so they are
+/// kept as is when underscores are replaced with spaces.
+#[rustfmt::skip]
+pub const UNDERSCORE_TAGS: [&str; 19] = [
+    ">_<",
+    ">_o",
+    "0_0",
+    "o_o",
+    "3_3",
+    "6_9",
+    "@_@",
+    "u_u",
+    "x_x",
+    "^_^",
+    "|_|",
+    "=_=",
+    "+_+",
+    "+_-",
+    "._.",
+    "<o>_<o>",
+    "<|>_<|>",
+    "||_||",
+    "(o)_(o)",
+];
+
+/// Replaces the underscores of `tag` with spaces, unless it is one of `UNDERSCORE_TAGS`.
+pub fn fix_tag_underscore(tag: &str) -> String {
+    if UNDERSCORE_TAGS.contains(&tag) {
+        tag.to_string()
+    } else {
+        tag.replace('_', " ")
+    }
+}
+
+/// Options for `format_tags`.
+#[derive(Debug, Clone)]
+pub struct TagFormatOptions {
+    /// The string placed between tags.
+    pub separator: String,
+    /// Keeps underscores instead of replacing them with spaces.
+    pub keep_underscores: bool,
+    /// Escapes `(` and `)` with a backslash, as prompt syntaxes use them for weights.
+    pub escape_parentheses: bool,
+    /// Orders character and general tags together by descending confidence instead of
+    /// listing character tags first.
+    pub sort_by_confidence: bool,
+    /// Starts the caption with the highest-scoring rating tag.
+    pub include_rating: bool,
+}
+
+impl Default for TagFormatOptions {
+    fn default() -> Self {
+        Self {
+            separator: ", ".to_string(),
+            keep_underscores: false,
+            escape_parentheses: true,
+            sort_by_confidence: false,
+            include_rating: false,
+        }
+    }
+}
+
+/// Formats the character and general tags of `result` as a single caption string.
+pub fn format_tags(result: &TaggingResult, opts: &TagFormatOptions) -> String {
+    let mut tags: Vec<(&str, f32)> = result
+        .character
+        .iter()
+        .chain(&result.general)
+        .map(|(tag, &score)| (tag.as_str(), score))
+        .collect();
+    if opts.sort_by_confidence {
+        // A stable sort keeps character tags first among equal scores.
+        tags.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    }
+
+    let rating = result
+        .top_rating()
+        .filter(|_| opts.include_rating)
+        .map(|(tag, _)| tag);
+
+    rating
+        .into_iter()
+        .chain(tags.into_iter().map(|(tag, _)| tag))
+        .map(|tag| format_tag(tag, opts))
+        .collect::<Vec<_>>()
+        .join(&opts.separator)
+}
+
+/// Applies the underscore and parenthesis options to a single tag.
+fn format_tag(tag: &str, opts: &TagFormatOptions) -> String {
+    let tag = if opts.keep_underscores {
+        tag.to_string()
+    } else {
+        fix_tag_underscore(tag)
+    };
+    if opts.escape_parentheses {
+        tag.replace('(', "\\(").replace(')', "\\)")
+    } else {
+        tag
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::Prediction;
+
+    fn result() -> TaggingResult {
+        let prediction = |pairs: &[(&str, f32)]| -> Prediction {
+            pairs
+                .iter()
+                .map(|&(tag, score)| (tag.to_string(), score))
+                .collect()
+        };
+        TaggingResult::new(
+            prediction(&[("general", 0.9)]),
+            prediction(&[("hatsune_miku_(cosplay)", 0.6)]),
+            prediction(&[("long_hair", 0.8), ("^_^", 0.5)]),
+            prediction(&[]),
+            prediction(&[]),
+            prediction(&[]),
+        )
+    }
+
+    #[test]
+    fn test_format_tags_escapes_parentheses() {
+        assert_eq!(
+            format_tags(&result(), &TagFormatOptions::default()),
+            "hatsune miku \\(cosplay\\), long hair, ^_^"
+        );
+    }
+
+    #[test]
+    fn test_format_tags_options() {
+        let opts = TagFormatOptions {
+            separator: " ".to_string(),
+            keep_underscores: true,
+            escape_parentheses: false,
+            sort_by_confidence: true,
+            include_rating: true,
+        };
+        assert_eq!(
+            format_tags(&result(), &opts),
+            "general long_hair hatsune_miku_(cosplay) ^_^"
+        );
+    }
+}
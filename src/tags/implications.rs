@@ -0,0 +1,156 @@
+//! # Tag Implications
+//!
+//! Danbooru tags imply more general ones, e.g. `cat_ears` implies `animal_ears`. A
+//! model may predict the specific tag without its parents, so `TagImplications` lets
+//! results be expanded with every tag their tags imply.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+};
+
+use crate::error::TaggerError;
+
+/// A row of an implications CSV.
+#[derive(Debug, Deserialize)]
+struct ImplicationRecord {
+    antecedent: String,
+    consequent: String,
+}
+
+/// A map from tags to the tags they imply.
+///
+/// Implications are followed transitively, so `cat_ears -> animal_ears` and
+/// `animal_ears -> ears` add both parents to a result with `cat_ears`.
+#[derive(Debug, Clone, Default)]
+pub struct TagImplications {
+    implications: HashMap<String, Vec<String>>,
+    injected_score: Option<f32>,
+}
+
+impl TagImplications {
+    /// Creates an empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads implications from a JSON object mapping each tag to the tags it implies,
+    /// e.g. `{"cat_ears": ["animal_ears"]}`.
+    pub fn load_json<P: AsRef<Path>>(json_path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(json_path.as_ref())
+            .map_err(TaggerError::from)
+            .with_context(|| format!("Failed to read JSON file at {:?}", json_path.as_ref()))?;
+        let implications: HashMap<String, Vec<String>> = serde_json::from_str(&content)
+            .map_err(TaggerError::from)
+            .context("Failed to deserialize tag implications")?;
+        Ok(Self {
+            implications,
+            injected_score: None,
+        })
+    }
+
+    /// Loads implications from a CSV file with `antecedent` and `consequent` columns,
+    /// one implication per row.
+    pub fn load_csv<P: AsRef<Path>>(csv_path: P) -> Result<Self> {
+        let mut reader = csv::Reader::from_path(csv_path.as_ref())
+            .map_err(TaggerError::from)
+            .with_context(|| format!("Failed to read CSV file at {:?}", csv_path.as_ref()))?;
+        let mut implications = Self::new();
+        for record in reader.deserialize() {
+            let record: ImplicationRecord = record
+                .map_err(TaggerError::from)
+                .context("Failed to deserialize implication record")?;
+            implications.add(record.antecedent, record.consequent);
+        }
+        Ok(implications)
+    }
+
+    /// Records that `antecedent` implies `consequent`.
+    pub fn add(&mut self, antecedent: impl Into<String>, consequent: impl Into<String>) {
+        let consequents = self.implications.entry(antecedent.into()).or_default();
+        let consequent = consequent.into();
+        if !consequents.contains(&consequent) {
+            consequents.push(consequent);
+        }
+    }
+
+    /// Gives implied tags a fixed score instead of the score of the tag implying them.
+    pub fn with_injected_score(mut self, score: f32) -> Self {
+        self.injected_score = Some(score);
+        self
+    }
+
+    /// Returns the score an added parent gets when implied by a tag scoring `score`.
+    pub fn implied_score(&self, score: f32) -> f32 {
+        self.injected_score.unwrap_or(score)
+    }
+
+    /// Returns the tags `tag` implies directly.
+    pub fn direct(&self, tag: &str) -> &[String] {
+        self.implications
+            .get(tag)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns every tag `tag` implies, directly or through other implied tags, in
+    /// breadth-first order. Cycles are followed only once.
+    pub fn implied(&self, tag: &str) -> Vec<&str> {
+        let mut implied = Vec::new();
+        let mut seen = HashSet::from([tag]);
+        let mut queue = VecDeque::from([tag]);
+        while let Some(current) = queue.pop_front() {
+            for parent in self.direct(current).iter().map(String::as_str) {
+                if seen.insert(parent) {
+                    implied.push(parent);
+                    queue.push_back(parent);
+                }
+            }
+        }
+        implied
+    }
+
+    /// Returns whether no implications are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.implications.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_implied_is_transitive() {
+        let mut implications = TagImplications::new();
+        implications.add("cat_ears", "animal_ears");
+        implications.add("animal_ears", "ears");
+        implications.add("ears", "cat_ears");
+
+        let implied = implications.implied("cat_ears");
+        assert_eq!(implied, vec!["animal_ears", "ears"]);
+        assert!(implications.implied("solo").is_empty());
+    }
+
+    #[test]
+    fn test_load_json_and_csv() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let json_path = temp_dir.path().join("implications.json");
+        std::fs::write(&json_path, r#"{"cat_ears": ["animal_ears"]}"#).unwrap();
+        let csv_path = temp_dir.path().join("implications.csv");
+        std::fs::write(
+            &csv_path,
+            "antecedent,consequent\ncat_ears,animal_ears\ncat_ears,animal_ears\n",
+        )
+        .unwrap();
+
+        for implications in [
+            TagImplications::load_json(&json_path).unwrap(),
+            TagImplications::load_csv(&csv_path).unwrap(),
+        ] {
+            assert_eq!(implications.direct("cat_ears"), ["animal_ears"]);
+        }
+    }
+}
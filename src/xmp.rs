@@ -0,0 +1,246 @@
+//! # XMP Metadata
+//!
+//! This module writes tags into an image's own XMP metadata (`dc:subject`), so they
+//! travel with the file and show up in photo managers such as Lightroom or digiKam.
+//!
+//! JPEG (APP1 segment) and PNG (`iTXt` chunk) files are supported. Writing replaces
+//! any XMP packet already in the file.
+
+use anyhow::{Context, Result};
+use std::{fs, path::Path};
+
+/// The identifier that starts a JPEG APP1 segment holding XMP.
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// The keyword of the PNG `iTXt` chunk holding XMP.
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Writes `tags` into the XMP `dc:subject` of the JPEG or PNG at `path`.
+pub fn write_tags_to_xmp<S: AsRef<str>>(path: &Path, tags: &[S]) -> Result<()> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let packet = build_packet(tags);
+
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    let output = match extension.as_deref() {
+        Some("jpg" | "jpeg") => insert_into_jpeg(&data, packet.as_bytes()),
+        Some("png") => insert_into_png(&data, packet.as_bytes()),
+        _ => anyhow::bail!("XMP writing is only supported for JPEG and PNG: {:?}", path),
+    }
+    .with_context(|| format!("Failed to embed XMP into {:?}", path))?;
+
+    fs::write(path, output).with_context(|| format!("Failed to write {:?}", path))
+}
+
+/// Reads the tags from the XMP `dc:subject` of the file at `path`.
+///
+/// The packet is located by scanning for its `x:xmpmeta` element, as the XMP
+/// specification allows, so this works for any format that stores XMP uncompressed.
+/// Returns an empty list when the file has no XMP.
+pub fn read_tags_from_xmp(path: &Path) -> Result<Vec<String>> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let text = String::from_utf8_lossy(&data);
+
+    let Some(start) = text.find("<x:xmpmeta") else {
+        return Ok(Vec::new());
+    };
+    let packet = &text[start..];
+    let packet = &packet[..packet.find("</x:xmpmeta>").unwrap_or(packet.len())];
+    let Some(subject_start) = packet.find("<dc:subject>") else {
+        return Ok(Vec::new());
+    };
+    let subject = &packet[subject_start..];
+    let subject = &subject[..subject.find("</dc:subject>").unwrap_or(subject.len())];
+
+    Ok(subject
+        .split("<rdf:li>")
+        .skip(1)
+        .filter_map(|item| item.split_once("</rdf:li>"))
+        .map(|(tag, _)| unescape_xml(tag))
+        .collect())
+}
+
+/// Builds an XMP packet whose `dc:subject` bag holds `tags`.
+fn build_packet<S: AsRef<str>>(tags: &[S]) -> String {
+    let items: String = tags
+        .iter()
+        .map(|tag| format!("     <rdf:li>{}</rdf:li>\n", escape_xml(tag.as_ref())))
+        .collect();
+
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+         \x20<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+         \x20 <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+         \x20  <dc:subject>\n\
+         \x20   <rdf:Bag>\n\
+         {}\
+         \x20   </rdf:Bag>\n\
+         \x20  </dc:subject>\n\
+         \x20 </rdf:Description>\n\
+         \x20</rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>",
+        items
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// Returns a copy of the JPEG `data` with its XMP APP1 segment replaced by `packet`.
+///
+/// The new segment goes right after the JFIF APP0 segment, where readers expect it.
+fn insert_into_jpeg(data: &[u8], packet: &[u8]) -> Result<Vec<u8>> {
+    anyhow::ensure!(data.starts_with(&[0xFF, 0xD8]), "Not a JPEG file");
+    let segment_len = 2 + JPEG_XMP_HEADER.len() + packet.len();
+    anyhow::ensure!(segment_len <= u16::MAX as usize, "XMP packet is too large");
+
+    let mut xmp_segment = vec![0xFF, 0xE1];
+    xmp_segment.extend_from_slice(&(segment_len as u16).to_be_bytes());
+    xmp_segment.extend_from_slice(JPEG_XMP_HEADER);
+    xmp_segment.extend_from_slice(packet);
+
+    let mut output = Vec::with_capacity(data.len() + xmp_segment.len());
+    output.extend_from_slice(&data[..2]);
+    let mut xmp_segment = Some(xmp_segment);
+    let mut pos = 2;
+
+    loop {
+        anyhow::ensure!(pos + 4 <= data.len() && data[pos] == 0xFF, "Malformed JPEG segment");
+        let marker = data[pos + 1];
+
+        if marker != 0xE0 {
+            if let Some(segment) = xmp_segment.take() {
+                output.extend_from_slice(&segment);
+            }
+        }
+        // Start of scan: the rest is entropy-coded image data.
+        if marker == 0xDA {
+            output.extend_from_slice(&data[pos..]);
+            break;
+        }
+
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        anyhow::ensure!(end <= data.len(), "Truncated JPEG segment");
+
+        let is_xmp = marker == 0xE1 && data[pos + 4..end].starts_with(JPEG_XMP_HEADER);
+        if !is_xmp {
+            output.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+
+    Ok(output)
+}
+
+/// Returns a copy of the PNG `data` with its XMP `iTXt` chunk replaced by `packet`.
+///
+/// The new chunk goes right after `IHDR`.
+fn insert_into_png(data: &[u8], packet: &[u8]) -> Result<Vec<u8>> {
+    anyhow::ensure!(data.starts_with(PNG_SIGNATURE), "Not a PNG file");
+
+    // keyword, null separator, no compression, no language tag or translated keyword
+    let mut chunk_data = PNG_XMP_KEYWORD.to_vec();
+    chunk_data.extend_from_slice(&[0, 0, 0, 0, 0]);
+    chunk_data.extend_from_slice(packet);
+
+    let mut output = Vec::with_capacity(data.len() + chunk_data.len() + 12);
+    output.extend_from_slice(PNG_SIGNATURE);
+    let mut pos = PNG_SIGNATURE.len();
+
+    while pos < data.len() {
+        anyhow::ensure!(pos + 12 <= data.len(), "Truncated PNG chunk");
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into()?) as usize;
+        let end = pos + 12 + len;
+        anyhow::ensure!(end <= data.len(), "Truncated PNG chunk");
+        let chunk_type = &data[pos + 4..pos + 8];
+        let body = &data[pos + 8..pos + 8 + len];
+
+        let is_xmp = chunk_type == b"iTXt"
+            && body.starts_with(PNG_XMP_KEYWORD)
+            && body.get(PNG_XMP_KEYWORD.len()) == Some(&0);
+        if !is_xmp {
+            output.extend_from_slice(&data[pos..end]);
+        }
+        if chunk_type == b"IHDR" {
+            write_png_chunk(&mut output, b"iTXt", &chunk_data);
+        }
+        pos = end;
+    }
+
+    Ok(output)
+}
+
+fn write_png_chunk(output: &mut Vec<u8>, chunk_type: &[u8; 4], body: &[u8]) {
+    output.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    let crc_start = output.len();
+    output.extend_from_slice(chunk_type);
+    output.extend_from_slice(body);
+    let crc = crc32(&output[crc_start..]);
+    output.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// The CRC-32 used by PNG chunks.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_xmp_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let tags = ["1girl", "long hair", "^_^", "black & white"];
+
+        for name in ["image.png", "image.jpg"] {
+            let path = temp_dir.path().join(name);
+            RgbImage::from_pixel(16, 16, Rgb([200, 100, 50])).save(&path).unwrap();
+            assert!(read_tags_from_xmp(&path).unwrap().is_empty());
+
+            write_tags_to_xmp(&path, &tags).unwrap();
+            assert_eq!(read_tags_from_xmp(&path).unwrap(), tags);
+
+            // Writing again replaces the previous packet instead of adding another.
+            write_tags_to_xmp(&path, &["cat"]).unwrap();
+            assert_eq!(read_tags_from_xmp(&path).unwrap(), ["cat"]);
+            let data = fs::read(&path).unwrap();
+            assert_eq!(String::from_utf8_lossy(&data).matches("<x:xmpmeta").count(), 1);
+
+            // The image must still decode.
+            assert_eq!(image::open(&path).unwrap().width(), 16);
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    }
+}
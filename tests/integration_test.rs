@@ -1,7 +1,12 @@
-use eros::prelude::{
-    convert_and_strip_metadata, rename_files_in_selected_dirs, resize_media,
-    suggest_media_directories,
+use eros::{
+    prelude::{
+        convert_and_strip_metadata, convert_and_strip_metadata_with_options,
+        rename_files_in_selected_dirs, resize_media, suggest_media_directories, undo_rename,
+        ConvertOptions, TargetFormat, RENAME_MANIFEST_FILE,
+    },
+    processor::open_image,
 };
+use image::{codecs::jpeg::JpegEncoder, Rgb, RgbImage};
 use std::fs;
 use std::path::Path;
 use tempfile::tempdir;
@@ -11,6 +16,20 @@ use common::setup;
 
 #[test]
 fn test_full_preprocessing_pipeline() {
+    run_preprocessing_pipeline(TargetFormat::Png);
+}
+
+#[test]
+fn test_full_preprocessing_pipeline_jpeg() {
+    run_preprocessing_pipeline(TargetFormat::Jpeg);
+}
+
+#[test]
+fn test_full_preprocessing_pipeline_webp() {
+    run_preprocessing_pipeline(TargetFormat::WebP);
+}
+
+fn run_preprocessing_pipeline(target_format: TargetFormat) {
     // 1. Setup: Ensure assets are downloaded and create a temporary directory for the test.
     setup();
     let temp_dir = tempdir().unwrap();
@@ -42,13 +61,27 @@ fn test_full_preprocessing_pipeline() {
     assert!(renamed_video_path.exists());
 
     // 4. Test convert_and_strip_metadata
-    convert_and_strip_metadata(&selected_dirs).unwrap();
+    let options = ConvertOptions {
+        keep_originals: false,
+        target_format,
+        ..ConvertOptions::default()
+    };
+    convert_and_strip_metadata_with_options(&selected_dirs, &options).unwrap();
 
-    let converted_image_path = temp_dir.path().join("1.png");
+    let converted_image_path = temp_dir
+        .path()
+        .join("1")
+        .with_extension(target_format.extension());
     let converted_video_path = temp_dir.path().join("2.mp4"); // Stays mp4
     assert!(converted_image_path.exists());
     assert!(converted_video_path.exists());
-    assert!(!renamed_image_path.exists()); // Original should be deleted
+    if converted_image_path != renamed_image_path {
+        assert!(!renamed_image_path.exists()); // Original should be deleted
+    }
+    assert_eq!(
+        image::ImageFormat::from_path(&converted_image_path).unwrap(),
+        image::guess_format(&fs::read(&converted_image_path).unwrap()).unwrap()
+    );
 
     // 5. Test resize_media
     resize_media(&selected_dirs, (448, 448)).unwrap();
@@ -69,4 +102,151 @@ fn test_full_preprocessing_pipeline() {
         .unwrap();
     assert_eq!(video.width(), 448);
     assert_eq!(video.height(), 448);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_resize_video_with_special_characters_in_name() {
+    setup();
+    let temp_dir = tempdir().unwrap();
+    let video_path = temp_dir.path().join("my clip (1) & 'draft'; -x:y.mp4");
+    fs::copy("tests/assets/test_video.mp4", &video_path).unwrap();
+
+    resize_media(&[temp_dir.path().to_path_buf()], (448, 448)).unwrap();
+
+    assert!(video_path.exists());
+    ffmpeg_next::init().unwrap();
+    let ictx = ffmpeg_next::format::input(&video_path).unwrap();
+    let stream = ictx.streams().best(ffmpeg_next::media::Type::Video).unwrap();
+    let video = ffmpeg_next::codec::context::Context::from_parameters(stream.parameters())
+        .unwrap()
+        .decoder()
+        .video()
+        .unwrap();
+    assert_eq!(video.width(), 448);
+    assert_eq!(video.height(), 448);
+}
+
+#[test]
+fn test_convert_keeps_originals_by_default() {
+    setup();
+    let temp_dir = tempdir().unwrap();
+    let image_path = temp_dir.path().join("photo.jpg");
+    fs::copy("tests/assets/test_image.jpg", &image_path).unwrap();
+
+    convert_and_strip_metadata(&[temp_dir.path().to_path_buf()]).unwrap();
+
+    assert!(image_path.exists());
+    assert!(temp_dir.path().join("photo.png").exists());
+}
+
+/// Writes a 16x8 JPEG, red on the left half and blue on the right, flagged with
+/// EXIF orientation 6 (rotate 90° clockwise to display).
+fn write_rotated_jpeg(path: &Path) {
+    let img = RgbImage::from_fn(16, 8, |x, _| {
+        if x < 8 {
+            Rgb([255, 0, 0])
+        } else {
+            Rgb([0, 0, 255])
+        }
+    });
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, 100)
+        .encode_image(&img)
+        .unwrap();
+
+    // An APP1 segment holding a big-endian TIFF header and one IFD entry:
+    // Orientation (0x0112), SHORT, count 1, value 6.
+    let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+    exif.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0]);
+    let mut app1 = vec![0xFF, 0xE1];
+    app1.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+    app1.extend_from_slice(&exif);
+
+    // Insert the segment right after the SOI marker.
+    jpeg.splice(2..2, app1);
+    fs::write(path, jpeg).unwrap();
+}
+
+#[test]
+fn test_exif_orientation_is_applied() {
+    let temp_dir = tempdir().unwrap();
+    let image_path = temp_dir.path().join("sideways.jpg");
+    write_rotated_jpeg(&image_path);
+
+    // Without orientation handling, the image is still sideways.
+    let raw = image::open(&image_path).unwrap();
+    assert_eq!((raw.width(), raw.height()), (16, 8));
+
+    let upright = open_image(&image_path).unwrap().to_rgb8();
+    assert_eq!(upright.dimensions(), (8, 16));
+    // The left half ends up on top after rotating clockwise.
+    assert!(upright.get_pixel(4, 3)[0] > 200);
+    assert!(upright.get_pixel(4, 12)[2] > 200);
+
+    // Converting to PNG drops the EXIF data, so the rotation must be in the pixels.
+    convert_and_strip_metadata(&[temp_dir.path().to_path_buf()]).unwrap();
+    let converted = image::open(temp_dir.path().join("sideways.png"))
+        .unwrap()
+        .to_rgb8();
+    assert_eq!(converted.dimensions(), (8, 16));
+    assert!(converted.get_pixel(4, 3)[0] > 200);
+}
+
+#[test]
+fn test_rename_keeps_numbered_files_and_can_be_undone() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    // Numbered files keep their names, and new names skip their numbers.
+    fs::write(dir.join("1.jpg"), "one").unwrap();
+    fs::write(dir.join("3.jpg"), "three").unwrap();
+    fs::write(dir.join("a.jpg"), "a").unwrap();
+    fs::write(dir.join("photo.png"), "photo").unwrap();
+
+    let renamed = rename_files_in_selected_dirs(&[dir.to_path_buf()]).unwrap();
+    assert_eq!(renamed.len(), 2);
+    assert_eq!(fs::read_to_string(dir.join("1.jpg")).unwrap(), "one");
+    assert_eq!(fs::read_to_string(dir.join("2.jpg")).unwrap(), "a");
+    assert_eq!(fs::read_to_string(dir.join("3.jpg")).unwrap(), "three");
+    assert_eq!(fs::read_to_string(dir.join("4.png")).unwrap(), "photo");
+
+    let manifest = fs::read_to_string(dir.join(RENAME_MANIFEST_FILE)).unwrap();
+    let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    assert_eq!(manifest["4.png"], "photo.png");
+
+    // A second run only renames the new file, and still maps back to the original
+    // names.
+    fs::write(dir.join("new.jpg"), "new").unwrap();
+    let renamed = rename_files_in_selected_dirs(&[dir.to_path_buf()]).unwrap();
+    assert_eq!(renamed, vec![(dir.join("new.jpg"), dir.join("5.jpg"))]);
+    let restored = undo_rename(dir).unwrap();
+    assert_eq!(restored.len(), 3);
+    assert_eq!(fs::read_to_string(dir.join("a.jpg")).unwrap(), "a");
+    assert_eq!(fs::read_to_string(dir.join("photo.png")).unwrap(), "photo");
+    assert_eq!(fs::read_to_string(dir.join("new.jpg")).unwrap(), "new");
+    assert_eq!(fs::read_to_string(dir.join("1.jpg")).unwrap(), "one");
+    assert_eq!(fs::read_to_string(dir.join("3.jpg")).unwrap(), "three");
+    assert!(!dir.join("2.jpg").exists());
+    assert!(!dir.join(RENAME_MANIFEST_FILE).exists());
+    assert!(undo_rename(dir).is_err());
+}
+
+#[test]
+fn test_convert_to_jpeg_strips_exif() {
+    let temp_dir = tempdir().unwrap();
+    let image_path = temp_dir.path().join("sideways.jpg");
+    write_rotated_jpeg(&image_path);
+    let original = fs::read(&image_path).unwrap();
+    assert!(original.windows(4).any(|w| w == b"Exif"));
+
+    let options = ConvertOptions {
+        target_format: TargetFormat::Jpeg,
+        ..ConvertOptions::default()
+    };
+    convert_and_strip_metadata_with_options(&[temp_dir.path().to_path_buf()], &options).unwrap();
+
+    // Re-encoded in place, upright and without the EXIF block.
+    let bytes = fs::read(&image_path).unwrap();
+    assert!(!bytes.windows(4).any(|w| w == b"Exif"));
+    let converted = image::load_from_memory(&bytes).unwrap();
+    assert_eq!((converted.width(), converted.height()), (8, 16));
+}
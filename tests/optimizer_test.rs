@@ -58,4 +58,325 @@ async fn test_optimize_image_reduces_size() -> Result<()> {
     assert!(optimized_size > 0, "Optimized image should not be empty");
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Saves `img` as `name`, in the format its extension implies, and returns the
+/// path and size of the file.
+fn write_image_as(
+    img: &image::RgbImage,
+    dir: &std::path::Path,
+    name: &str,
+) -> Result<(PathBuf, u64)> {
+    let path = dir.join(name);
+    img.save(&path)?;
+    let size = fs::metadata(&path)?.len();
+    Ok((path, size))
+}
+
+#[tokio::test]
+async fn test_optimize_png_and_webp_reduce_size() -> Result<()> {
+    let temp_dir = tempdir()?;
+    // The `image` crate writes PNGs with standard zlib compression, which `oxipng`
+    // beats on smooth content, and WebPs losslessly, which lossy encoding beats on noise.
+    let gradient = image::RgbImage::from_fn(256, 256, |x, y| {
+        image::Rgb([x as u8, y as u8, ((x + y) / 2) as u8])
+    });
+    let mut state = 12345u32;
+    let noise = image::RgbImage::from_fn(256, 256, |_, _| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        let [r, g, b, _] = state.to_be_bytes();
+        image::Rgb([r, g, b])
+    });
+    let (png_path, png_size) = write_image_as(&gradient, temp_dir.path(), "gradient.png")?;
+    let (webp_path, webp_size) = write_image_as(&noise, temp_dir.path(), "noise.webp")?;
+
+    optimizer::optimize_media_in_dirs(&[temp_dir.path().to_path_buf()]).await?;
+
+    for (path, original_size) in [(png_path, png_size), (webp_path, webp_size)] {
+        let optimized_size = fs::metadata(&path)?.len();
+        assert!(
+            optimized_size > 0 && optimized_size < original_size,
+            "Optimized {:?} should be smaller. Original: {}, Optimized: {}",
+            path,
+            original_size,
+            optimized_size
+        );
+        image::open(&path)?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_optimize_jpeg_quality() -> Result<()> {
+    let mut sizes = Vec::new();
+    for jpeg_quality in [90.0, 30.0] {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("test_image.jpg");
+        fs::copy("tests/assets/test_image.jpg", &path)?;
+
+        let options = optimizer::OptimizerOptions {
+            jpeg_quality,
+            ..Default::default()
+        };
+        optimizer::optimize_media_in_dirs_with_options(&[temp_dir.path().to_path_buf()], &options)
+            .await?;
+        sizes.push(fs::metadata(&path)?.len());
+    }
+
+    assert!(
+        sizes[1] < sizes[0],
+        "Lower quality should be smaller: {:?}",
+        sizes
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_optimize_max_parallel() -> Result<()> {
+    common::setup();
+
+    let mut optimized_images = Vec::new();
+    for max_parallel in [1, 4] {
+        let temp_dir = tempdir()?;
+        let test_image_path = temp_dir.path().join("test_image.jpg");
+        let test_video_path = temp_dir.path().join("test_video.mp4");
+        fs::copy("tests/assets/test_image.jpg", &test_image_path)?;
+        fs::copy("tests/assets/test_video.mp4", &test_video_path)?;
+
+        let original_video_size = fs::metadata(&test_video_path)?.len();
+
+        let options = optimizer::OptimizerOptions {
+            max_parallel,
+            ..Default::default()
+        };
+        optimizer::optimize_media_in_dirs_with_options(&[temp_dir.path().to_path_buf()], &options)
+            .await?;
+
+        let optimized_video_size = fs::metadata(&test_video_path)?.len();
+        assert!(optimized_video_size > 0 && optimized_video_size < original_video_size);
+        optimized_images.push(fs::read(&test_image_path)?);
+    }
+
+    assert_eq!(optimized_images[0], optimized_images[1]);
+
+    Ok(())
+}
+
+/// Encodes a striped red/blue image as a near-lossless 4:4:4 JPEG.
+fn write_striped_jpeg(path: &std::path::Path) -> image::RgbImage {
+    let img = image::RgbImage::from_fn(64, 64, |x, _| {
+        if x % 2 == 0 {
+            image::Rgb([255, 0, 0])
+        } else {
+            image::Rgb([0, 0, 255])
+        }
+    });
+
+    let mut compress = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+    compress.set_quality(100.0);
+    compress.set_size(64, 64);
+    compress.set_chroma_sampling_pixel_sizes((1, 1), (1, 1));
+    let mut comp = compress.start_compress(Vec::new()).unwrap();
+    comp.write_scanlines(img.as_raw()).unwrap();
+    fs::write(path, comp.finish().unwrap()).unwrap();
+
+    img
+}
+
+fn mean_abs_error(a: &image::RgbImage, b: &image::RgbImage) -> f64 {
+    let total: u64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&x, &y)| (x as i64 - y as i64).unsigned_abs())
+        .sum();
+    total as f64 / a.as_raw().len() as f64
+}
+
+#[tokio::test]
+async fn test_optimize_jpeg_chroma_subsampling() -> Result<()> {
+    let mut errors = Vec::new();
+    for chroma_subsampling in [
+        optimizer::ChromaSubsampling::Yuv444,
+        optimizer::ChromaSubsampling::Yuv420,
+    ] {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("stripes.jpg");
+        let reference = write_striped_jpeg(&path);
+
+        let options = optimizer::OptimizerOptions {
+            chroma_subsampling,
+            ..Default::default()
+        };
+        optimizer::optimize_media_in_dirs_with_options(&[temp_dir.path().to_path_buf()], &options)
+            .await?;
+
+        let optimized = image::open(&path)?.to_rgb8();
+        errors.push(mean_abs_error(&reference, &optimized));
+    }
+
+    assert!(
+        errors[0] < errors[1],
+        "4:4:4 should preserve colored edges better. 4:4:4 error: {}, 4:2:0 error: {}",
+        errors[0],
+        errors[1]
+    );
+
+    Ok(())
+}
+
+/// Writes a one-second MPEG-4 video with a silent AAC audio track.
+fn write_video_with_audio(path: &std::path::Path) -> Result<()> {
+    use ffmpeg_next as ffmpeg;
+
+    ffmpeg::init()?;
+    let mut octx = ffmpeg::format::output(&path)?;
+    let global_header = octx
+        .format()
+        .flags()
+        .contains(ffmpeg::format::Flags::GLOBAL_HEADER);
+
+    let (width, height, framerate) = (160, 120, 30);
+    let mut video_stream = octx.add_stream(ffmpeg::codec::Id::MPEG4)?;
+    let mut video = ffmpeg::codec::context::Context::from_parameters(video_stream.parameters())?
+        .encoder()
+        .video()?;
+    video.set_width(width);
+    video.set_height(height);
+    video.set_format(ffmpeg::format::Pixel::YUV420P);
+    video.set_time_base((1, framerate));
+    if global_header {
+        video.set_flags(ffmpeg::codec::Flags::GLOBAL_HEADER);
+    }
+    let mut video = video.open_as(ffmpeg::codec::Id::MPEG4)?;
+    video_stream.set_parameters(&video);
+    let video_index = video_stream.index();
+
+    let sample_rate = 44100;
+    let aac = ffmpeg::encoder::find(ffmpeg::codec::Id::AAC).expect("AAC encoder");
+    let mut audio_stream = octx.add_stream(aac)?;
+    let mut audio = ffmpeg::codec::context::Context::from_parameters(audio_stream.parameters())?
+        .encoder()
+        .audio()?;
+    audio.set_rate(sample_rate);
+    audio.set_ch_layout(ffmpeg::ChannelLayout::STEREO);
+    audio.set_format(ffmpeg::format::Sample::F32(
+        ffmpeg::format::sample::Type::Planar,
+    ));
+    audio.set_time_base((1, sample_rate));
+    if global_header {
+        audio.set_flags(ffmpeg::codec::Flags::GLOBAL_HEADER);
+    }
+    let mut audio = audio.open_as(aac)?;
+    audio_stream.set_parameters(&audio);
+    let audio_index = audio_stream.index();
+
+    octx.write_header()?;
+    let video_time_base = octx.stream(video_index).unwrap().time_base();
+    let audio_time_base = octx.stream(audio_index).unwrap().time_base();
+
+    let mut packet = ffmpeg::Packet::empty();
+    let mut frame = ffmpeg::frame::Video::new(video.format(), width, height);
+    for i in 0..framerate {
+        frame.set_pts(Some(i as i64));
+        video.send_frame(&frame)?;
+        while video.receive_packet(&mut packet).is_ok() {
+            packet.rescale_ts(video.time_base(), video_time_base);
+            packet.set_stream(video_index);
+            packet.write_interleaved(&mut octx)?;
+        }
+    }
+    video.send_eof()?;
+    while video.receive_packet(&mut packet).is_ok() {
+        packet.rescale_ts(video.time_base(), video_time_base);
+        packet.set_stream(video_index);
+        packet.write_interleaved(&mut octx)?;
+    }
+
+    let frame_size = audio.frame_size() as usize;
+    let mut samples = ffmpeg::frame::Audio::new(
+        audio.format(),
+        frame_size,
+        ffmpeg::ChannelLayoutMask::STEREO,
+    );
+    samples.set_rate(sample_rate as u32);
+    for i in 0..sample_rate as usize / frame_size {
+        for plane in 0..samples.planes() {
+            samples.plane_mut::<f32>(plane).fill(0.0);
+        }
+        samples.set_pts(Some((i * frame_size) as i64));
+        audio.send_frame(&samples)?;
+        while audio.receive_packet(&mut packet).is_ok() {
+            packet.rescale_ts(audio.time_base(), audio_time_base);
+            packet.set_stream(audio_index);
+            packet.write_interleaved(&mut octx)?;
+        }
+    }
+    audio.send_eof()?;
+    while audio.receive_packet(&mut packet).is_ok() {
+        packet.rescale_ts(audio.time_base(), audio_time_base);
+        packet.set_stream(audio_index);
+        packet.write_interleaved(&mut octx)?;
+    }
+
+    octx.write_trailer()?;
+    Ok(())
+}
+
+fn has_stream(path: &std::path::Path, medium: ffmpeg_next::media::Type) -> Result<bool> {
+    Ok(ffmpeg_next::format::input(&path)?
+        .streams()
+        .best(medium)
+        .is_some())
+}
+
+#[test]
+fn test_optimize_video_copies_audio() -> Result<()> {
+    for copy_audio in [true, false] {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("with_audio.mp4");
+        write_video_with_audio(&path)?;
+        assert!(has_stream(&path, ffmpeg_next::media::Type::Audio)?);
+
+        let options = optimizer::OptimizerOptions {
+            copy_audio,
+            ..Default::default()
+        };
+        optimizer::optimize_video(&path, &options)?;
+
+        assert!(has_stream(&path, ffmpeg_next::media::Type::Video)?);
+        assert_eq!(
+            has_stream(&path, ffmpeg_next::media::Type::Audio)?,
+            copy_audio
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_optimize_reports_progress() -> Result<()> {
+    common::setup();
+
+    let temp_dir = tempdir()?;
+    fs::copy("tests/assets/test_image.jpg", temp_dir.path().join("a.jpg"))?;
+    fs::copy("tests/assets/test_image.jpg", temp_dir.path().join("b.jpg"))?;
+    fs::write(temp_dir.path().join("notes.txt"), "not media")?;
+
+    let updates = std::sync::Mutex::new(Vec::new());
+    let optimized = optimizer::optimize_media_in_dirs_with(
+        &[temp_dir.path().to_path_buf()],
+        2,
+        |done, total| updates.lock().unwrap().push((done, total)),
+    )
+    .await?;
+
+    assert_eq!(optimized.len(), 2);
+    let mut updates = updates.into_inner().unwrap();
+    updates.sort();
+    assert_eq!(updates, vec![(1, 2), (2, 2)]);
+
+    Ok(())
+}
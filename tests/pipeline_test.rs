@@ -1,6 +1,14 @@
 use eros::{
-    pipeline::TaggingPipeline,
+    error::TaggerError,
+    file,
+    pipeline::{
+        Activation, AdaptiveThreshold, CategoryThresholds, Prediction, Tagger, TaggingPipeline,
+        TaggingPipelineBuilder, TaggingResult,
+    },
+    processor::{ImageProcessor, ResizeMode},
+    rating::RatingModel,
     tagger::{Device, TaggerModel},
+    tags::{implications::TagImplications, TagCategory},
 };
 use tokio::runtime::Runtime;
 
@@ -47,6 +55,25 @@ fn test_predict() {
     assert_eq!(result.general, sorted);
 }
 
+#[test]
+fn test_tag_bytes_end_to_end() {
+    let mut pipeline = get_pipeline();
+    let data = std::fs::read("tests/assets/test_image.jpg").unwrap();
+    let result = pipeline.tag_bytes(&data).unwrap();
+
+    assert!(!result.general.is_empty());
+    let expected = pipeline
+        .predict(image::load_from_memory(&data).unwrap(), None)
+        .unwrap();
+    assert_eq!(result.general, expected.general);
+
+    let err = pipeline.tag_bytes(b"not an image").unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TaggerError>(),
+        Some(TaggerError::Io(_))
+    ));
+}
+
 #[test]
 fn test_predict_batch() {
     let mut pipeline = get_pipeline();
@@ -66,4 +93,650 @@ fn test_predict_batch() {
     let mut sorted = result1.general.clone();
     sorted.sort_by(|_, v1, _, v2| v2.partial_cmp(v1).unwrap());
     assert_eq!(result1.general, sorted);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_predict_zero_threshold_returns_all_tags() {
+    let mut pipeline = get_pipeline();
+    pipeline.threshold = 0.0;
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let result = pipeline.predict(image, None).unwrap();
+
+    let total = result.rating.len() + result.character.len() + result.general.len();
+    assert_eq!(total, pipeline.tags.idx2tag().len());
+
+    let mut sorted = result.general.clone();
+    sorted.sort_by(|_, v1, _, v2| v2.partial_cmp(v1).unwrap());
+    assert_eq!(result.general, sorted);
+}
+
+#[test]
+fn test_indexed_prediction_round_trip() {
+    let mut pipeline = get_pipeline();
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let result = pipeline.predict(image, None).unwrap();
+
+    let indexed = pipeline.index_prediction(&result.general).unwrap();
+    assert_eq!(indexed.len(), result.general.len());
+    assert_eq!(pipeline.expand_prediction(&indexed).unwrap(), result.general);
+}
+
+#[test]
+fn test_prefetch_into_temp_cache() {
+    let repo_id = "SmilingWolf/wd-swinv2-tagger-v3";
+    let cache_dir = tempfile::tempdir().unwrap();
+    run_async(TaggingPipeline::prefetch_into(repo_id, cache_dir.path())).unwrap();
+
+    let repo_dir = cache_dir.path().join(repo_id);
+    for name in [file::MODEL_FILE, file::TAGS_FILE, file::CONFIG_FILE] {
+        assert!(repo_dir.join(name).exists(), "{} was not downloaded", name);
+    }
+}
+
+#[test]
+fn test_prefetch_into_json_only_repo() {
+    let repo_id = "eros-test/json-only-tagger";
+    let cache_dir = tempfile::tempdir().unwrap();
+    let repo_dir = cache_dir.path().join(repo_id);
+    std::fs::create_dir_all(&repo_dir).unwrap();
+
+    // Only the CSV is missing from the cache, so prefetching must fall back to
+    // `tags.json` instead of failing on the CSV download.
+    let config_path = run_async(file::get(
+        "SmilingWolf/wd-swinv2-tagger-v3",
+        file::CONFIG_FILE,
+    ))
+    .unwrap();
+    std::fs::copy(config_path, repo_dir.join(file::CONFIG_FILE)).unwrap();
+    std::fs::write(repo_dir.join(file::MODEL_FILE), b"onnx").unwrap();
+    std::fs::write(
+        repo_dir.join(file::TAGS_JSON_FILE),
+        r#"[{"tag_id": 1, "name": "1girl", "category": 0, "count": 10}]"#,
+    )
+    .unwrap();
+
+    run_async(TaggingPipeline::prefetch_into(repo_id, cache_dir.path())).unwrap();
+    assert!(!repo_dir.join(file::TAGS_FILE).exists());
+}
+
+#[test]
+fn test_from_local_matches_from_pretrained() {
+    let repo_id = "SmilingWolf/wd-swinv2-tagger-v3";
+    let mut pretrained = get_pipeline();
+    let cache_dir = tempfile::tempdir().unwrap();
+    run_async(TaggingPipeline::prefetch_into(repo_id, cache_dir.path())).unwrap();
+
+    let repo_dir = cache_dir.path().join(repo_id);
+    let mut local = TaggingPipeline::from_local(
+        repo_dir.join(file::MODEL_FILE),
+        repo_dir.join(file::TAGS_FILE),
+        repo_dir.join(file::CONFIG_FILE),
+    )
+    .unwrap();
+    assert_eq!(local.preprocessor, pretrained.preprocessor);
+
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let expected = pretrained.predict(image.clone(), None).unwrap();
+    let result = local.predict(image, None).unwrap();
+    assert_eq!(result.general, expected.general);
+}
+
+#[test]
+fn test_predict_with_rating_matches_separate_calls() {
+    let mut pipeline = get_pipeline();
+    let mut rating_model = run_async(RatingModel::new()).unwrap();
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+
+    let (fused_rating, fused_result) = pipeline
+        .predict_with_rating(&image, &mut rating_model)
+        .unwrap();
+    let rating = rating_model.rate(&image).unwrap();
+    let result = pipeline.predict(image, None).unwrap();
+
+    assert_eq!(fused_rating, rating);
+    assert_eq!(fused_result.rating, result.rating);
+    assert_eq!(fused_result.character, result.character);
+    assert_eq!(fused_result.general, result.general);
+}
+
+#[test]
+fn test_predict_batch_with_rating_matches_single_calls() {
+    let mut pipeline = get_pipeline();
+    let mut rating_model = run_async(RatingModel::new()).unwrap();
+    let images = [
+        image::open("tests/assets/test_image.jpg").unwrap(),
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            32,
+            32,
+            image::Rgb([200, 40, 40]),
+        )),
+        image::open("tests/assets/test_image.jpg").unwrap().fliph(),
+    ];
+
+    pipeline.max_batch_size = 2;
+    let batched = pipeline
+        .predict_batch_with_rating(&images.iter().collect::<Vec<_>>(), &mut rating_model)
+        .unwrap();
+
+    assert_eq!(batched.len(), images.len());
+    for (image, (rating, result)) in images.iter().zip(&batched) {
+        let (single_rating, single_result) = pipeline
+            .predict_with_rating(image, &mut rating_model)
+            .unwrap();
+        assert_eq!(*rating, single_rating);
+        assert!(result.rating.keys().eq(single_result.rating.keys()));
+        assert!(result.general.keys().eq(single_result.general.keys()));
+    }
+}
+
+#[test]
+fn test_per_category_thresholds() {
+    let mut pipeline = get_pipeline();
+    pipeline.set_thresholds(CategoryThresholds {
+        rating: 0.0,
+        character: 1.1,
+        general: 0.35,
+    });
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let result = pipeline.predict(image, None).unwrap();
+
+    assert!(result.character.is_empty());
+    assert!(result.general.values().all(|&p| p >= 0.35));
+    assert_eq!(
+        result.rating.len(),
+        pipeline
+            .tags
+            .label2tag()
+            .values()
+            .filter(|t| t.category() == eros::tags::TagCategory::Rating)
+            .count()
+    );
+}
+
+#[test]
+fn test_category_thresholds_from_f32() {
+    let thresholds = CategoryThresholds::from(0.4);
+    assert_eq!(
+        thresholds,
+        CategoryThresholds {
+            rating: 0.4,
+            character: 0.4,
+            general: 0.4,
+        }
+    );
+}
+
+#[test]
+fn test_categorize_matches_predict_batch() {
+    let mut pipeline = get_pipeline();
+    pipeline.set_thresholds(CategoryThresholds {
+        rating: 0.0,
+        character: 0.85,
+        general: 0.35,
+    });
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+
+    let tensor = pipeline.preprocessor.process(&image).unwrap();
+    let probs = pipeline.model.predict(tensor).unwrap().remove(0);
+    let categorized = pipeline
+        .tags
+        .categorize(&probs, pipeline.threshold, pipeline.thresholds.as_ref())
+        .unwrap();
+    let result = pipeline.predict_batch(vec![&image], None).unwrap().remove(0);
+
+    assert_eq!(categorized.rating, result.rating);
+    assert_eq!(categorized.character, result.character);
+    assert_eq!(categorized.general, result.general);
+}
+
+#[test]
+fn test_predict_top_k() {
+    let mut pipeline = get_pipeline();
+    pipeline.threshold = 0.0;
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let full = pipeline.predict(image.clone(), None).unwrap();
+
+    let k = 20;
+    let result = pipeline.predict_top_k(image, k).unwrap();
+    assert_eq!(result.general.len(), k.min(full.general.len()));
+    assert!(result.general.len() <= k);
+    assert!(full.general.keys().take(k).eq(result.general.keys()));
+}
+
+#[test]
+fn test_adaptive_threshold_evens_out_tag_counts() {
+    // One image with uniformly low scores, one with uniformly high scores.
+    let low: Vec<f32> = (0..1000).map(|i| i as f32 / 1000.0 * 0.5).collect();
+    let high: Vec<f32> = (0..1000).map(|i| 0.3 + i as f32 / 1000.0 * 0.7).collect();
+    let count_above =
+        |scores: &[f32], cutoff: f32| scores.iter().filter(|&&s| s >= cutoff).count();
+
+    let fixed = 0.4;
+    let (fixed_low, fixed_high) = (count_above(&low, fixed), count_above(&high, fixed));
+    assert!(fixed_high > fixed_low * 3);
+
+    for adaptive in [
+        AdaptiveThreshold::MeanStdDev { k: 1.0 },
+        AdaptiveThreshold::Percentile(0.95),
+    ] {
+        let adaptive_low = count_above(&low, adaptive.cutoff(&low));
+        let adaptive_high = count_above(&high, adaptive.cutoff(&high));
+        assert!(adaptive_low > 0);
+        assert!(adaptive_low.abs_diff(adaptive_high) <= 5, "{:?}", adaptive);
+    }
+}
+
+#[test]
+fn test_predict_stream() {
+    let mut pipeline = get_pipeline();
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let expected = pipeline.predict(image.clone(), None).unwrap();
+
+    let images = std::iter::repeat(image).take(3);
+    let results: Vec<_> = pipeline
+        .predict_stream(images, 2)
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+
+    assert_eq!(results.len(), 3);
+    for result in results {
+        // Batched inference may differ from single-image inference in the last bits.
+        assert!(result.general.keys().eq(expected.general.keys()));
+        assert!(result.rating.keys().eq(expected.rating.keys()));
+    }
+}
+
+#[test]
+fn test_predict_batch_shared_matches_references() {
+    let mut pipeline = get_pipeline();
+    let image = std::sync::Arc::new(image::open("tests/assets/test_image.jpg").unwrap());
+
+    let shared = pipeline
+        .predict_batch_shared(&[image.clone(), image.clone()], None)
+        .unwrap();
+    let borrowed = pipeline
+        .predict_batch(vec![image.as_ref(), image.as_ref()], None)
+        .unwrap();
+
+    assert_eq!(shared.len(), borrowed.len());
+    for (a, b) in shared.iter().zip(&borrowed) {
+        assert_eq!(a.rating, b.rating);
+        assert_eq!(a.character, b.character);
+        assert_eq!(a.general, b.general);
+    }
+}
+
+#[test]
+fn test_from_pretrained_reports_progress_in_order() {
+    setup();
+    let messages = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = messages.clone();
+    let callback: eros::pipeline::ProgressCallback = Box::new(move |progress, message| {
+        recorded.lock().unwrap().push((progress, message));
+    });
+
+    let pipeline = run_async(TaggingPipeline::from_pretrained(
+        "SmilingWolf/wd-swinv2-tagger-v3",
+        Device::cpu(),
+        Some(callback),
+    ))
+    .unwrap();
+    assert!(!pipeline.tags.idx2tag().is_empty());
+
+    let messages = messages.lock().unwrap();
+    assert!(messages.windows(2).all(|w| w[0].0 <= w[1].0));
+    let text: Vec<&str> = messages.iter().map(|(_, m)| m.as_str()).collect();
+    assert_eq!(text.first(), Some(&"Initializing Tagger..."));
+    assert!(text.contains(&"Setting up preprocessor..."));
+    assert!(text.contains(&"Downloading tags..."));
+    assert_eq!(text.last(), Some(&"Pipeline ready."));
+}
+
+#[test]
+fn test_embed_requires_embedding_columns() {
+    let mut pipeline = get_pipeline();
+    assert!(!pipeline.tags.has_embeddings());
+
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let err = pipeline.embed(&image).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TaggerError>(),
+        Some(TaggerError::Config(_))
+    ));
+}
+
+#[test]
+fn test_builder() {
+    setup();
+    let pipeline = run_async(
+        TaggingPipelineBuilder::new()
+            .model_repo("SmilingWolf/wd-swinv2-tagger-v3")
+            .device(Device::cpu())
+            .threshold(0.35)
+            .category_thresholds(CategoryThresholds {
+                rating: 0.0,
+                character: 0.85,
+                general: 0.35,
+            })
+            .resize_filter(image::imageops::FilterType::Nearest)
+            .resize_mode(ResizeMode::CenterCrop)
+            .build(),
+    )
+    .unwrap();
+
+    assert_eq!(pipeline.threshold, 0.35);
+    assert_eq!(pipeline.thresholds.unwrap().character, 0.85);
+    assert_eq!(
+        pipeline.preprocessor.resize_filter,
+        image::imageops::FilterType::Nearest
+    );
+    assert_eq!(pipeline.preprocessor.resize_mode, ResizeMode::CenterCrop);
+}
+
+#[test]
+fn test_builder_requires_model_repo() {
+    let err = run_async(TaggingPipelineBuilder::new().build()).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TaggerError>(),
+        Some(TaggerError::Config(_))
+    ));
+}
+
+#[test]
+fn test_predict_from_tensor_matches_predict_batch() {
+    let mut pipeline = get_pipeline();
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let flipped = image.fliph();
+
+    let tensor = pipeline
+        .preprocessor
+        .process_batch(vec![&image, &flipped])
+        .unwrap();
+    let from_tensor = pipeline.predict_from_tensor(tensor.clone()).unwrap();
+    let batch = pipeline
+        .predict_batch(vec![&image, &flipped], None)
+        .unwrap();
+
+    assert_eq!(from_tensor.len(), 2);
+    for (a, b) in from_tensor.iter().zip(&batch) {
+        assert_eq!(a.rating, b.rating);
+        assert_eq!(a.character, b.character);
+        assert_eq!(a.general, b.general);
+    }
+    // The same tensor can be run again.
+    let again = pipeline.predict_from_tensor(tensor).unwrap();
+    assert_eq!(again[0].general, from_tensor[0].general);
+}
+
+#[test]
+fn test_activation() {
+    let logits = vec![-2.0, 0.0, 2.0];
+    assert_eq!(Activation::default(), Activation::None);
+    assert_eq!(Activation::None.apply(logits.clone()), logits);
+
+    let sigmoid = Activation::Sigmoid.apply(logits.clone());
+    assert_eq!(sigmoid[1], 0.5);
+    assert!((sigmoid[0] + sigmoid[2] - 1.0).abs() < 1e-6);
+
+    let softmax = Activation::Softmax.apply(logits);
+    assert!((softmax.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+    assert!(softmax[0] < softmax[1] && softmax[1] < softmax[2]);
+}
+
+#[test]
+fn test_sigmoid_activation_changes_scores() {
+    let mut pipeline = get_pipeline();
+    pipeline.threshold = 0.0;
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let plain = pipeline.predict(image.clone(), None).unwrap();
+
+    pipeline.activation = Activation::Sigmoid;
+    let activated = pipeline.predict(image, None).unwrap();
+    // WD taggers already output probabilities, so this applies a second sigmoid.
+    for (tag, &score) in &activated.general {
+        let expected = 1.0 / (1.0 + (-plain.general[tag]).exp());
+        assert!((score - expected).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_ignore_and_only_tags() {
+    let mut pipeline = get_pipeline();
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let result = pipeline.predict(image.clone(), None).unwrap();
+    let (top_tag, _) = result.general.first().expect("no general tag passed");
+    let top_tag = top_tag.clone();
+
+    pipeline.ignore_tags.insert(top_tag.clone());
+    let ignored = pipeline.predict(image.clone(), None).unwrap();
+    assert!(!ignored.general.contains_key(&top_tag));
+    assert_eq!(ignored.general.len(), result.general.len() - 1);
+
+    pipeline.ignore_tags.clear();
+    pipeline.only_tags = Some([top_tag.clone()].into_iter().collect());
+    let only = pipeline.predict(image, None).unwrap();
+    assert!(only.general.keys().eq([&top_tag]));
+    assert!(only.rating.is_empty());
+}
+
+#[test]
+fn test_predict_paths_skips_unreadable_files() {
+    let mut pipeline = get_pipeline();
+    pipeline.max_batch_size = 2;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let broken = temp_dir.path().join("broken.png");
+    std::fs::write(&broken, b"not an image").unwrap();
+    let image_path = std::path::PathBuf::from("tests/assets/test_image.jpg");
+    let missing = temp_dir.path().join("missing.png");
+    let paths = vec![image_path.clone(), broken, image_path.clone(), missing];
+
+    let results = pipeline.predict_paths(&paths);
+    let returned: Vec<_> = results.iter().map(|(path, _)| path.clone()).collect();
+    assert_eq!(returned, paths);
+    assert!(results[1].1.is_err());
+    assert!(results[3].1.is_err());
+
+    let expected = pipeline
+        .predict(image::open(&image_path).unwrap(), None)
+        .unwrap();
+    for (_, result) in [&results[0], &results[2]] {
+        assert_eq!(result.as_ref().unwrap().general, expected.general);
+    }
+}
+
+#[test]
+fn test_predict_batch_splits_into_sub_batches() {
+    let mut pipeline = get_pipeline();
+    let images: Vec<image::DynamicImage> = (0..40u8)
+        .map(|i| {
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+                32,
+                32,
+                image::Rgb([i * 6, 255 - i * 6, 128]),
+            ))
+        })
+        .collect();
+
+    pipeline.max_batch_size = 40;
+    let whole = pipeline
+        .predict_batch(images.iter().collect(), None)
+        .unwrap();
+    pipeline.max_batch_size = 8;
+    let chunked = pipeline
+        .predict_batch(images.iter().collect(), None)
+        .unwrap();
+
+    assert_eq!(chunked.len(), 40);
+    for (a, b) in whole.iter().zip(&chunked) {
+        // Results stay in input order and match the unsplit batch.
+        assert!(a.rating.keys().eq(b.rating.keys()));
+        assert!(a.general.keys().eq(b.general.keys()));
+    }
+}
+
+#[test]
+fn test_top_rating_and_is_explicit() {
+    let result = TaggingResult::new(
+        Prediction::from([
+            ("sensitive".to_string(), 0.6),
+            ("questionable".to_string(), 0.3),
+            ("general".to_string(), 0.1),
+        ]),
+        Prediction::new(),
+        Prediction::new(),
+        Prediction::new(),
+        Prediction::new(),
+        Prediction::new(),
+    );
+
+    assert_eq!(result.top_rating(), Some(("sensitive", 0.6)));
+    assert!(result.is_explicit(0.25));
+    assert!(!result.is_explicit(0.5));
+
+    let empty = TaggingResult::new(
+        Prediction::new(),
+        Prediction::new(),
+        Prediction::new(),
+        Prediction::new(),
+        Prediction::new(),
+        Prediction::new(),
+    );
+    assert_eq!(empty.top_rating(), None);
+    assert!(!empty.is_explicit(0.0));
+}
+
+#[test]
+fn test_category_counts_and_filter() {
+    let result = TaggingResult::new(
+        Prediction::from([("general".to_string(), 0.9)]),
+        Prediction::from([("hatsune_miku".to_string(), 0.8)]),
+        Prediction::from([("1girl".to_string(), 0.9), ("solo".to_string(), 0.7)]),
+        Prediction::new(),
+        Prediction::from([("vocaloid".to_string(), 0.6)]),
+        Prediction::new(),
+    );
+
+    assert_eq!(result.category_counts(), (1, 1, 2));
+    let counts = result.counts_by_category();
+    assert_eq!(counts[&TagCategory::Rating], 1);
+    assert_eq!(counts[&TagCategory::Character], 1);
+    assert_eq!(counts[&TagCategory::General], 2);
+    assert_eq!(counts[&TagCategory::Artist], 0);
+    assert_eq!(counts[&TagCategory::Copyright], 1);
+    assert_eq!(counts[&TagCategory::Meta], 0);
+    assert_eq!(result.filter(TagCategory::General), &result.general);
+    assert_eq!(result.filter(TagCategory::Character), &result.character);
+    assert_eq!(result.filter(TagCategory::Copyright), &result.copyright);
+    assert!(result.filter(TagCategory::Artist).is_empty());
+
+    let categories: Vec<TagCategory> = result.categories().map(|(c, _)| c).collect();
+    assert_eq!(
+        categories,
+        [
+            TagCategory::Rating,
+            TagCategory::Character,
+            TagCategory::General,
+            TagCategory::Artist,
+            TagCategory::Copyright,
+            TagCategory::Meta,
+        ]
+    );
+    assert!(result
+        .categories()
+        .all(|(category, tags)| tags == result.filter(category)));
+}
+
+#[test]
+fn test_apply_implications() {
+    let mut implications = TagImplications::new();
+    implications.add("cat_ears", "animal_ears");
+    implications.add("animal_ears", "ears");
+    implications.add("hatsune_miku", "vocaloid");
+
+    let mut result = TaggingResult::new(
+        Prediction::new(),
+        Prediction::from([("hatsune_miku".to_string(), 0.8)]),
+        Prediction::from([("cat_ears".to_string(), 0.7), ("ears".to_string(), 0.9)]),
+        Prediction::new(),
+        Prediction::from([("vocaloid".to_string(), 0.6)]),
+        Prediction::new(),
+    );
+    result.apply_implications(&implications);
+
+    let general: Vec<(&str, f32)> = result
+        .general
+        .iter()
+        .map(|(tag, &score)| (tag.as_str(), score))
+        .collect();
+    assert_eq!(
+        general,
+        [("ears", 0.9), ("cat_ears", 0.7), ("animal_ears", 0.7)]
+    );
+    // Already present in another category, so it stays there with the best score.
+    assert_eq!(result.copyright.get("vocaloid"), Some(&0.8));
+    assert!(!result.character.contains_key("vocaloid"));
+
+    let mut result = TaggingResult::new(
+        Prediction::new(),
+        Prediction::new(),
+        Prediction::from([("cat_ears".to_string(), 0.7)]),
+        Prediction::new(),
+        Prediction::new(),
+        Prediction::new(),
+    );
+    result.apply_implications(&implications.with_injected_score(1.0));
+    assert_eq!(result.general.get("animal_ears"), Some(&1.0));
+    assert_eq!(result.general.get("ears"), Some(&1.0));
+}
+
+/// A `Tagger` that tags every image as `1girl` without running a model.
+struct MockTagger;
+
+impl Tagger for MockTagger {
+    fn predict(&mut self, images: Vec<&image::DynamicImage>) -> anyhow::Result<Vec<TaggingResult>> {
+        Ok(images
+            .iter()
+            .map(|_| {
+                TaggingResult::new(
+                    Prediction::from([("general".to_string(), 0.9)]),
+                    Prediction::new(),
+                    Prediction::from([("1girl".to_string(), 0.8)]),
+                    Prediction::new(),
+                    Prediction::new(),
+                    Prediction::new(),
+                )
+            })
+            .collect())
+    }
+}
+
+/// Counts how many of `images` the tagger tags with `tag`.
+fn count_tagged(tagger: &mut dyn Tagger, images: &[image::DynamicImage], tag: &str) -> usize {
+    let results = tagger.predict(images.iter().collect()).unwrap();
+    assert_eq!(results.len(), images.len());
+    results
+        .iter()
+        .filter(|result| result.general.contains_key(tag))
+        .count()
+}
+
+#[test]
+fn test_mock_tagger_as_trait_object() {
+    let images = vec![image::DynamicImage::new_rgb8(8, 8); 3];
+    let mut tagger: Box<dyn Tagger> = Box::new(MockTagger);
+    assert_eq!(count_tagged(tagger.as_mut(), &images, "1girl"), 3);
+    assert_eq!(count_tagged(tagger.as_mut(), &[], "1girl"), 0);
+}
+
+#[test]
+fn test_pipeline_as_tagger_matches_predict_batch() {
+    let mut pipeline = get_pipeline();
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let expected = pipeline.predict_batch(vec![&image], None).unwrap();
+
+    let mut tagger: Box<dyn Tagger> = Box::new(pipeline);
+    let results = tagger.predict(vec![&image]).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].rating, expected[0].rating);
+    assert_eq!(results[0].general, expected[0].general);
+}
@@ -1,5 +1,8 @@
-use eros::processor::{ImagePreprocessor, ImageProcessor};
-use image::{Rgb, RgbImage};
+use eros::processor::{
+    CenterCropPreprocessor, ChannelOrder, ImagePreprocessor, ImageProcessor, ResizeMode,
+    TensorLayout,
+};
+use image::{GrayImage, Luma, Rgb, RgbImage};
 use ndarray::s;
 use tokio::runtime::Runtime;
 
@@ -22,7 +25,7 @@ fn test_process_image() {
         448,
         vec![0.48145466, 0.4578275, 0.40821073],
         vec![0.26862954, 0.26130258, 0.27577711],
-        true, // Test NHWC layout
+        TensorLayout::Nhwc,
     );
     let tensor = processor.process(&image).unwrap();
 
@@ -42,7 +45,7 @@ fn test_process_batch() {
         448,
         vec![0.48145466, 0.4578275, 0.40821073],
         vec![0.26862954, 0.26130258, 0.27577711],
-        true, // Test NHWC layout
+        TensorLayout::Nhwc,
     );
     let batch_tensor = processor.process_batch(vec![&image, &image]).unwrap();
 
@@ -64,7 +67,7 @@ fn test_from_pretrained_processor() {
     .unwrap();
     assert_eq!(processor.height, 448);
     assert_eq!(processor.width, 448);
-    assert_eq!(processor.bgr, true); // This model uses the fallback NHWC layout
+    assert_eq!(processor.layout, TensorLayout::Nhwc); // This model uses the fallback NHWC layout
     assert_eq!(processor.mean, vec![0.48145466, 0.4578275, 0.40821073]);
 }
 
@@ -112,4 +115,209 @@ fn test_aspect_ratio_preservation() {
 
     assert!((center_pixel_r - norm_r).abs() < 1e-5);
     assert!((center_pixel_g - norm_g).abs() < 1e-5);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_high_bit_depth_preserves_precision() {
+    setup();
+    // Two 16-bit values that collapse to the same 8-bit value (1000 / 257 ≈ 1100 / 257 ≈ 4).
+    let dark = image::DynamicImage::ImageRgb16(image::ImageBuffer::from_pixel(
+        64,
+        64,
+        Rgb([1000u16, 1000, 1000]),
+    ));
+    let lighter = image::DynamicImage::ImageRgb16(image::ImageBuffer::from_pixel(
+        64,
+        64,
+        Rgb([1100u16, 1100, 1100]),
+    ));
+
+    let mut processor =
+        ImagePreprocessor::new(64, 64, vec![0.0; 3], vec![1.0; 3], TensorLayout::Nchw);
+
+    let dark_8 = processor.process(&dark).unwrap()[[0, 0, 32, 32]];
+    let lighter_8 = processor.process(&lighter).unwrap()[[0, 0, 32, 32]];
+    assert_eq!(dark_8, lighter_8);
+
+    processor.high_bit_depth = true;
+    let dark_16 = processor.process(&dark).unwrap()[[0, 0, 32, 32]];
+    let lighter_16 = processor.process(&lighter).unwrap()[[0, 0, 32, 32]];
+    assert!((dark_16 - 1000.0 / 65535.0).abs() < 1e-6);
+    assert!((lighter_16 - 1100.0 / 65535.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_process_animated_gif() {
+    setup();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let gif_path = temp_dir.path().join("animated.gif");
+    let frames = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]]
+        .into_iter()
+        .map(|color| image::Frame::new(image::RgbaImage::from_pixel(32, 32, image::Rgba(color))));
+    image::codecs::gif::GifEncoder::new(std::fs::File::create(&gif_path).unwrap())
+        .encode_frames(frames)
+        .unwrap();
+
+    let processor = ImagePreprocessor::new(32, 32, vec![0.0; 3], vec![1.0; 3], TensorLayout::Nchw);
+
+    let tensor = processor.process_animated(&gif_path, 10).unwrap();
+    assert_eq!(tensor.shape(), &[3, 3, 32, 32]);
+    // Each slice comes from a different frame.
+    assert!(tensor[[0, 0, 16, 16]] > 0.9);
+    assert!(tensor[[1, 1, 16, 16]] > 0.9);
+    assert!(tensor[[2, 2, 16, 16]] > 0.9);
+
+    let capped = processor.process_animated(&gif_path, 2).unwrap();
+    assert_eq!(capped.shape(), &[2, 3, 32, 32]);
+}
+
+#[test]
+fn test_custom_pad_color() {
+    setup();
+    let image = image::DynamicImage::ImageRgb8(RgbImage::from_pixel(40, 10, Rgb([0, 0, 0])));
+    let mean = vec![0.5, 0.4, 0.3];
+    let std = vec![0.2, 0.25, 0.3];
+    let processor = ImagePreprocessor::new(32, 32, mean.clone(), std.clone(), TensorLayout::Nchw)
+        .with_pad_color(Rgb([255, 64, 0]));
+
+    let tensor = processor.process(&image).unwrap();
+
+    // The top-left corner lies in the padding of the wide image.
+    for (channel, expected) in [255u8, 64, 0].into_iter().enumerate() {
+        let value = tensor[[0, channel, 0, 0]] * std[channel] + mean[channel];
+        assert!((value * 255.0 - expected as f32).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn test_resize_filter() {
+    setup();
+    // A hard vertical edge that different filters blur differently when downscaled.
+    let image = image::DynamicImage::ImageRgb8(RgbImage::from_fn(100, 100, |x, _| {
+        if x < 50 {
+            Rgb([0, 0, 0])
+        } else {
+            Rgb([255, 255, 255])
+        }
+    }));
+    let mut processor =
+        ImagePreprocessor::new(30, 30, vec![0.0; 3], vec![1.0; 3], TensorLayout::Nchw);
+    assert_eq!(processor.resize_filter, image::imageops::FilterType::Lanczos3);
+    let lanczos = processor.process(&image).unwrap();
+
+    processor.resize_filter = image::imageops::FilterType::Nearest;
+    let nearest = processor.process(&image).unwrap();
+
+    assert_eq!(lanczos.shape(), nearest.shape());
+    assert_ne!(lanczos, nearest);
+}
+
+#[test]
+fn test_process_grayscale_image() {
+    setup();
+    let image = image::DynamicImage::ImageLuma8(GrayImage::from_pixel(16, 16, Luma([128])));
+    let processor = ImagePreprocessor::new(16, 16, vec![0.0; 3], vec![1.0; 3], TensorLayout::Nchw);
+
+    let tensor = processor.process(&image).unwrap();
+    assert_eq!(tensor.shape(), &[1, 3, 16, 16]);
+    // The gray value is replicated into every RGB channel.
+    for channel in 0..3 {
+        assert!((tensor[[0, channel, 8, 8]] - 128.0 / 255.0).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_process_mismatched_mean_is_an_error() {
+    setup();
+    let image = image::DynamicImage::ImageLuma8(GrayImage::new(16, 16));
+    let processor = ImagePreprocessor::new(16, 16, vec![0.5], vec![0.5], TensorLayout::Nchw);
+
+    let err = processor.process(&image).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<eros::error::TaggerError>(),
+        Some(eros::error::TaggerError::Config(_))
+    ));
+}
+
+#[test]
+fn test_legacy_bgr_flag_selects_layout() {
+    let legacy = ImagePreprocessor::new(16, 16, vec![0.0; 3], vec![1.0; 3], true);
+    assert_eq!(legacy.layout, TensorLayout::Nhwc);
+    assert_eq!(legacy.channel_order, ChannelOrder::Rgb);
+    assert_eq!(
+        ImagePreprocessor::new(16, 16, vec![0.0; 3], vec![1.0; 3], false).layout,
+        TensorLayout::Nchw
+    );
+}
+
+#[test]
+fn test_bgr_channel_order() {
+    setup();
+    let image = image::DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([255, 0, 0])));
+    let mean = vec![0.0; 3];
+    let std = vec![1.0; 3];
+
+    let rgb = ImagePreprocessor::new(8, 8, mean.clone(), std.clone(), TensorLayout::Nhwc);
+    let bgr = rgb.clone().with_channel_order(ChannelOrder::Bgr);
+    let rgb = rgb.process(&image).unwrap();
+    let bgr = bgr.process(&image).unwrap();
+
+    assert_eq!(rgb[[0, 4, 4, 0]], 1.0);
+    assert_eq!(rgb[[0, 4, 4, 2]], 0.0);
+    assert_eq!(bgr[[0, 4, 4, 0]], 0.0);
+    assert_eq!(bgr[[0, 4, 4, 2]], 1.0);
+}
+
+#[test]
+fn test_allow_upscale() {
+    setup();
+    let image = image::DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([0, 0, 0])));
+    let mut processor =
+        ImagePreprocessor::new(32, 32, vec![0.0; 3], vec![1.0; 3], TensorLayout::Nchw);
+    // Counts the black pixels of the image, as opposed to the gray padding.
+    let content_pixels = |processor: &ImagePreprocessor| {
+        let tensor = processor.process(&image).unwrap();
+        tensor
+            .slice(s![0, 0, .., ..])
+            .iter()
+            .filter(|&&v| v < 0.1)
+            .count()
+    };
+
+    assert!(!processor.allow_upscale);
+    assert_eq!(content_pixels(&processor), 4 * 4);
+
+    processor.allow_upscale = true;
+    assert_eq!(content_pixels(&processor), 32 * 32);
+}
+
+#[test]
+fn test_center_crop_has_no_padding() {
+    setup();
+    // A wide image whose middle is red and whose left and right edges are blue, so a
+    // center crop keeps only red.
+    let image = image::DynamicImage::ImageRgb8(RgbImage::from_fn(80, 40, |x, _| {
+        if (15..65).contains(&x) {
+            Rgb([255, 0, 0])
+        } else {
+            Rgb([0, 0, 255])
+        }
+    }));
+    let mut padded = ImagePreprocessor::new(32, 32, vec![0.0; 3], vec![1.0; 3], TensorLayout::Nchw);
+    padded.resize_filter = image::imageops::FilterType::Nearest;
+    let cropped = CenterCropPreprocessor::new(padded.clone());
+
+    let tensor = cropped.process(&image).unwrap();
+    assert_eq!(tensor.shape(), &[1, 3, 32, 32]);
+    // Every corner is red image content rather than gray padding.
+    for (y, x) in [(0, 0), (0, 31), (31, 0), (31, 31)] {
+        assert_eq!(tensor[[0, 0, y, x]], 1.0);
+        assert_eq!(tensor[[0, 2, y, x]], 0.0);
+    }
+
+    // The padded preprocessor leaves gray bars above and below the wide image.
+    assert_eq!(padded.process(&image).unwrap()[[0, 0, 0, 0]], 128.0 / 255.0);
+
+    let selected = padded.with_resize_mode(ResizeMode::CenterCrop);
+    assert_eq!(selected.process(&image).unwrap(), tensor);
+}
@@ -1,7 +1,9 @@
 use eros::{
+    error::TaggerError,
     rating::{Rating, RatingModel},
     tagger::{Device, TaggerModel},
 };
+use std::collections::HashSet;
 use tokio::runtime::Runtime;
 
 mod common;
@@ -22,4 +24,100 @@ fn test_rating_model() {
     // NOTE: The expected rating is Sfw because the procedurally generated test image is
     // a simple, neutral gray square, which should not be classified as NSFW.
     assert_eq!(rating, Rating::Sfw);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_classify_multi_class_labels() {
+    let labels = ["general", "sensitive", "questionable", "explicit"];
+
+    let strict: HashSet<String> = ["sensitive", "questionable", "explicit"]
+        .map(String::from)
+        .into();
+    let lenient: HashSet<String> = ["explicit"].map(String::from).into();
+
+    let decide = |nsfw_labels: &HashSet<String>| -> Vec<Rating> {
+        labels
+            .iter()
+            .map(|label| Rating::classify(label, nsfw_labels))
+            .collect()
+    };
+
+    assert_eq!(
+        decide(&strict),
+        vec![Rating::Sfw, Rating::Nsfw, Rating::Nsfw, Rating::Nsfw]
+    );
+    assert_eq!(
+        decide(&lenient),
+        vec![Rating::Sfw, Rating::Sfw, Rating::Sfw, Rating::Nsfw]
+    );
+}
+
+#[test]
+fn test_rating_model_uses_configured_nsfw_labels() {
+    TaggerModel::init(Device::cpu()).unwrap();
+    let mut model = run_async(RatingModel::new()).unwrap();
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+
+    // Treating every label as NSFW flips the decision for the neutral test image.
+    model.set_nsfw_labels(["nsfw", "sfw"]);
+    assert!(model.is_nsfw("sfw"));
+    assert_eq!(model.rate(&image).unwrap(), Rating::Nsfw);
+}
+
+#[test]
+fn test_rate_batch_matches_rate() {
+    TaggerModel::init(Device::cpu()).unwrap();
+    let mut model = run_async(RatingModel::new()).unwrap();
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+
+    let single = model.rate(&image).unwrap();
+    let batch = model.rate_batch(&[&image, &image, &image]).unwrap();
+    assert_eq!(batch, vec![single.clone(), single.clone(), single]);
+    assert!(model.rate_batch(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn test_rate_with_score() {
+    TaggerModel::init(Device::cpu()).unwrap();
+    let mut model = run_async(RatingModel::new()).unwrap();
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+
+    let (rating, score) = model.rate_with_score(&image).unwrap();
+    assert_eq!(rating, model.rate(&image).unwrap());
+    // The winning label of a two-class softmax has at least half the probability mass.
+    assert!((0.5..=1.0).contains(&score));
+}
+
+#[test]
+fn test_rate_label_matches_rate() {
+    TaggerModel::init(Device::cpu()).unwrap();
+    let mut model = run_async(RatingModel::new()).unwrap();
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+
+    let (label, label_score) = model.rate_label(&image).unwrap();
+    let (rating, score) = model.rate_with_score(&image).unwrap();
+    let expected = if model.is_nsfw(&label) {
+        Rating::Nsfw
+    } else {
+        Rating::Sfw
+    };
+    assert_eq!(rating, expected);
+    assert_eq!(label_score, score);
+}
+
+#[test]
+fn test_rate_bytes_matches_rate() {
+    TaggerModel::init(Device::cpu()).unwrap();
+    let mut model = run_async(RatingModel::new()).unwrap();
+    let data = std::fs::read("tests/assets/test_image.jpg").unwrap();
+    let image = image::load_from_memory(&data).unwrap();
+
+    let rating = model.rate_bytes(&data).unwrap();
+    assert_eq!(rating, model.rate(&image).unwrap());
+
+    let err = model.rate_bytes(b"not an image").unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TaggerError>(),
+        Some(TaggerError::Io(_))
+    ));
+}
@@ -1,5 +1,6 @@
 use eros::{
-    processor::{ImagePreprocessor, ImageProcessor},
+    file::RatingModelFile,
+    processor::{ImagePreprocessor, ImageProcessor, TensorLayout},
     tagger::{Device, TaggerModel},
     tags::LabelTags,
 };
@@ -59,4 +60,28 @@ fn test_predict_batch() {
     assert_eq!(predictions.len(), 2); // Batch size of 2
     assert_eq!(predictions[0].len(), tags.idx2tag().len());
     assert_eq!(predictions[1].len(), tags.idx2tag().len());
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_load_model_with_custom_input_name() {
+    setup();
+    TaggerModel::init(Device::cpu()).unwrap();
+    // The rating model is a ViT export whose input is named "pixel_values".
+    let model_path = run_async(RatingModelFile::get()).unwrap();
+    let mut model = TaggerModel::load(model_path).unwrap();
+    assert_ne!(model.input_name(), "input");
+
+    let processor =
+        ImagePreprocessor::new(224, 224, vec![0.5; 3], vec![0.5; 3], TensorLayout::Nchw);
+    let image = image::open("tests/assets/test_image.jpg").unwrap();
+    let predictions = model.predict(processor.process(&image).unwrap()).unwrap();
+    assert_eq!(predictions.len(), 1);
+}
+
+#[test]
+fn test_init_reports_active_provider() {
+    setup();
+    TaggerModel::init(Device::cpu()).unwrap();
+    assert_eq!(TaggerModel::active_provider(), "CPU");
+    assert_eq!(Device::Cpu.name(), "CPU");
+}